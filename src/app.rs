@@ -1,43 +1,133 @@
+use crate::bulk_rename::{apply_renames, plan_renames, rename_actions};
 use crate::cache::Cache;
 use crate::commands::parse_cmds;
 use crate::commands::read_config_file;
-use crate::commands::Command;
-use crate::config::Config;
+use crate::commands::{command_info, Command, CommandInfo, Conflicts, OwnFile, COMMANDS};
+use crate::config::{Config, EscAction, HiddenScope, LeftKey, NewIn, RightKey, Template};
 use crate::file_tree::{FileTree, FileTreeState};
-use crate::keymap::KeyMap;
-use crate::prompt::Prompt;
+use crate::keymap::{format_keys, KeyMap, KeyMatch};
+use crate::popup::{Popup, PopupAction};
+use crate::preview::Preview;
+use crate::tasks::Tasks;
+use crate::trash;
+use crate::prompt::{complete_path, last_word, Prompt};
 use crate::prompt::StatusLine;
+use crate::history::{self, Action, History};
+use crate::util::copy::{copy_recursive, describe_errors, move_path, CopyOptions, Overwrite};
+use crate::util::{
+  expand_home, fill_template, format_status, fuzzy_score, glob_match, mime_type, osc52, parse_badges, rename_path, rename_target, sanitize_name,
+  unique_path, write_atomic,
+};
 use crossterm::event::{KeyCode, KeyModifiers, KeyEvent, MouseEvent, MouseButton, MouseEventKind};
+use path_absolutize::Absolutize;
+use crossterm::style::Print;
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::io::Write;
+use std::process::Stdio;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc::{channel, Receiver, TryRecvError};
 use std::path::{Path, PathBuf};
-use ratatui::layout::{Constraint, Direction, Layout};
+use std::time::{Duration, Instant};
+use ratatui::layout::{Constraint, Direction, Layout, Rect};
+use ratatui::widgets::Paragraph;
 use ratatui::Frame;
 use tui_textarea::{Input, Key};
+use crate::watcher::Watcher;
 use crate::Opts;
 
 
 pub struct App<'a> {
   pub opts:&'a Opts,
-  pub enhanced_graphics: bool,
   pub config: Config,
   pub tree: FileTreeState,
   pub exit: bool,
   pub statusline: StatusLine<'a>,
   pub keymap: KeyMap,
+  pub popup: Option<Popup>,
+  /// Whether the terminal currently has focus
+  pub focused: bool,
+  /// Set when the screen has to be cleared and fully redrawn
+  pub redraw: bool,
+  /// Paths changed since the `on_change` hook last ran
+  changed_paths: Vec<PathBuf>,
+  last_change: Option<Instant>,
+  last_root_change: Option<Instant>,
+  /// Last navigation command and when it ran, for `nav_acceleration`
+  last_nav: Option<(Command, Instant)>,
+  nav_repeat: usize,
+  tasks: Tasks,
+  last_badges: Option<Instant>,
+  /// The output of `badge_cmd` running in the background
+  badge_output: Option<Receiver<String>>,
+  /// The next letter typed jumps to a sibling, see `Command::Jump`
+  pending_jump: bool,
+  /// Digits typed before a motion, like the 5 in `5j`
+  pending_count: Option<usize>,
+  /// Keys typed so far of a mapping of several keys, and when the last was
+  pending_keys: Vec<KeyPress>,
+  last_key: Option<Instant>,
+  /// The last previewed file, reloaded when the selection changes
+  preview: Option<Preview>,
+  preview_scroll: u16,
+  preview_wrap: bool,
+  /// Keys scroll the preview instead of moving in the tree
+  preview_focused: bool,
+  /// Entries to paste, from `yank` or `cut`
+  clipboard: Vec<PathBuf>,
+  /// Whether pasting moves the clipboard entries instead of copying them
+  clipboard_cut: bool,
+  /// The selection last written for `write_selection`
+  written_selection: Option<PathBuf>,
+  /// File operations for `undo` and `redo`
+  pub history: History,
+  /// The last command run with `shell`, for `repeat`
+  last_shell: Option<String>,
+  /// The path chosen with `accept`, printed on exit
+  pub picked: Option<PathBuf>,
+  /// Paths saved with `bookmark`, by name
+  bookmarks: HashMap<String, PathBuf>,
+  /// Where the tree was last drawn, for mapping clicks to lines
+  tree_area: Rect,
+  /// The line last clicked and when, to tell double clicks
+  last_click: Option<(usize, Instant)>,
+  /// Notices changes in the expanded directories, so the tree isn't rescanned
+  /// every tick
+  watcher: Watcher,
+  /// The working directory sidetree was started in, for `selectcwd`. `cd`
+  /// changes the process' one later
+  start_dir: Result<PathBuf, String>,
 }
 
+/// How long the filesystem has to be left alone before `on_change` fires
+const CHANGE_HOOK_DELAY: Duration = Duration::from_millis(100);
+
+/// Navigation keys repeated within this window count as held down
+const NAV_REPEAT_WINDOW: Duration = Duration::from_millis(150);
+/// Number of repeats before the step size grows by one line
+const NAV_ACCEL_REPEATS: usize = 8;
+const NAV_MAX_STEP: usize = 8;
+/// Larger counts before a motion are cut down to this
+const MAX_COUNT: usize = 99999;
+
+/// Clicks on the same line within this window make a double click
+const DOUBLE_CLICK: Duration = Duration::from_millis(400);
+
+/// How long a key that is both a mapping and the start of longer ones waits
+/// for the next key before running its own command
+const KEY_SEQUENCE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// `openall` asks for confirmation before opening more files than this
+const OPEN_ALL_CONFIRM: usize = 10;
+
+/// Lines scrolled by page keys in the preview
+const PREVIEW_PAGE: u16 = 10;
+
 
 #[derive(Debug, Clone, PartialEq,Eq,Hash,Copy)]
 pub struct KeyPress(pub KeyCode,pub KeyModifiers);
 
 impl KeyPress {
-  //pub fn modify(&self,modifier:KeyPress)->KeyPress {
-  //  KeyPress(self.0,modifier.1)
-  //}
-  pub fn charize(&self,modifier:KeyPress)->KeyPress {
-    KeyPress(modifier.0,self.1)
-  }
-
-
   pub fn has_modifier(&self,km:KeyModifiers)->bool {
     self.1 & km != KeyModifiers::NONE
   }
@@ -49,7 +139,7 @@ impl KeyPress {
     self.has_modifier(KeyModifiers::CONTROL)
   }
 
-  pub fn to_input(&self) -> Input {
+  pub fn to_input(self) -> Input {
     let key = match self.0 {
       KeyCode::Backspace => {Key::Backspace}
       KeyCode::Enter => {Key::Enter}
@@ -79,7 +169,7 @@ impl KeyPress {
       KeyCode::Media(_) => {Key::Null}
       KeyCode::Modifier(_) => {Key::Null}
     };
-    Input{key:key,
+    Input{key,
       alt:self.has_alt(),
       ctrl:self.has_control(),
       shift:self.has_modifier(KeyModifiers::SHIFT)}
@@ -87,7 +177,17 @@ impl KeyPress {
 }
 impl From<KeyEvent> for KeyPress {
    fn from(ke : KeyEvent) -> KeyPress{
-  KeyPress(ke.code,ke.modifiers)
+    // Keys are parsed from config with return as '\n'
+    let code = match ke.code {
+      KeyCode::Enter => KeyCode::Char('\n'),
+      c => c,
+    };
+    // Shift is already in the character, and keys are parsed without it
+    let modifiers = match code {
+      KeyCode::Char(_) => ke.modifiers - KeyModifiers::SHIFT,
+      _ => ke.modifiers,
+    };
+    KeyPress(code,modifiers)
   }
 }
 impl From<char> for KeyPress {
@@ -102,15 +202,44 @@ impl From<KeyCode> for KeyPress {
   }
 }
 impl<'a> App<'a> {
-  pub fn new(opts:&'a Opts, cache: Cache, enhanced_graphics:bool) -> App<'a> {
+  pub fn new(opts:&'a Opts, cache: Cache) -> App<'a> {
     let mut res = App {
       opts,
-      enhanced_graphics,
       config: Config::default(),
       tree: FileTreeState::new(PathBuf::from(".")),
       exit: false,
       statusline: StatusLine::new(),
-      keymap: KeyMap::new(),
+      keymap: KeyMap::with_defaults(),
+      popup: None,
+      focused: true,
+      redraw: false,
+      changed_paths: Vec::new(),
+      last_change: None,
+      last_root_change: None,
+      last_nav: None,
+      nav_repeat: 0,
+      tasks: Tasks::default(),
+      last_badges: None,
+      badge_output: None,
+      pending_jump: false,
+      pending_count: None,
+      pending_keys: Vec::new(),
+      last_key: None,
+      preview: None,
+      preview_scroll: 0,
+      preview_wrap: false,
+      preview_focused: false,
+      clipboard: Vec::new(),
+      clipboard_cut: false,
+      written_selection: None,
+      history: History::default(),
+      last_shell: None,
+      watcher: Watcher::new(),
+      picked: None,
+      bookmarks: HashMap::new(),
+      tree_area: Rect::default(),
+      last_click: None,
+      start_dir: std::env::current_dir().map_err(|e| e.to_string()),
     };
     res.read_cache(cache);
     res.tree.update(&res.config);
@@ -122,25 +251,159 @@ impl<'a> App<'a> {
 
 impl<'a> App<'a> {
   pub fn draw(&mut self, f: &mut Frame) {
+    // In minimal mode, the status line only takes up space while prompting
+    let show_status = !self.config.minimal || self.statusline.has_focus();
     let chunks = Layout::default()
       .direction(Direction::Vertical)
-      .constraints([Constraint::Min(0), Constraint::Length(1)].as_ref())
+      .constraints([Constraint::Min(0), Constraint::Length(show_status as u16)].as_ref())
       .split(f.size());
+    let modes = self.view_scope() + &self.pending_keys_text() + &self.mode_flags();
+    let status_chunks = Layout::default()
+      .direction(Direction::Horizontal)
+      .constraints([Constraint::Min(0), Constraint::Length(modes.len() as u16)].as_ref())
+      .split(chunks[1]);
+
+    let tree_area = if self.config.preview {
+      let columns = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Percentage(50), Constraint::Percentage(50)].as_ref())
+        .split(chunks[0]);
+      self.draw_preview(f, columns[1]);
+      columns[0]
+    } else {
+      chunks[0]
+    };
+    self.tree_area = tree_area;
+    f.render_stateful_widget(FileTree::new(&self.config), tree_area, &mut self.tree);
+    if !self.focused && self.config.dim_unfocused {
+      f.buffer_mut().set_style(chunks[0], self.config.unfocused_style);
+    }
+    if show_status {
+      let idle = self.idle_status();
+      self.statusline.draw(f, status_chunks[0], &idle);
+      f.render_widget(Paragraph::new(modes), status_chunks[1]);
+    }
+    if let Some(popup) = &self.popup {
+      popup.draw(f, chunks[0]);
+    }
+  }
+
+  /// Draw the selected file next to the tree, reading it if the selection changed
+  fn draw_preview(&mut self, f: &mut Frame, area: Rect) {
+    let path = &self.tree.entry().path;
+    if self.preview.as_ref().is_none_or(|p| &p.path != path) {
+      let max_bytes = self.config.preview_max_bytes.get() as u64;
+      self.preview = Some(Preview::load(path, max_bytes));
+      self.preview_scroll = 0;
+    }
+    if let Some(preview) = &self.preview {
+      let focused = self.preview_focused;
+      preview.draw(f, area, &self.config, &mut self.preview_scroll, self.preview_wrap, focused);
+    }
+  }
+
+  /// Scroll the preview while it has focus. Returns false for keys it doesn't use
+  fn preview_key(&mut self, k: KeyPress) -> bool {
+    let scroll = &mut self.preview_scroll;
+    match k {
+      KeyPress(KeyCode::Char('j') | KeyCode::Down, _) => *scroll = scroll.saturating_add(1),
+      KeyPress(KeyCode::Char('k') | KeyCode::Up, _) => *scroll = scroll.saturating_sub(1),
+      KeyPress(KeyCode::PageDown | KeyCode::Char(' '), _) => {
+        *scroll = scroll.saturating_add(PREVIEW_PAGE)
+      }
+      KeyPress(KeyCode::PageUp, _) => *scroll = scroll.saturating_sub(PREVIEW_PAGE),
+      KeyPress(KeyCode::Char('g') | KeyCode::Home, _) => *scroll = 0,
+      KeyPress(KeyCode::Char('w'), _) => self.run_command(&Command::PreviewWrap),
+      KeyPress(KeyCode::Tab | KeyCode::Char('h') | KeyCode::Esc | KeyCode::Left, _) => {
+        self.preview_focused = false
+      }
+      _ => return false,
+    }
+    true
+  }
+
+  /// The directory the view is narrowed to, relative to the root
+  fn view_scope(&self) -> String {
+    match self.tree.view_root() {
+      Some(dir) => {
+        let rel = dir.strip_prefix(&self.tree.root_entry.path).unwrap_or(dir);
+        format!(" [{}]", rel.to_string_lossy())
+      }
+      None => String::new(),
+    }
+  }
+
+  /// `statusline_format` filled in for the selection, shown when there is no
+  /// message
+  fn idle_status(&self) -> String {
+    let format = &self.config.statusline_format;
+    if format.is_empty() {
+      return String::new();
+    }
+    let path = &self.tree.entry().path;
+    let rel = path.strip_prefix(&self.tree.root_entry.path).unwrap_or(path);
+    let rel = match rel.as_os_str().is_empty() {
+      true => Path::new("."),
+      false => rel,
+    };
+    let (index, total) = self.tree.position();
+    format_status(format, &rel.to_string_lossy(), index, total)
+  }
+
+  /// The keys of a sequence in progress, so it's clear more are expected
+  fn pending_keys_text(&self) -> String {
+    let count = self.pending_count.map(|n| n.to_string()).unwrap_or_default();
+    match self.pending_keys.is_empty() && count.is_empty() {
+      true => String::new(),
+      false => format!(" {}{}", count, format_keys(&self.pending_keys)),
+    }
+  }
 
-    f.render_stateful_widget(FileTree::new(&self.config), chunks[0], &mut self.tree);
-    self.statusline.draw(f, chunks[1]);
+  /// Single letter flags for the active modes, shown at the end of the statusline
+  fn mode_flags(&self) -> String {
+    if !self.config.show_modes {
+      return String::new();
+    }
+    let flags = [
+      ('H', self.config.show_hidden),
+      ('D', self.config.dirs_only),
+      ('I', self.config.file_icons),
+      ('Q', self.config.quit_on_open),
+    ];
+    let flags: String = flags
+      .iter()
+      .map(|&(c, on)| if on { c } else { '-' })
+      .collect();
+    format!(" {}", flags)
   }
 
   pub fn read_cache(&mut self, cache: Cache) {
+    self.statusline.set_histories(cache.histories);
+    self.bookmarks = cache.bookmarks;
     self.tree.extend_expanded_paths(cache.expanded_paths);
     self.tree.update(&self.config);
     self.tree.select_path(&cache.selected_path);
   }
 
   pub fn get_cache(&self) -> Cache {
+    // The cache is TOML, which can only hold UTF-8 paths
+    let mut expanded_paths = self.tree.expanded_paths.clone();
+    expanded_paths.retain(|p| p.to_str().is_some());
+    let selected_path = self
+      .tree
+      .entry()
+      .path
+      .ancestors()
+      .find(|p| p.to_str().is_some())
+      .unwrap_or(Path::new(""))
+      .to_path_buf();
+    let mut bookmarks = self.bookmarks.clone();
+    bookmarks.retain(|_, p| p.to_str().is_some());
     Cache {
-      expanded_paths: self.tree.expanded_paths.clone(),
-      selected_path: self.tree.entry().path.clone(),
+      expanded_paths,
+      selected_path,
+      bookmarks,
+      histories: self.statusline.histories(self.config.history_limit.get()),
     }
   }
 
@@ -148,46 +411,259 @@ impl<'a> App<'a> {
     self.tree.update(&self.config);
   }
 
+  /// Rescan the tree if the watcher noticed changes in it, and have git look
+  /// at the directories they were in. Directories are watched from the first
+  /// rescan after they're expanded
+  pub fn update_changed(&mut self) {
+    self.watcher.watch(self.tree.shown_dirs());
+    if let Some(paths) = self.watcher.changed() {
+      for path in &paths {
+        self.tree.invalidate_git(path);
+      }
+      self.update();
+    }
+  }
+
   pub fn tick(&mut self) {
-    self.update();
+    self.expire_pending_keys();
+    self.receive_badges();
+    if self.watcher.is_active() && !self.tree.is_loading() {
+      // Still refreshed for what the watcher can't see, like git status
+      self.tree.refresh(&self.config);
+    } else {
+      self.update();
+    }
+    self.run_change_hook();
+    self.run_cd_hook();
+    self.write_changed_selection();
+    self.report_tasks();
+    let interval = Duration::from_secs(self.config.badge_interval.get() as u64);
+    let due = self.last_badges.is_none_or(|t| t.elapsed() >= interval);
+    if self.config.badge_interval.get() > 0 && !self.config.badge_cmd.is_empty() && due {
+      self.run_badge_cmd();
+    }
   }
 
-  pub fn on_mouse(&mut self, me: MouseEvent) -> Option<()> {
-    if self.statusline.has_focus() {
-      return Some(());
+  /// Start `badge_cmd` in the background, unless it's still running. Its
+  /// output replaces the badges once it's done
+  fn run_badge_cmd(&mut self) {
+    self.last_badges = Some(Instant::now());
+    if self.badge_output.is_some() {
+      return;
     }
+    let mut command = self.shell_command(self.config.badge_cmd.as_str());
+    let (tx, rx) = channel();
+    self.badge_output = Some(rx);
+    self.tasks.spawn_quiet("badges", move |cancel| {
+      let output = command.output().map_err(|e| e.to_string())?;
+      if !output.status.success() {
+        return Err(format!("badge_cmd failed with {}", output.status));
+      }
+      if !cancel.load(Ordering::Relaxed) {
+        let _ = tx.send(String::from_utf8_lossy(&output.stdout).to_string());
+      }
+      Ok(())
+    });
+  }
 
+  /// Show the output of `badge_cmd` if it finished, without rescanning
+  fn receive_badges(&mut self) {
+    let Some(output) = &self.badge_output else {
+      return;
+    };
+    match output.try_recv() {
+      Ok(stdout) => {
+        self.tree.badges = parse_badges(&stdout, &self.tree.root_entry.path);
+        self.tree.refresh(&self.config);
+      }
+      Err(TryRecvError::Empty) => return,
+      // Failed or cancelled, which is reported as a task
+      Err(TryRecvError::Disconnected) => {}
+    }
+    self.badge_output = None;
+  }
 
-      match me.kind {
+  /// Report background tasks that finished since the last tick
+  fn report_tasks(&mut self) {
+    for (name, res) in self.tasks.poll() {
+      match res {
+        Ok(()) => self.statusline.info.info(format!("{} done", name).as_str()),
+        Err(e) => self.error(format!("{}: {}", name, e).as_str()),
+      }
+    }
+  }
 
-        MouseEventKind::Down(MouseButton::Left) | MouseEventKind::Down(MouseButton::Right) => {
-          let line = (me.row - 1) as usize;
-          if self.tree.selected_idx() == Some(line) {
-            let entry = self.tree.entry().clone();
-            if entry.is_dir {
-              self.tree.toggle_expanded(&entry.path);
-            } else {
-              self.run_command(&Command::Open(None))
-            }
-          } else {
-            self.tree.select_nth(line);
+  /// Change the root of the tree, for the `on_cd` hook to pick up. What could
+  /// be undone happened outside of the new tree, so it's forgotten
+  fn change_root(&mut self, path: PathBuf) {
+    if path != self.tree.root_entry.path {
+      self.history.clear();
+    }
+    self.tree.change_root(&self.config, path);
+    self.last_root_change = Some(Instant::now());
+  }
+
+  /// Run `on_cd` once the root has stopped changing
+  fn run_cd_hook(&mut self) {
+    match self.last_root_change {
+      Some(t) if t.elapsed() >= CHANGE_HOOK_DELAY => {}
+      _ => return,
+    }
+    self.last_root_change = None;
+    if self.config.on_cd.is_empty() {
+      return;
+    }
+    let cmd = self.config.on_cd.clone();
+    self.run_shell(cmd.as_str());
+  }
+
+  /// Write the selection to the selection file after it changed, if enabled
+  fn write_changed_selection(&mut self) {
+    let selected = self.tree.entry().path.clone();
+    if !self.config.write_selection || self.written_selection.as_ref() == Some(&selected) {
+      return;
+    }
+    self.written_selection = Some(selected);
+    self.run_command(&Command::WriteSelection(None));
+  }
+
+  /// Write the selected path to `path`, `selection_file` or
+  /// `$SIDETREE_SELECTION_FILE`, for editors to pick up
+  fn write_selection(&mut self, path: Option<&Path>) {
+    let path = match path {
+      Some(path) => path.to_path_buf(),
+      None if !self.config.selection_file.is_empty() => {
+        PathBuf::from(self.config.selection_file.as_str())
+      }
+      None => match std::env::var_os("SIDETREE_SELECTION_FILE") {
+        Some(path) => PathBuf::from(path),
+        None => return self.error("no selection file, set selection_file"),
+      },
+    };
+    let selected = self.tree.entry().path.as_os_str().as_encoded_bytes().to_vec();
+    if let Err(e) = write_atomic(&path, &selected) {
+      self.error(format!("{}: {}", path.display(), e).as_str());
+    }
+  }
+
+  /// Record a path modified by a file operation, for the `on_change` hook
+  fn changed(&mut self, path: &Path) {
+    self.tree.invalidate_git(path);
+    self.changed_paths.push(path.to_path_buf());
+    self.last_change = Some(Instant::now());
+  }
+
+  /// The directory `mk` creates entries in for `place`
+  fn new_entry_dir(&self, place: NewIn) -> Result<PathBuf, String> {
+    let entry = self.tree.entry();
+    let top = self.tree.view_root().unwrap_or(&self.tree.root_entry.path);
+    match place {
+      NewIn::Auto => Ok(self.tree.current_dir()),
+      NewIn::Sibling if entry.path == top => Err("the root has no siblings in the tree".into()),
+      NewIn::Sibling => Ok(entry.path.parent().unwrap_or(top).to_path_buf()),
+      NewIn::Child if entry.is_dir => Ok(entry.path.clone()),
+      NewIn::Child => Err(format!("{}: not a directory", entry.path.display())),
+    }
+  }
+
+  /// Run `on_change` once for all changes, after they have settled down
+  fn run_change_hook(&mut self) {
+    match self.last_change {
+      Some(t) if t.elapsed() >= CHANGE_HOOK_DELAY => {}
+      _ => return,
+    }
+    self.last_change = None;
+    let paths = std::mem::take(&mut self.changed_paths);
+    if self.config.on_change.is_empty() {
+      return;
+    }
+    let all = paths
+      .iter()
+      .map(|p| p.to_string_lossy())
+      .collect::<Vec<_>>()
+      .join("\n");
+    let last = paths.last().map(|p| p.to_string_lossy().to_string()).unwrap_or_default();
+    let cmd = self.config.on_change.clone();
+    self.run_shell_with_env(
+      cmd.as_str(),
+      &[("sidetree_changed", last.as_str()), ("sidetree_changed_all", all.as_str())],
+    );
+  }
+
+  /// A click selects a line, a double click expands or opens it like
+  /// `<return>` and a right click shows what can be done with it
+  pub fn on_mouse(&mut self, me: MouseEvent) -> Option<()> {
+    if self.statusline.has_focus() || self.popup.is_some() {
+      return Some(());
+    }
+    let area = self.tree_area;
+    let in_tree = area.x <= me.column
+      && me.column < area.x + area.width
+      && area.y <= me.row
+      && me.row < area.y + area.height;
+    match me.kind {
+      MouseEventKind::Down(button @ (MouseButton::Left | MouseButton::Right)) if in_tree => {
+        let index = self.tree.index_at_row((me.row - area.y) as usize)?;
+        let double = self
+          .last_click
+          .is_some_and(|(last, t)| last == index && t.elapsed() < DOUBLE_CLICK);
+        self.tree.select_nth(index);
+        self.last_click = Some((index, Instant::now()));
+        match button {
+          MouseButton::Right => self.show_entry_menu(),
+          _ if double => {
+            // A third click starts over
+            self.last_click = None;
+            self.run_command(&Command::Enter);
           }
+          _ => {}
         }
-        MouseEventKind::ScrollDown => {
-          self.tree.select_next();
-        }
-        MouseEventKind::ScrollUp => {
-          self.tree.select_prev();
-        }
-        _ => {}
-
+      }
+      MouseEventKind::ScrollDown => {
+        self.tree.select_next();
+      }
+      MouseEventKind::ScrollUp => {
+        self.tree.select_prev();
+      }
+      _ => {}
     };
     Some(())
   }
 
-  
+  /// Let the user choose what to do with the selected entry
+  fn show_entry_menu(&mut self) {
+    let Some(line) = self.tree.line().filter(|l| !l.is_parent) else {
+      return;
+    };
+    let name = line.path.file_name().unwrap_or_default().to_string_lossy().to_string();
+    let items = vec![
+      ("open".to_string(), Command::Enter),
+      ("rename".to_string(), Command::Rename { name: None, overwrite: false }),
+      ("delete".to_string(), Command::Delete { prompt: true }),
+    ];
+    self.popup = Some(Popup::menu(&name, items));
+  }
+
+  /// Pasted text only goes to an open prompt, so it can never trigger key bindings
+  pub fn on_paste(&mut self, text: &str) {
+    if let Some(cmd) = self.statusline.on_paste(text) {
+      self.run_command(&cmd);
+    }
+  }
+
   pub fn on_key(&mut self, _k:KeyEvent ) -> Option<()> {
     let k = KeyPress::from(_k);
+    if let Some(popup) = &mut self.popup {
+      match popup.on_key(k) {
+        PopupAction::Keep => {}
+        PopupAction::Close => self.popup = None,
+        PopupAction::Run(cmd) => {
+          self.popup = None;
+          self.run_command(&cmd);
+        }
+      }
+      return Some(());
+    }
     if self.statusline.has_focus() {
       let (update, cmd) = self.statusline.on_key(k);
       if let Some(cmd) = cmd {
@@ -198,65 +674,103 @@ impl<'a> App<'a> {
       }
       return Some(());
     }
-    self.keymap.get_mapping(
-      k.clone())
-        .and_then(|cmd| {
-          self.run_command(&cmd);
-                      return Some(());
-        });
-
-    match k {
-      KeyPress(KeyCode::Char('q'),_) => {
-        self.exit = true;
-      }
-      KeyPress(KeyCode::Char('j') | KeyCode::Down,_) => {
-        self.tree.select_next();
-      }
-      KeyPress(KeyCode::Char('k') | KeyCode::Up,_ ) => {
-        self.tree.select_prev();
-      }
-      KeyPress(KeyCode::Char('\n'), _,) => {
-        let entry = self.tree.entry().clone();
-        if entry.is_dir {
-          self.tree.toggle_expanded(&entry.path);
-        } else {
-          self.run_command(&Command::Open(None))
-        }
-      }
-      KeyPress(KeyCode::Char('l'), m) if (m & KeyModifiers::ALT) == KeyModifiers::NONE => {
-        self.run_command(&Command::Cd(None));
+    if self.pending_jump {
+      self.pending_jump = false;
+      if let KeyPress(KeyCode::Char(c), _) = k {
+        self.run_command(&Command::Jump(Some(c)));
       }
+      return Some(());
+    }
+    if self.on_count_key(k) {
+      return Some(());
+    }
+    // Before the key map, which has the tree's navigation keys
+    if self.preview_focused && self.config.preview && self.preview_key(k) {
+      self.pending_count = None;
+      return Some(());
+    }
+    // Everything else is in the key map, see `KeyMap::with_defaults`
+    self.on_mapped_key(k);
+    // A count applies to the whole sequence
+    if self.pending_keys.is_empty() {
+      self.pending_count = None;
+    }
+    Some(())
+  }
 
-      KeyPress(KeyCode::Char('l') | KeyCode::Right, _) => {
-        let entry = self.tree.entry().clone();
-        if entry.is_dir {
-          if !entry.is_expanded() {
-            self.tree.expand(&entry.path);
-          } else {
-            self.tree.select_next();
-          }
-        }
+  /// Add a digit to the count for the next motion. Digits that are mapped,
+  /// and 0 at the start of a count, are left to the key map. Returns whether
+  /// the key was used
+  fn on_count_key(&mut self, k: KeyPress) -> bool {
+    let digit = match k {
+      KeyPress(KeyCode::Char(c), m) if m.difference(KeyModifiers::SHIFT).is_empty() => c.to_digit(10),
+      _ => None,
+    };
+    let digit = match digit {
+      Some(d) if self.pending_keys.is_empty() => d as usize,
+      _ => return false,
+    };
+    let counting = self.pending_count.is_some();
+    if !counting && (digit == 0 || !matches!(self.keymap.lookup(&[k]), KeyMatch::None)) {
+      return false;
+    }
+    let count = self.pending_count.unwrap_or(0);
+    self.pending_count = Some((count * 10 + digit).min(MAX_COUNT));
+    true
+  }
+
+  /// Look `k` up in the key map, continuing a sequence in progress. Keys
+  /// that aren't mapped do nothing
+  fn on_mapped_key(&mut self, k: KeyPress) {
+    let mut keys = std::mem::take(&mut self.pending_keys);
+    keys.push(k);
+    match self.keymap.lookup(&keys) {
+      KeyMatch::Command(cmd) => self.run_command(&cmd),
+      KeyMatch::Prefix(_) => {
+        self.pending_keys = keys;
+        self.last_key = Some(Instant::now());
       }
-      KeyPress(KeyCode::Char('h') | KeyCode::Left, _) => {
-        let entry = self.tree.entry().clone();
-        if entry.is_expanded() {
-          self.tree.collapse(&entry.path);
-        } else {
-          self.tree.select_up();
+      KeyMatch::None if keys.len() == 1 => {}
+      // Doesn't continue the sequence. A mapping of the keys before still
+      // runs, otherwise they're dropped along with this one
+      KeyMatch::None => {
+        keys.pop();
+        if let Some(cmd) = self.keymap.get_mapping(&keys) {
+          self.run_command(&cmd);
+          self.on_mapped_key(k);
         }
       }
-      KeyPress(KeyCode::Char('!'), _) => {
-        self.statusline.prompt(Box::new(ShellPrompt {}));
-      }
-      KeyPress(KeyCode::Char(':'), _) => {
-        self.statusline.prompt(Box::new(CmdPrompt {}));
-      }
-      KeyPress(KeyCode::Char('.'), _) => {
-        self.config.show_hidden = !self.config.show_hidden;
+    }
+  }
+
+  /// Give up on a key sequence when the next key takes too long, running the
+  /// mapping of the keys so far if there is one
+  fn expire_pending_keys(&mut self) {
+    if self.last_key.is_none_or(|t| t.elapsed() < KEY_SEQUENCE_TIMEOUT) {
+      return;
+    }
+    self.last_key = None;
+    let keys = std::mem::take(&mut self.pending_keys);
+    if let Some(cmd) = self.keymap.get_mapping(&keys) {
+      self.run_command(&cmd);
+    }
+  }
+
+  /// Number of lines to move for a navigation command.
+  /// With `nav_acceleration`, this grows while the same key is held down
+  fn nav_step(&mut self, k: Command) -> usize {
+    if !self.config.nav_acceleration {
+      return 1;
+    }
+    let now = Instant::now();
+    match self.last_nav {
+      Some((ref last, t)) if *last == k && now.duration_since(t) < NAV_REPEAT_WINDOW => {
+        self.nav_repeat += 1
       }
-      _ => {}
+      _ => self.nav_repeat = 0,
     }
-    Some(())
+    self.last_nav = Some((k, now));
+    (1 + self.nav_repeat / NAV_ACCEL_REPEATS).min(NAV_MAX_STEP)
   }
 
   pub fn run_commands(&mut self, cmds: &Vec<Command>) {
@@ -267,18 +781,121 @@ impl<'a> App<'a> {
 
   pub fn run_command(&mut self, cmd: &Command) {
     use Command::*;
+    let on_parent = self.tree.line().is_some_and(|l| l.is_parent);
     match cmd {
       Quit => {
         self.quit();
       }
+      // The `..` line has no entry of its own, opening it goes up instead
+      Open(None, _) if on_parent => self.open_parent(),
+      Rename { .. } | ToggleMark | MarkNext if on_parent => self.error("not possible on .."),
+      Delete { .. } | Trash | Yank | Cut if on_parent && self.tree.selected_paths.is_empty() => {
+        self.error("not possible on ..")
+      }
+      Shell(cmd) if cmd.is_empty() => self.statusline.prompt(Box::new(ShellPrompt {})),
       Shell(cmd) => {
+        self.last_shell = Some(cmd.clone());
         self.run_shell(cmd.as_str());
       }
-      Open(path) => {
-        let cmd = self.config.open_cmd.clone();
-        let path = path.as_ref().unwrap_or_else(|| &self.tree.entry().path);
-        let _path = path.clone();
-        self.run_shell(cmd.as_str());
+      ShellAsync(cmd) => {
+        // Its output would end up on top of the tree
+        let child = self
+          .shell_command(cmd)
+          .stdin(Stdio::null())
+          .stdout(Stdio::null())
+          .stderr(Stdio::null())
+          .spawn();
+        match child {
+          Ok(child) => self.tasks.add_process(cmd, child),
+          Err(e) => self.error(format!("{}: {}", cmd, e).as_str()),
+        }
+      }
+      Down | Up => {
+        let steps = match self.pending_count.take() {
+          Some(count) => count,
+          None => self.nav_step(cmd.clone()),
+        };
+        for _ in 0..steps {
+          match cmd {
+            Down => self.tree.select_next(),
+            _ => self.tree.select_prev(),
+          }
+        }
+      }
+      HalfPageDown => self.tree.select_page(true, true),
+      HalfPageUp => self.tree.select_page(false, true),
+      PageDown => self.tree.select_page(true, false),
+      PageUp => self.tree.select_page(false, false),
+      First | Last => match self.pending_count.take() {
+        Some(count) => self.tree.select_nth(count.saturating_sub(1)),
+        None if *cmd == First => self.tree.select_first(),
+        None => self.tree.select_last(),
+      },
+      Enter => match self.tree.line().map(|l| (l.path.clone(), l.is_dir && !l.is_parent)) {
+        Some((path, true)) => self.tree.toggle_expanded(&path),
+        Some((_, false)) => self.run_command(&Command::Open(None, None)),
+        None => {}
+      },
+      Right => match self.config.right_key {
+        RightKey::Drill => self.tree.drill_down(),
+        RightKey::Next => match self.tree.line().map(|l| (l.path.clone(), l.is_dir, l.is_expanded)) {
+          _ if on_parent => {}
+          Some((path, true, false)) => self.tree.expand(&path),
+          Some((_, true, true)) => self.tree.select_next(),
+          _ => {}
+        },
+      },
+      Left => match self.tree.line().map(|l| (l.path.clone(), l.is_expanded, l.level)) {
+        Some((path, true, _)) => self.tree.collapse(&path),
+        Some((_, false, 0)) if self.config.left_key == LeftKey::Ascend => self.open_parent(),
+        _ => {
+          self.tree.select_up();
+        }
+      },
+      Prompt(text) => self.statusline.prompt(Box::new(CmdPrompt {
+        root: self.tree.root_entry.path.clone(),
+        reveal_paths: self.config.prompt_reveal,
+        init: text.clone().unwrap_or_default(),
+      })),
+      Palette => self.statusline.prompt(Box::new(PalettePrompt)),
+      Escape => match self.config.esc_action {
+        EscAction::Auto if !self.tasks.is_empty() => self.run_command(&Command::Cancel),
+        EscAction::Auto if !self.tree.selected_paths.is_empty() => self.tree.clear_marks(),
+        EscAction::Auto => self.statusline.info.clear(),
+        EscAction::None => {}
+        EscAction::ClearMarks => self.tree.clear_marks(),
+        EscAction::Cancel => self.run_command(&Command::Cancel),
+        EscAction::Quit => self.quit(),
+      },
+      MarkNext => {
+        self.run_command(&Command::ToggleMark);
+        self.tree.select_next();
+      }
+      Repeat => match self.last_shell.clone() {
+        Some(cmd) => self.run_shell(cmd.as_str()),
+        None => self.error("no shell command to repeat"),
+      },
+      // Picking a file is done by opening it
+      Open(None, _) if self.opts.pick && !self.tree.entry().path.is_dir() => {
+        self.run_command(&Accept)
+      }
+      Accept => {
+        self.picked = Some(self.tree.entry().path.clone());
+        self.quit();
+      }
+      Open(path, pos) => {
+        let path = path.as_ref().map(|p| self.tree.root_entry.path.join(p));
+        let cmd = self.resolve_open_command(path.as_ref().unwrap_or(&self.tree.entry().path));
+        let path = path.map(|p| p.to_string_lossy().to_string());
+        let line = pos.map(|p| p.line.to_string()).unwrap_or_default();
+        let col = pos.and_then(|p| p.col).map(|c| c.to_string()).unwrap_or_default();
+        let mut env = vec![("sidetree_line", line.as_str()), ("sidetree_col", col.as_str())];
+        if let Some(path) = &path {
+          env.push(("sidetree_entry", path.as_str()));
+        }
+        if !self.is_open_elsewhere(&env) {
+          self.run_shell_with_env(cmd.as_str(), &env);
+        }
         if self.config.quit_on_open {
           self.quit();
         }
@@ -289,7 +906,7 @@ impl<'a> App<'a> {
       },
       Set(opt, val) => {
         if let Err(e) = self.config.set_opt(opt, val) {
-          self.statusline.info.error(e.as_str());
+          self.error(e.as_str());
         }
       }
       Echo(msg) => {
@@ -299,85 +916,895 @@ impl<'a> App<'a> {
         let path = path.as_ref().unwrap_or_else(|| &self.tree.entry().path);
         let path = path.clone();
         match std::env::set_current_dir(path.as_path()) {
-          Ok(()) => self
-            .tree
-            .change_root(&self.config, std::env::current_dir().unwrap()),
+          Ok(()) => match std::env::current_dir() {
+            Ok(dir) => self.change_root(dir),
+            Err(err) => self.error(err.to_string().as_str()),
+          },
           Err(err) => self.error(err.to_string().as_str()),
         }
       }
       MapKey(key, cmd) => {
-        self.keymap.add_mapping(*key, (**cmd).clone());
+        self.keymap.add_mapping(key.clone(), (**cmd).clone());
+      }
+      Unmap(keys) => self.keymap.remove_mapping(keys),
+      YankPath { relative } => {
+        let path = &self.tree.entry().path;
+        let path = match relative {
+          true => match path.strip_prefix(&self.tree.root_entry.path) {
+            Ok(rel) if rel.as_os_str().is_empty() => Path::new("."),
+            Ok(rel) => rel,
+            Err(_) => path,
+          },
+          false => path,
+        };
+        let text = path.to_string_lossy().to_string();
+        match self.copy_to_clipboard(&text) {
+          Ok(()) => self.statusline.info.info(format!("copied {}", text).as_str()),
+          Err(e) => self.error(format!("could not copy: {}", e).as_str()),
+        }
+      }
+      ExpandAll => {
+        let dir = self.tree.current_dir();
+        let depth = self.config.max_expand_depth.get();
+        self.tree.expand_all(&self.config, &dir, depth);
+      }
+      CollapseAll => {
+        let dir = self.tree.current_dir();
+        self.tree.collapse_all(&dir);
+        // What was selected below it isn't shown anymore
+        if self.tree.entry().path != dir {
+          self.tree.update(&self.config);
+          self.tree.select_path(&dir);
+        }
+      }
+      ExpandLevel(depth) => {
+        let dir = self.tree.current_dir();
+        let selected = self.tree.entry().path.clone();
+        self.tree.expand_level(&self.config, &dir, *depth);
+        self.tree.update(&self.config);
+        if !self.tree.select_path(&selected) {
+          self.tree.select_nth(0);
+        }
       }
-      Rename(name) => {
+      Search(None) => self.statusline.prompt(Box::new(SearchPrompt)),
+      Search(Some(pattern)) if pattern.is_empty() => self.tree.search = None,
+      Search(Some(pattern)) => {
+        self.tree.search = Some(pattern.clone());
+        self.run_command(&SearchNext);
+      }
+      SearchNext | SearchPrev => match self.tree.search.clone() {
+        Some(pattern) => {
+          for _ in 0..self.pending_count.take().unwrap_or(1) {
+            if !self.tree.select_match(&pattern, *cmd == SearchNext) {
+              self.error(format!("no match for {}", pattern).as_str());
+              break;
+            }
+          }
+        }
+        None => self.error("no search, see search"),
+      },
+      RootUp => {
+        let old = self.tree.root_entry.path.clone();
+        match old.parent() {
+          Some(parent) => {
+            // Keep both shown as they were, with the old root selected
+            self.tree.expand(parent);
+            self.tree.expand(&old);
+            self.change_root(parent.to_path_buf());
+            self.tree.select_path(&old);
+          }
+          None => self.error("already at the top"),
+        }
+      }
+      RootDown => {
+        let dir = self.tree.current_dir();
+        let selected = self.tree.entry().path.clone();
+        if dir != self.tree.root_entry.path {
+          self.tree.expand(&dir);
+          self.change_root(dir);
+          self.tree.select_path(&selected);
+        }
+      }
+      Bookmark(Some(name)) => {
+        let path = self.tree.entry().path.clone();
+        self.statusline.info.info(format!("bookmarked {} as {}", path.display(), name).as_str());
+        self.bookmarks.insert(name.clone(), path);
+      }
+      Bookmark(None) => {
+        let name = self
+          .tree
+          .entry()
+          .path
+          .file_name()
+          .map(|n| n.to_string_lossy().to_string())
+          .unwrap_or_default();
+        self.statusline.prompt(Box::new(BookmarkPrompt { name }));
+      }
+      GotoBookmark(name) => match self.bookmarks.get(name).cloned() {
+        Some(path) if path.exists() => self.goto_path(path),
+        Some(path) => self.error(format!("{}: no such path: {}", name, path.display()).as_str()),
+        None => self.error(format!("no bookmark {}", name).as_str()),
+      },
+      Bookmarks => self.show_bookmarks(),
+      Reload => self.reload_config(),
+      SelectCwd => match self.start_dir.clone() {
+        Ok(dir) if dir.exists() => self.goto_path(dir),
+        Ok(dir) => self.error(format!("no such directory: {}", dir.display()).as_str()),
+        Err(e) => self.error(format!("no working directory: {}", e).as_str()),
+      },
+      Keys => self.popup = Some(Popup::new("keys", self.keymap.help_lines())),
+      Rename { name, overwrite } => {
         if let Some(name) = name {
-          let src = &self.tree.entry().path;
-          let mut dst = src.clone();
-          dst.set_file_name(name);
-          // TODO: Error handling
-          if !dst.exists() {
-            std::fs::rename(src, dst).unwrap();
+          let src = self.tree.entry().path.clone();
+          let dst = match rename_target(&src, name, self.config.strict_names) {
+            Ok(dst) => dst,
+            Err(e) => return self.error(e.as_str()),
+          };
+          if dst == src {
+            return;
           }
+          if !overwrite && std::fs::symlink_metadata(&dst).is_ok() {
+            let text = format!("{} exists, overwrite? [y/N]>", dst.display());
+            let yes = Rename { name: Some(name.clone()), overwrite: true };
+            return self.statusline.prompt(Box::new(ConfirmPrompt { text, yes }));
+          }
+          // What gets overwritten is lost, undo can only move the entry back
+          let mut actions = Vec::new();
+          if *overwrite && std::fs::symlink_metadata(&dst).is_ok() {
+            match history::delete(&dst) {
+              Ok(action) => actions.push(action),
+              Err(e) => return self.error(format!("could not rename: {}", e).as_str()),
+            }
+          }
+          match rename_path(&src, &dst, *overwrite) {
+            Ok(()) => {
+              actions.push(Action::Move { from: src, to: dst.clone() });
+              self.changed(&dst);
+            }
+            Err(e) => self.error(format!("could not rename: {}", e).as_str()),
+          }
+          self.history.record("rename", actions);
         } else {
           self.statusline.prompt(Box::new(RenamePrompt {
-            old_name: self
-              .tree
-              .entry()
-              .path
-              .file_name()
-              .unwrap()
-              .to_string_lossy()
-              .into(),
+            src: self.tree.entry().path.clone(),
+            strict: self.config.strict_names,
           }));
         }
       }
-      NewFile(name) => {
+      BulkRename => self.bulk_rename(),
+      NewFile { name, place } => {
+        let place = place.unwrap_or(self.config.new_in);
+        let dir = match self.new_entry_dir(place) {
+          Ok(dir) => dir,
+          Err(e) => return self.error(e.as_str()),
+        };
         if let Some(name) = name {
-          let mut path = self.tree.current_dir();
-          path.push(name);
-          // TODO: Error handling
-          if !path.exists() {
-            if name.ends_with('/') {
-              std::fs::create_dir_all(path).unwrap();
-            } else {
-              std::fs::write(path, "").unwrap();
+          let name = match sanitize_name(name, true, self.config.strict_names) {
+            Ok(name) => name,
+            Err(e) => return self.error(e.as_str()),
+          };
+          let mut path = dir;
+          path.push(&name);
+          if path.exists() {
+            return self.error(format!("{} already exists", path.display()).as_str());
+          }
+          let created = created_ancestor(&path);
+          let res = if name.ends_with('/') {
+            std::fs::create_dir_all(&path)
+          } else {
+            let content = match self.template_for(&path) {
+              Ok(content) => content,
+              Err(e) => return self.error(e.as_str()),
+            };
+            path
+              .parent()
+              .map_or(Ok(()), std::fs::create_dir_all)
+              .and_then(|_| std::fs::write(&path, content))
+          };
+          if let Err(e) = res {
+            // Don't leave the directories made for it behind
+            if created != path {
+              let _ = std::fs::remove_dir_all(&created);
             }
+            return self.error(format!("could not create {}: {}", path.display(), e).as_str());
           }
+          self.history.record("mk", vec![Action::Create { path: created }]);
+          self.changed(&path);
         } else {
-          self.statusline.prompt(Box::new(NewFilePrompt {}));
+          self.statusline.prompt(Box::new(NewFilePrompt { dir, place }));
         }
       }
       NewDir(name) => {
         if let Some(name) = name {
+          let name = match sanitize_name(name, true, self.config.strict_names) {
+            Ok(name) => name,
+            Err(e) => return self.error(e.as_str()),
+          };
           let mut path = self.tree.current_dir();
           path.push(name);
-          // TODO: Error handling
-          if !path.exists() {
-            std::fs::create_dir_all(path).unwrap();
+          if path.exists() {
+            return self.error(format!("{} already exists", path.display()).as_str());
+          }
+          let created = created_ancestor(&path);
+          if let Err(e) = std::fs::create_dir_all(&path) {
+            return self.error(format!("could not create {}: {}", path.display(), e).as_str());
           }
+          self.history.record("mkdir", vec![Action::Create { path: created }]);
+          self.changed(&path);
         } else {
-          self.statusline.prompt(Box::new(NewDirPrompt {}));
+          self.statusline.prompt(Box::new(NewDirPrompt {
+            dir: self.tree.current_dir(),
+          }));
         }
       }
 
-      Delete { prompt } => {
-        if !prompt {
-          let path = &self.tree.entry().path;
-          // TODO: Error handling
-          if path.is_dir() {
-            std::fs::remove_dir_all(path).unwrap();
-          } else {
-            std::fs::remove_file(path).unwrap();
+      Help(name) => self.show_help(name.as_deref()),
+      ToggleMark => {
+        let path = self.tree.entry().path.clone();
+        self.tree.toggle_mark(&path);
+      }
+      InvertMarks => {
+        self.tree.invert_marks();
+        self.report_marks();
+      }
+      MarkAll => {
+        self.tree.mark_all();
+        self.report_marks();
+      }
+      ClearMarks => {
+        self.tree.clear_marks();
+        self.report_marks();
+      }
+      ShowDir(file) => {
+        let path = self.own_file(file);
+        if let Some(dir) = path.parent() {
+          self.change_root(dir.to_path_buf());
+          self.tree.select_path(&path);
+        }
+      }
+      EditFile(file) => {
+        let path = self.own_file(file);
+        self.edit_file(&path);
+      }
+      AddAction(action) => {
+        self.config.actions.push((**action).clone());
+      }
+      Actions => self.show_actions(),
+      ToggleHidden => {
+        self.config.show_hidden = !self.config.show_hidden;
+      }
+      ToggleDirsOnly => {
+        self.config.dirs_only = !self.config.dirs_only;
+      }
+      ToggleHiddenHere => {
+        let dir = self.tree.current_dir();
+        self.tree.toggle_hidden_in(&dir);
+      }
+      ToggleHiddenScoped { other } => {
+        let local = self.config.hidden_scope == HiddenScope::Local;
+        if local != *other {
+          self.run_command(&ToggleHiddenHere);
+        } else {
+          self.run_command(&ToggleHidden);
+        }
+      }
+      Diff => self.diff_marked(),
+      Yank | Cut => {
+        let paths = self.marked_or_selected();
+        let verb = if *cmd == Cut { "cut" } else { "yanked" };
+        self.statusline.info.info(format!("{} {}", verb, paths.len()).as_str());
+        self.clipboard = paths;
+        self.clipboard_cut = *cmd == Cut;
+        self.tree.clear_marks();
+      }
+      Paste(conflicts) => self.paste(*conflicts),
+      Active(None) => self.tree.active_path = None,
+      Active(Some(path)) => {
+        let path = self.tree.root_entry.path.join(path);
+        match path.absolutize() {
+          Ok(path) => self.tree.active_path = Some(path.to_path_buf()),
+          Err(e) => return self.error(e.to_string().as_str()),
+        }
+        self.run_command(&Reveal(path));
+      }
+      PreviewWrap => self.preview_wrap = !self.preview_wrap,
+      PreviewFocus if !self.config.preview => self.error("preview is off, see the preview option"),
+      PreviewFocus => self.preview_focused = !self.preview_focused,
+      AddAssoc(assoc) => self.config.add_association(assoc.clone()),
+      DumpKeys(path) => self.dump_keys(path.as_deref()),
+      WriteSelection(path) => self.write_selection(path.as_deref()),
+      Undo | Redo => {
+        let (res, done) = match cmd {
+          Undo => (self.history.undo(), "undid"),
+          _ => (self.history.redo(), "redid"),
+        };
+        match res {
+          Ok(op) => {
+            for action in &op.actions {
+              self.changed(action.changed_path());
+            }
+            self.statusline.info.info(format!("{} {}", done, op.name).as_str());
           }
+          Err(e) => self.error(e.as_str()),
+        }
+      }
+      NextDir | PrevDir => {
+        for _ in 0..self.pending_count.take().unwrap_or(1) {
+          match cmd {
+            NextDir => self.tree.select_next_dir(),
+            _ => self.tree.select_prev_dir(),
+          }
+        }
+      }
+      TopDir => self.tree.select_top_ancestor(),
+      AddTemplate(ext, template) => {
+        self.config.templates.insert(ext.clone(), template.clone());
+      }
+      Jump(None) => self.pending_jump = true,
+      Jump(Some(c)) => {
+        if !self.tree.jump_sibling(*c) {
+          self.error(format!("no entry starting with {} here", c).as_str());
+        }
+      }
+      Badges if self.config.badge_cmd.is_empty() => self.error("badge_cmd is not set"),
+      Badges => self.run_badge_cmd(),
+      Cancel => match self.tasks.cancel_last() {
+        Some(name) => self.statusline.info.info(format!("cancelled {}", name).as_str()),
+        None => self.error("nothing to cancel"),
+      },
+      Narrow => {
+        let dir = self.tree.current_dir();
+        self.tree.narrow(&self.config, dir);
+      }
+      Widen => {
+        if !self.tree.widen(&self.config) {
+          self.error("view is not narrowed");
+        }
+      }
+      Select(path) => {
+        let path = self.tree.root_entry.path.join(path);
+        self.tree.select_closest(&path);
+      }
+      Find(None) => {
+        self.statusline.prompt(Box::new(FindPrompt {
+          candidates: self.find_candidates(),
+          original: self.tree.entry().path.clone(),
+          best: None,
+        }));
+      }
+      Find(Some(query)) => {
+        let candidates = self.find_candidates();
+        match fuzzy_matches(query, &candidates).first() {
+          Some(path) => self.run_command(&Reveal(path.to_path_buf())),
+          None => self.error(format!("no match for {}", query).as_str()),
+        }
+      }
+      Reveal(path) => {
+        let path = self.tree.root_entry.path.join(path);
+        if path.exists() {
+          self.tree.expand_to_path(&path);
+          self.tree.update(&self.config);
+          self.tree.select_path(&path);
         } else {
-          self.statusline.prompt(Box::new(DeletePrompt {}));
+          self.error(format!("no such path: {}", path.display()).as_str());
         }
       }
+
+      OpenAll { prompt } => {
+        let mut paths: Vec<_> = self
+          .tree
+          .selected_paths
+          .iter()
+          .filter(|p| !p.is_dir())
+          .cloned()
+          .collect();
+        paths.sort();
+        if paths.is_empty() {
+          self.error("no marked files");
+        } else if *prompt && paths.len() > OPEN_ALL_CONFIRM {
+          self.statusline.prompt(Box::new(ConfirmPrompt {
+            text: format!("open {} files? [y/N]>", paths.len()),
+            yes: OpenAll { prompt: false },
+          }));
+        } else {
+          self.open_all(&paths);
+        }
+      }
+      Delete { prompt } => {
+        let paths = self.marked_or_selected();
+        if *prompt {
+          let verb = if self.config.use_trash { "trash" } else { "delete" };
+          let text = match paths.as_slice() {
+            [_] => format!("{}? [y/N]>", verb),
+            _ => format!("{} {} entries? [y/N]>", verb, paths.len()),
+          };
+          let yes = Delete { prompt: false };
+          return self.statusline.prompt(Box::new(ConfirmPrompt { text, yes }));
+        }
+        if self.config.use_trash {
+          return self.run_command(&Trash);
+        }
+        // Gone for good, only trashed entries can be brought back
+        self.remove_paths("rm", "delete", &paths, history::delete);
+      }
+      Trash => {
+        let dir = trash::trash_dir(&self.config.trash_dir);
+        let paths = self.marked_or_selected();
+        self.remove_paths("trash", "trash", &paths, |path| {
+          let trashed = trash::put(&dir, path)?;
+          let path = path.to_path_buf();
+          Ok(Action::Trash { path, trashed })
+        });
+      }
     }
     self.update();
   }
+  /// The entries `find` searches, with their paths relative to the view root
+  fn find_candidates(&self) -> Vec<(String, PathBuf)> {
+    let root = self.tree.view_root().unwrap_or(&self.tree.root_entry.path);
+    self
+      .tree
+      .known_paths(&self.config)
+      .into_iter()
+      .map(|p| (p.strip_prefix(root).unwrap_or(&p).to_string_lossy().to_string(), p))
+      .collect()
+  }
+
+  /// Remove `paths` with `remove`, as one operation for `undo`, and clear the
+  /// marks. Failures are reported as "could not `verb`"
+  fn remove_paths(
+    &mut self,
+    name: &str,
+    verb: &str,
+    paths: &[PathBuf],
+    mut remove: impl FnMut(&Path) -> Result<Action, String>,
+  ) {
+    let mut failed = Vec::new();
+    let mut actions = Vec::new();
+    for path in paths {
+      // Already gone with a marked directory it's in
+      if path.ancestors().skip(1).any(|a| paths.iter().any(|p| p == a)) {
+        continue;
+      }
+      match remove(path) {
+        Ok(action) => {
+          actions.push(action);
+          self.changed(path);
+        }
+        Err(e) => failed.push(e),
+      }
+    }
+    self.history.record(name, actions);
+    self.tree.clear_marks();
+    if !failed.is_empty() {
+      self.error(format!("could not {} {}", verb, failed.join(", ")).as_str());
+    }
+  }
+
+  /// The marked paths, sorted, or the selected one when nothing is marked
+  fn marked_or_selected(&self) -> Vec<PathBuf> {
+    let mut paths: Vec<_> = self.tree.selected_paths.iter().cloned().collect();
+    if paths.is_empty() {
+      paths.push(self.tree.entry().path.clone());
+    }
+    paths.sort();
+    paths
+  }
+
+  /// Copy or move the clipboard entries into the selected directory. What
+  /// happens to names that are taken depends on `conflicts`, though copies into
+  /// the directory they're from always get a numeric suffix. Cut entries stay in
+  /// the clipboard until they were moved
+  fn paste(&mut self, conflicts: Conflicts) {
+    if self.clipboard.is_empty() {
+      return self.error("nothing to paste, yank or cut something first");
+    }
+    let dir = self.tree.current_dir();
+    let taken: Vec<PathBuf> = self
+      .clipboard
+      .iter()
+      .filter_map(|src| {
+        let dst = dir.join(src.file_name()?);
+        (dst != *src && std::fs::symlink_metadata(&dst).is_ok()).then_some(dst)
+      })
+      .collect();
+    if conflicts == Conflicts::Ask && !taken.is_empty() {
+      let text = match taken.as_slice() {
+        [dst] => format!("{} exists, overwrite? [y/N]>", dst.display()),
+        _ => format!("{} entries exist, overwrite? [y/N]>", taken.len()),
+      };
+      let yes = Command::Paste(Conflicts::Overwrite);
+      return self.statusline.prompt(Box::new(ConfirmPrompt { text, yes }));
+    }
+    let mut failed = Vec::new();
+    let mut moved = Vec::new();
+    let mut actions = Vec::new();
+    for src in self.clipboard.clone() {
+      let Some(name) = src.file_name() else {
+        failed.push(format!("{}: no file name", src.display()));
+        continue;
+      };
+      // Moving something to where it already is does nothing
+      if self.clipboard_cut && src.parent() == Some(dir.as_path()) {
+        moved.push(src);
+        continue;
+      }
+      let dst = dir.join(name);
+      let replace = conflicts == Conflicts::Overwrite && taken.contains(&dst);
+      let dst = if replace {
+        if src.starts_with(&dst) {
+          failed.push(format!("{}: would replace a directory it's in", src.display()));
+          continue;
+        }
+        // Moved entries replace what's there, which is lost for good
+        if self.clipboard_cut {
+          match history::delete(&dst) {
+            Ok(action) => actions.push(action),
+            Err(e) => {
+              failed.push(e);
+              continue;
+            }
+          }
+        }
+        dst
+      } else {
+        unique_path(&dir, name)
+      };
+      let res = if self.clipboard_cut {
+        move_path(&src, &dst)
+      } else {
+        // Copied directories are merged into the ones already there
+        let overwrite = match replace {
+          true => Overwrite::Always,
+          false => Overwrite::Never,
+        };
+        let opts = CopyOptions { overwrite, ..Default::default() };
+        copy_recursive(&src, &dst, opts).map_err(|e| describe_errors(&e))
+      };
+      // Partial copies are changes too. Undo can't delete what a copy was
+      // merged into, only report what it overwrote
+      if !self.clipboard_cut && replace {
+        actions.push(Action::Remove { path: dst.clone() });
+      } else if !self.clipboard_cut && dst.exists() {
+        actions.push(Action::Create { path: dst.clone() });
+      }
+      match res {
+        Ok(()) => {
+          if self.clipboard_cut {
+            actions.push(Action::Move { from: src.clone(), to: dst.clone() });
+          }
+          self.changed(&dst);
+          moved.push(src);
+        }
+        Err(e) => {
+          failed.push(e);
+          if dst.exists() {
+            self.changed(&dst);
+          }
+        }
+      }
+    }
+    self.history.record("paste", actions);
+    if self.clipboard_cut {
+      self.clipboard.retain(|p| !moved.contains(p));
+    }
+    if failed.is_empty() {
+      self.statusline.info.info(format!("pasted into {}", dir.display()).as_str());
+    } else {
+      self.error(format!("could not paste {}", failed.join(", ")).as_str());
+    }
+  }
+
+  /// Widen the view if it's narrowed, otherwise change the root to its parent
+  fn open_parent(&mut self) {
+    if self.tree.view_root().is_some() {
+      self.run_command(&Command::Widen);
+    } else if let Some(parent) = self.tree.root_entry.path.parent() {
+      self.run_command(&Command::Cd(Some(parent.to_path_buf())));
+    }
+  }
+
+  fn report_marks(&mut self) {
+    let msg = format!("{} marked", self.tree.selected_paths.len());
+    self.statusline.info.info(msg.as_str());
+  }
+
+  fn show_help(&mut self, name: Option<&str>) {
+    let usage = |name: &str, args: &str| format!("{} {}", name, args).trim_end().to_string();
+    match name {
+      None => {
+        let width = COMMANDS
+          .iter()
+          .map(|c| usage(c.name, c.args).len())
+          .max()
+          .unwrap_or(0);
+        let lines = COMMANDS
+          .iter()
+          .map(|c| format!("{:width$}  {}", usage(c.name, c.args), c.desc))
+          .collect();
+        self.popup = Some(Popup::new("help", lines));
+      }
+      Some(name) => match command_info(name) {
+        Some(c) => {
+          let lines = vec![usage(c.name, c.args), String::new(), c.desc.to_string()];
+          self.popup = Some(Popup::new(&format!("help: {}", c.name), lines));
+        }
+        None => self.error(format!("unknown command {}", name).as_str()),
+      },
+    }
+  }
+
+  /// Write the key mappings as `map` commands to `path`, or show them
+  fn dump_keys(&mut self, path: Option<&Path>) {
+    let mut lines: Vec<String> = self
+      .keymap
+      .mappings()
+      .map(|(keys, cmd)| Command::MapKey(keys.clone(), Box::new(cmd.clone())).to_string())
+      .collect();
+    lines.sort();
+    match path {
+      Some(path) => {
+        let text: String = lines.iter().map(|l| format!("{}\n", l)).collect();
+        match std::fs::write(path, text) {
+          Ok(()) => {
+            let msg = format!("wrote {} mappings to {}", lines.len(), path.display());
+            self.statusline.info.info(msg.as_str());
+          }
+          Err(e) => self.error(format!("{}: {}", path.display(), e).as_str()),
+        }
+      }
+      None => self.popup = Some(Popup::new("keys", lines)),
+    }
+  }
+
+  /// Show the differences between the two marked entries in a popup
+  fn diff_marked(&mut self) {
+    let mut paths: Vec<_> = self.tree.selected_paths.iter().cloned().collect();
+    if paths.len() != 2 {
+      return self.error(format!("diff needs 2 marked entries, got {}", paths.len()).as_str());
+    }
+    paths.sort();
+    let cmd = if self.config.diff_cmd.is_empty() {
+      r#"diff -u "$sidetree_diff_a" "$sidetree_diff_b""#
+    } else {
+      self.config.diff_cmd.as_str()
+    };
+    let output = self
+      .shell_command(cmd)
+      .env("sidetree_diff_a", &paths[0])
+      .env("sidetree_diff_b", &paths[1])
+      .output();
+    let output = match output {
+      Ok(output) => output,
+      Err(e) => return self.error(e.to_string().as_str()),
+    };
+    // diff exits with 1 when the files differ
+    if !matches!(output.status.code(), Some(0 | 1)) {
+      let stderr = String::from_utf8_lossy(&output.stderr);
+      return self.error(format!("diff failed: {}", stderr.trim()).as_str());
+    }
+    let mut lines: Vec<String> = String::from_utf8_lossy(&output.stdout)
+      .lines()
+      .map(|l| l.replace('\t', "    "))
+      .collect();
+    if lines.is_empty() {
+      lines.push("no differences".to_string());
+    }
+    self.popup = Some(Popup::new("diff", lines));
+  }
+
+  /// Content for a new file at `path`, from the template for its extension,
+  /// or its whole name if it has none
+  fn template_for(&self, path: &Path) -> Result<String, String> {
+    let key = path.extension().or(path.file_name()).map(|k| k.to_string_lossy());
+    let template = match key.and_then(|k| self.config.templates.get(k.as_ref())) {
+      Some(template) => template,
+      None => return Ok(String::new()),
+    };
+    let text = match template {
+      Template::Text(text) => text.clone(),
+      Template::File(file) => {
+        let home = std::env::var_os("HOME").unwrap_or_default();
+        let file = expand_home(file, Path::new(&home));
+        std::fs::read_to_string(&file).map_err(|e| format!("{}: {}", file.display(), e))?
+      }
+    };
+    let date = chrono::Local::now().format("%Y-%m-%d").to_string();
+    Ok(fill_template(&text, path, &date))
+  }
+
+  /// Select `path`, moving the root to it, or the directory it's in, when
+  /// it's outside the tree
+  fn goto_path(&mut self, path: PathBuf) {
+    if !path.starts_with(&self.tree.root_entry.path) {
+      let dir = match path.is_dir() {
+        true => path.clone(),
+        false => path.parent().map(Path::to_path_buf).unwrap_or_else(|| path.clone()),
+      };
+      self.change_root(dir);
+    }
+    self.run_command(&Command::Reveal(path));
+  }
+
+  /// Let the user choose one of the bookmarks to go to
+  fn show_bookmarks(&mut self) {
+    let mut items: Vec<_> = self
+      .bookmarks
+      .iter()
+      .map(|(name, path)| {
+        let label = format!("{}  {}", name, path.display());
+        (label, Command::GotoBookmark(name.clone()))
+      })
+      .collect();
+    if items.is_empty() {
+      return self.error("no bookmarks, add one with bookmark");
+    }
+    items.sort_by(|a, b| a.0.cmp(&b.0));
+    self.popup = Some(Popup::menu("bookmarks", items));
+  }
+
+  /// Let the user choose one of the actions matching the selected entry
+  fn show_actions(&mut self) {
+    let name = self
+      .tree
+      .entry()
+      .path
+      .file_name()
+      .map(|n| n.to_string_lossy().to_string())
+      .unwrap_or_default();
+    let items: Vec<_> = self
+      .config
+      .actions
+      .iter()
+      .filter(|a| glob_match(&a.pattern, &name))
+      .map(|a| (a.label.clone(), a.cmd.clone()))
+      .collect();
+    if items.is_empty() {
+      self.error(format!("no actions for {}", name).as_str());
+    } else {
+      self.popup = Some(Popup::menu(&format!("actions: {}", name), items));
+    }
+  }
+
+  fn own_file(&self, file: &OwnFile) -> PathBuf {
+    match file {
+      OwnFile::Config => self.conf_file(),
+      OwnFile::Cache => Cache::default_file_path(),
+    }
+  }
+
+  /// The config file in use
+  pub fn conf_file(&self) -> PathBuf {
+    self.opts.config.clone().unwrap_or_else(crate::default_conf_file)
+  }
+
+  /// Run the config file, then the `--exec` commands, as on startup
+  pub fn load_config(&mut self) -> Result<(), String> {
+    self.run_script_file(&self.conf_file())?;
+    if self.opts.minimal {
+      self.config.minimal = true;
+    }
+    if let Some(exec) = &self.opts.exec {
+      let cmds = parse_cmds(exec)?;
+      self.run_commands(&cmds);
+    }
+    Ok(())
+  }
+
+  /// Load the config again on the default config and key map, so removed
+  /// options and mappings don't linger. When the file can't be read, the
+  /// current config is kept
+  fn reload_config(&mut self) {
+    let path = self.conf_file();
+    let config = std::mem::take(&mut self.config);
+    let keymap = std::mem::replace(&mut self.keymap, KeyMap::with_defaults());
+    // Errors in the file replace this
+    self.statusline.info.info(format!("reloaded {}", path.display()).as_str());
+    // It only fails before anything ran, `--exec` already parsed on startup
+    if let Err(e) = self.load_config() {
+      self.config = config;
+      self.keymap = keymap;
+      self.error(format!("{}: {}", path.display(), e).as_str());
+    }
+  }
+
+  /// Leave the TUI while `f` runs, for handing the terminal to another program
+  pub fn suspend<R>(&mut self, f: impl FnOnce() -> R) -> std::io::Result<R> {
+    crate::restore_terminal()?;
+    let res = f();
+    crate::setup_terminal()?;
+    self.redraw = true;
+    Ok(res)
+  }
+
+  /// Rename the marked entries, or the entries in the current directory, by
+  /// editing their paths in $EDITOR
+  fn bulk_rename(&mut self) {
+    let mut sources: Vec<PathBuf> = self.tree.selected_paths.iter().cloned().collect();
+    sources.sort();
+    if sources.is_empty() {
+      sources = self.tree.visible_children(&self.tree.current_dir());
+    }
+    if sources.is_empty() {
+      return self.error("nothing to rename");
+    }
+    let base = self.tree.root_entry.path.clone();
+    let mut text = String::new();
+    for p in &sources {
+      text += &p.strip_prefix(&base).unwrap_or(p).to_string_lossy();
+      text += "\n";
+    }
+    // A new file only we can read, rather than one at a name others can guess
+    let tmp = tempfile::Builder::new()
+      .prefix("sidetree-rename-")
+      .suffix(".txt")
+      .tempfile()
+      .and_then(|mut tmp| tmp.write_all(text.as_bytes()).map(|_| tmp));
+    let tmp = match tmp {
+      Ok(tmp) => tmp,
+      Err(e) => return self.error(e.to_string().as_str()),
+    };
+    let edited = self.edit_file(tmp.path()).then(|| std::fs::read_to_string(tmp.path()));
+    let edited = match edited {
+      Some(Ok(edited)) => edited,
+      Some(Err(e)) => return self.error(e.to_string().as_str()),
+      None => return,
+    };
+    let lines: Vec<&str> = edited.lines().collect();
+    match plan_renames(&base, &sources, &lines).and_then(|r| apply_renames(&r).map(|_| r)) {
+      Ok(renames) => {
+        for (_, dst) in &renames {
+          self.changed(dst);
+        }
+        self.history.record("bulkrename", rename_actions(&renames));
+        self.tree.clear_marks();
+        self.statusline.info.info(format!("renamed {}", renames.len()).as_str());
+      }
+      Err(e) => self.error(e.as_str()),
+    }
+  }
+
+  /// Open `path` in $VISUAL or $EDITOR, falling back to vi.
+  /// Returns false if the editor failed
+  fn edit_file(&mut self, path: &Path) -> bool {
+    let res = self.suspend(|| {
+      std::process::Command::new("sh")
+        .arg("-c")
+        .arg("${VISUAL:-${EDITOR:-vi}} \"$1\"")
+        .arg("--")
+        .arg(path)
+        .status()
+    });
+    match res {
+      Ok(Ok(status)) if status.success() => true,
+      Ok(Ok(status)) => {
+        self.error(format!("Editor failed with {}", status).as_str());
+        false
+      }
+      Ok(Err(err)) | Err(err) => {
+        self.error(err.to_string().as_str());
+        false
+      }
+    }
+  }
+
   pub fn error(&mut self, msg: &str) {
+    self.log_error(msg);
     self.statusline.info.error(msg)
   }
+
+  /// Append `msg` to `error_log`, if set
+  fn log_error(&self, msg: &str) {
+    if self.config.error_log.is_empty() {
+      return;
+    }
+    let line = format!("{} {}\n", chrono::Local::now().format("%Y-%m-%d %H:%M:%S"), msg);
+    let _ = std::fs::OpenOptions::new()
+      .create(true)
+      .append(true)
+      .open(&self.config.error_log)
+      .and_then(|mut f| f.write_all(line.as_bytes()));
+  }
   fn quit(&mut self) {
     self.exit = true;
   }
@@ -389,40 +1816,132 @@ impl<'a> App<'a> {
   }
 
   fn run_shell(&mut self, cmd: &str) {
-    let output = std::process::Command::new("sh")
+    self.run_shell_with_env(cmd, &[]);
+  }
+
+  /// Run `cmd` like `run_shell`, with additional environment variables
+  fn run_shell_with_env(&mut self, cmd: &str, env: &[(&str, &str)]) {
+    if let Err(e) = self.shell_status(cmd, env) {
+      self.error(e.as_str());
+    }
+  }
+
+  /// Run `cmd` with additional environment variables, failing if it does
+  fn shell_status(&self, cmd: &str, env: &[(&str, &str)]) -> Result<(), String> {
+    let output = self
+      .shell_command_with_env(cmd, env)
+      .output()
+      .map_err(|e| e.to_string())?;
+    if output.status.success() {
+      Ok(())
+    } else {
+      Err(format!("Command failed with {}", output.status))
+    }
+  }
+
+  /// Run `open_cmd` for every file in `paths`, reporting failures together
+  fn open_all(&mut self, paths: &[PathBuf]) {
+    let mut failed = Vec::new();
+    for path in paths {
+      let cmd = self.resolve_open_command(path);
+      let entry = path.to_string_lossy();
+      let env = [("sidetree_entry", entry.as_ref())];
+      if self.is_open_elsewhere(&env) {
+        continue;
+      }
+      if let Err(e) = self.shell_status(cmd.as_str(), &env) {
+        failed.push(format!("{}: {}", entry, e));
+      }
+    }
+    if failed.is_empty() {
+      self.statusline.info.info(format!("opened {}", paths.len()).as_str());
+    } else {
+      self.error(format!("could not open {}", failed.join(", ")).as_str());
+    }
+    if self.config.quit_on_open {
+      self.quit();
+    }
+  }
+
+  /// The shell command to open `path` with, from the associations or `open_cmd`
+  fn resolve_open_command(&self, path: &Path) -> String {
+    self.config.open_cmd_for(path, || mime_type(path)).to_string()
+  }
+
+  /// Build a `sh -c` process for `cmd` with the sidetree environment
+  fn shell_command(&self, cmd: &str) -> std::process::Command {
+    self.shell_command_with_env(cmd, &[])
+  }
+
+  /// `shell_command` with additional environment variables. `$1` follows
+  /// `sidetree_entry` when it's among them, as for each file of `openall`
+  fn shell_command_with_env(&self, cmd: &str, env: &[(&str, &str)]) -> std::process::Command {
+    let entry = env
+      .iter()
+      .find(|(name, _)| *name == "sidetree_entry")
+      .map_or(self.tree.entry().path.as_os_str(), |(_, value)| OsStr::new(value));
+    let mut command = std::process::Command::new("sh");
+    command
       .arg("-c")
       .arg(cmd)
       .arg("--")
-      .arg(self.tree.entry().path.to_str().unwrap_or(""))
-      .env(
-        "sidetree_root",
-        self.tree.root_entry.path.to_str().unwrap_or(""),
-      )
-      .env(
-        "sidetree_entry",
-        self.tree.entry().path.to_str().unwrap_or(""),
-      )
-      .env(
-        "sidetree_dir",
-        self.tree.current_dir().to_str().unwrap_or(""),
-      )
-      .output();
-    match output {
-      Err(err) => {
-        self.statusline.info.error(&err.to_string());
-      }
-      Ok(output) => {
-        if !output.status.success() {
-          self
-            .statusline
-            .info
-            .error(format!("Command failed with {}", output.status).as_str())
-        }
-      }
+      .arg(entry)
+      .env("sidetree_root", &self.tree.root_entry.path)
+      .env("sidetree_entry", &self.tree.entry().path)
+      .env("sidetree_dir", self.tree.current_dir())
+      .envs(env.iter().copied());
+    command
+  }
+
+  /// Put `text` in the system clipboard, with `clipboard_cmd` if set, or else
+  /// by asking the terminal with OSC 52. Terminals without OSC 52 ignore it
+  fn copy_to_clipboard(&self, text: &str) -> Result<(), String> {
+    if self.config.clipboard_cmd.is_empty() {
+      let mut out = crate::ui_output();
+      return crossterm::execute!(out, Print(osc52(text.as_bytes()))).map_err(|e| e.to_string());
+    }
+    let mut child = self
+      .shell_command(self.config.clipboard_cmd.as_str())
+      .stdin(Stdio::piped())
+      .stdout(Stdio::null())
+      .stderr(Stdio::null())
+      .spawn()
+      .map_err(|e| e.to_string())?;
+    if let Some(mut stdin) = child.stdin.take() {
+      stdin.write_all(text.as_bytes()).map_err(|e| e.to_string())?;
+    }
+    let status = child.wait().map_err(|e| e.to_string())?;
+    match status.success() {
+      true => Ok(()),
+      false => Err(format!("clipboard_cmd failed with {}", status)),
+    }
+  }
+
+  /// Ask `is_open_cmd` whether the selected file is already open elsewhere.
+  /// The hook exits with 0 if it is, after focusing it
+  fn is_open_elsewhere(&self, env: &[(&str, &str)]) -> bool {
+    if self.config.is_open_cmd.is_empty() {
+      return false;
     }
+    self
+      .shell_command_with_env(self.config.is_open_cmd.as_str(), env)
+      .output()
+      .map(|o| o.status.success())
+      .unwrap_or(false)
   }
 }
 
+/// The outermost directory of `path` that doesn't exist yet, or `path` itself,
+/// which is what creating it adds
+fn created_ancestor(path: &Path) -> PathBuf {
+  path
+    .ancestors()
+    .take_while(|a| !a.exists())
+    .last()
+    .unwrap_or(path)
+    .to_path_buf()
+}
+
 pub struct ShellPrompt {}
 
 impl Prompt for ShellPrompt {
@@ -430,26 +1949,214 @@ impl Prompt for ShellPrompt {
     "!"
   }
   fn on_submit(&mut self, text: &str) -> Option<Command> {
-    Some(Command::Shell(text.to_string()))
+    // A trailing `&` runs it in the background, like in a shell
+    match text.trim_end().strip_suffix('&') {
+      Some(cmd) if !cmd.ends_with('&') && !cmd.trim().is_empty() => {
+        Some(Command::ShellAsync(cmd.trim_end().to_string()))
+      }
+      _ => Some(Command::Shell(text.to_string())),
+    }
   }
   fn on_cancel(&mut self) -> Option<Command> {
     None
   }
 }
 
-pub struct CmdPrompt {}
+/// Asks for the pattern of `search`. Submitting nothing clears it
+struct SearchPrompt;
+
+impl Prompt for SearchPrompt {
+  fn prompt_text(&self) -> &str {
+    "search>"
+  }
+
+  fn on_submit(&mut self, input: &str) -> Option<Command> {
+    Some(Command::Search(Some(input.to_string())))
+  }
+}
+
+/// Asks for the name of a new bookmark
+struct BookmarkPrompt {
+  name: String,
+}
+
+impl Prompt for BookmarkPrompt {
+  fn prompt_text(&self) -> &str {
+    "bookmark>"
+  }
+
+  fn on_submit(&mut self, input: &str) -> Option<Command> {
+    let name = input.trim();
+    (!name.is_empty()).then(|| Command::Bookmark(Some(name.to_string())))
+  }
+
+  fn init_text(&self) -> String {
+    self.name.clone()
+  }
+}
+
+pub struct CmdPrompt {
+  root: PathBuf,
+  /// Whether input that isn't a command may be a path to reveal
+  reveal_paths: bool,
+  init: String,
+}
 
 impl Prompt for CmdPrompt {
   fn prompt_text(&self) -> &str {
     ":"
   }
   fn on_submit(&mut self, text: &str) -> Option<Command> {
+    if self.reveal_paths && parse_cmds(text).is_err() {
+      let path = self.root.join(text.trim());
+      let under_root = path
+        .absolutize()
+        .map(|p| p.starts_with(&self.root))
+        .unwrap_or(false);
+      if under_root && path.exists() {
+        return Some(Command::Reveal(path));
+      }
+    }
     Some(Command::CmdStr(text.to_string()))
   }
+
+  fn init_text(&self) -> String {
+    self.init.clone()
+  }
+
+  /// Command names for the first word, paths for the others
+  fn on_complete(&mut self, input: &str) -> Vec<String> {
+    let mut words = input.split(char::is_whitespace);
+    let word = words.next_back().unwrap_or_default();
+    if words.next().is_some() {
+      return complete_path(&self.root, word);
+    }
+    COMMANDS
+      .iter()
+      .filter(|c| c.name.starts_with(word))
+      .map(|c| c.name.to_string())
+      .collect()
+  }
+}
+
+/// Paths in `candidates` matching `query`, best first. Ties go to the shortest
+fn fuzzy_matches<'a>(query: &str, candidates: &'a [(String, PathBuf)]) -> Vec<&'a Path> {
+  let mut matches: Vec<_> = candidates
+    .iter()
+    .filter_map(|(text, path)| Some((fuzzy_score(query, text)?, text, path)))
+    .collect();
+  matches.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.len().cmp(&b.1.len())).then(a.1.cmp(b.1)));
+  matches.into_iter().map(|(_, _, path)| path.as_path()).collect()
+}
+
+/// Commands whose name or, failing that, description matches `query`, best
+/// first
+fn palette_matches(query: &str) -> Vec<&'static CommandInfo> {
+  let mut matches: Vec<_> = COMMANDS
+    .iter()
+    .filter_map(|c| match fuzzy_score(query, c.name) {
+      Some(score) => Some((true, score, c)),
+      None => Some((false, fuzzy_score(query, c.desc)?, c)),
+    })
+    .collect();
+  matches.sort_by(|a, b| (b.0, b.1).cmp(&(a.0, a.1)).then(a.2.name.len().cmp(&b.2.name.len())));
+  matches.into_iter().map(|(_, _, c)| c).collect()
+}
+
+/// Picks a command by name or description. Commands that need arguments open
+/// the command prompt for them
+struct PalettePrompt;
+
+impl Prompt for PalettePrompt {
+  fn prompt_text(&self) -> &str {
+    "palette>"
+  }
+
+  fn on_submit(&mut self, input: &str) -> Option<Command> {
+    if input.trim().is_empty() {
+      return None;
+    }
+    let info = command_info(input.trim()).or_else(|| palette_matches(input).first().copied())?;
+    // Optional arguments are prompted for by the command itself
+    match info.args.is_empty() || info.args.starts_with('[') {
+      true => Some(Command::CmdStr(info.name.to_string())),
+      false => Some(Command::Prompt(Some(format!("{} ", info.name)))),
+    }
+  }
+
+  /// The best match, with its arguments and description
+  fn on_change(&mut self, input: &str) -> Option<String> {
+    if input.is_empty() {
+      return None;
+    }
+    let matches = palette_matches(input);
+    let c = matches.first()?;
+    let usage = format!("{} {}", c.name, c.args);
+    let more = match matches.len() {
+      1 => String::new(),
+      n => format!(" (+{})", n - 1),
+    };
+    Some(format!("{}: {}{}", usage.trim_end(), c.desc, more))
+  }
+
+  /// Names for a query of a single word
+  fn on_complete(&mut self, input: &str) -> Vec<String> {
+    if input.contains(char::is_whitespace) {
+      return Vec::new();
+    }
+    palette_matches(input).iter().map(|c| c.name.to_string()).collect()
+  }
+}
+
+/// Moves the selection to the best match while typing
+pub struct FindPrompt {
+  /// Paths relative to the view root to match against, with the full paths
+  candidates: Vec<(String, PathBuf)>,
+  /// The selection before the prompt opened, restored on cancel
+  original: PathBuf,
+  best: Option<PathBuf>,
+}
+
+impl Prompt for FindPrompt {
+  fn prompt_text(&self) -> &str {
+    "find>"
+  }
+
+  fn on_submit(&mut self, _: &str) -> Option<Command> {
+    match self.best.take() {
+      Some(path) => Some(Command::Reveal(path)),
+      None => self.on_cancel(),
+    }
+  }
+
+  fn on_cancel(&mut self) -> Option<Command> {
+    Some(Command::Select(self.original.clone()))
+  }
+
+  fn on_change(&mut self, input: &str) -> Option<String> {
+    if input.is_empty() {
+      self.best = None;
+      return None;
+    }
+    let matches = fuzzy_matches(input, &self.candidates);
+    self.best = matches.first().map(|p| p.to_path_buf());
+    Some(match matches.len() {
+      0 => "no match".to_string(),
+      1 => "1 match".to_string(),
+      n => format!("{} matches", n),
+    })
+  }
+
+  /// Entries in collapsed directories aren't shown, so select the closest
+  /// directory that is until the prompt is submitted
+  fn on_edit(&mut self, _: &str) -> Option<Command> {
+    Some(Command::Select(self.best.clone().unwrap_or_else(|| self.original.clone())))
+  }
 }
 
 pub struct RenamePrompt {
-  old_name: String,
+  src: PathBuf,
+  strict: bool,
 }
 
 impl Prompt for RenamePrompt {
@@ -458,15 +2165,35 @@ impl Prompt for RenamePrompt {
   }
 
   fn on_submit(&mut self, input: &str) -> Option<Command> {
-    Some(Command::Rename(Some(input.into())))
+    Some(Command::Rename {
+      name: Some(input.into()),
+      overwrite: false,
+    })
   }
 
   fn init_text(&self) -> String {
-    self.old_name.clone()
+    self
+      .src
+      .file_name()
+      .map(|n| n.to_string_lossy().into())
+      .unwrap_or_default()
+  }
+
+  /// Show where the entry would end up
+  fn on_change(&mut self, input: &str) -> Option<String> {
+    Some(match rename_target(&self.src, input, self.strict) {
+      Ok(dst) if dst != self.src && dst.exists() => format!("→ {} (exists)", dst.display()),
+      Ok(dst) => format!("→ {}", dst.display()),
+      Err(e) => e,
+    })
   }
 }
 
-pub struct NewFilePrompt {}
+pub struct NewFilePrompt {
+  /// Where the entry will be created
+  dir: PathBuf,
+  place: NewIn,
+}
 
 impl Prompt for NewFilePrompt {
   fn prompt_text(&self) -> &str {
@@ -474,11 +2201,25 @@ impl Prompt for NewFilePrompt {
   }
 
   fn on_submit(&mut self, input: &str) -> Option<Command> {
-    Some(Command::NewFile(Some(input.into())))
+    Some(Command::NewFile {
+      name: Some(input.into()),
+      place: Some(self.place),
+    })
+  }
+
+  /// Show where the entry will be created
+  fn on_change(&mut self, input: &str) -> Option<String> {
+    Some(format!("→ {}", self.dir.join(input).display()))
+  }
+
+  fn on_complete(&mut self, input: &str) -> Vec<String> {
+    complete_path(&self.dir, last_word(input))
   }
 }
 
-pub struct NewDirPrompt {}
+pub struct NewDirPrompt {
+  dir: PathBuf,
+}
 
 impl Prompt for NewDirPrompt {
   fn prompt_text(&self) -> &str {
@@ -488,20 +2229,129 @@ impl Prompt for NewDirPrompt {
   fn on_submit(&mut self, input: &str) -> Option<Command> {
     Some(Command::NewDir(Some(input.into())))
   }
+
+  fn on_complete(&mut self, input: &str) -> Vec<String> {
+    complete_path(&self.dir, last_word(input))
+  }
 }
 
-pub struct DeletePrompt {}
+/// Asks before something destructive, running `yes` only if the answer is y
+pub struct ConfirmPrompt {
+  text: String,
+  yes: Command,
+}
 
-impl Prompt for DeletePrompt {
+impl Prompt for ConfirmPrompt {
   fn prompt_text(&self) -> &str {
-    "delete? [y/N]>"
+    &self.text
+  }
+
+  fn keeps_history(&self) -> bool {
+    false
   }
 
   fn on_submit(&mut self, input: &str) -> Option<Command> {
     if input == "y" || input == "Y" {
-      Some(Command::Delete { prompt: false })
+      Some(self.yes.clone())
     } else {
       None
     }
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use crate::app::App;
+  use crate::cache::Cache;
+  use crate::commands::{Command, Conflicts};
+  use crate::config::{ShowRoot, Template};
+  use crate::history::Action;
+  use crate::Opts;
+  use clap::Parser;
+  use std::fs;
+  use std::path::Path;
+
+  #[test]
+  fn change_root_clears_history() {
+    let dir = tempfile::tempdir().unwrap();
+    let (tree, other) = (dir.path().join("tree"), dir.path().join("other"));
+    fs::create_dir(&tree).unwrap();
+    fs::create_dir(&other).unwrap();
+    let opts = Opts::parse_from(["sidetree"]);
+    let mut app = App::new(&opts, Cache::default());
+    app.change_root(tree.clone());
+
+    let file = tree.join("a");
+    fs::write(&file, "").unwrap();
+    app.history.record("mk", vec![Action::Create { path: file.clone() }]);
+    app.change_root(other);
+    assert!(app.history.undo().is_err());
+    assert!(file.exists());
+  }
+
+  #[test]
+  fn paste_overwrite_merges_copies() {
+    let dir = tempfile::tempdir().unwrap();
+    let (tree, other) = (dir.path().join("tree"), dir.path().join("other"));
+    fs::create_dir_all(tree.join("sub")).unwrap();
+    fs::create_dir_all(other.join("sub")).unwrap();
+    fs::write(tree.join("sub/both"), "old").unwrap();
+    fs::write(tree.join("sub/kept"), "").unwrap();
+    fs::write(other.join("sub/both"), "new").unwrap();
+    let opts = Opts::parse_from(["sidetree"]);
+    let mut app = App::new(&opts, Cache::default());
+    app.change_root(tree.clone());
+    assert_eq!(app.tree.current_dir(), tree);
+
+    app.clipboard = vec![other.join("sub")];
+    app.run_command(&Command::Paste(Conflicts::Overwrite));
+    assert_eq!(fs::read_to_string(tree.join("sub/both")).unwrap(), "new");
+    assert!(tree.join("sub/kept").exists());
+    assert!(other.join("sub/both").exists());
+    // Undo reports what was overwritten instead of deleting the merged directory
+    assert!(app.history.undo().is_err());
+    assert!(tree.join("sub/kept").exists());
+  }
+
+  #[test]
+  fn reload_replays_exec() {
+    let dir = tempfile::tempdir().unwrap();
+    let conf = dir.path().join("sidetreerc");
+    fs::write(&conf, "set show_hidden true").unwrap();
+    let conf_arg = conf.to_string_lossy().to_string();
+    let opts = Opts::parse_from(["sidetree", "--config", &conf_arg, "--exec", "set dirs_only true"]);
+    let mut app = App::new(&opts, Cache::default());
+    app.load_config().unwrap();
+    assert!(app.config.show_hidden && app.config.dirs_only);
+
+    fs::write(&conf, "").unwrap();
+    app.run_command(&Command::Reload);
+    assert!(!app.config.show_hidden && app.config.dirs_only);
+
+    // A broken file leaves the config as it was
+    fs::write(&conf, "set show_hidden true\nnosuchcommand").unwrap();
+    app.config.show_root = ShowRoot::Hidden;
+    app.run_command(&Command::Reload);
+    assert_eq!(app.config.show_root, ShowRoot::Hidden);
+    assert!(!app.config.show_hidden);
+  }
+
+  #[test]
+  fn template_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let template = dir.path().join("rs.tmpl");
+    fs::write(&template, "// {stem}\n").unwrap();
+    let opts = Opts::parse_from(["sidetree"]);
+    let mut app = App::new(&opts, Cache::default());
+    let add = |ext: &str, path: &Path| {
+      Command::AddTemplate(ext.to_string(), Template::File(path.to_path_buf()))
+    };
+    app.run_command(&add("rs", &template));
+    app.run_command(&add("md", &dir.path().join("missing")));
+
+    assert_eq!(app.template_for(Path::new("/src/main.rs")), Ok("// main\n".to_string()));
+    let err = app.template_for(Path::new("/doc/README.md")).unwrap_err();
+    assert!(err.starts_with(&dir.path().join("missing").display().to_string()), "{}", err);
+    assert_eq!(app.template_for(Path::new("/src/lib.c")), Ok(String::new()));
+  }
+}
@@ -0,0 +1,180 @@
+use crate::history::Action;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// Pair up `sources` with the edited `lines`, resolved relative to `base`.
+///
+/// Fails if the number of lines changed, a line is empty, two lines rename to
+/// the same path, a target exists and is not renamed itself, or its directory
+/// doesn't exist.
+/// Unchanged entries are left out of the result.
+pub fn plan_renames(
+  base: &Path,
+  sources: &[PathBuf],
+  lines: &[&str],
+) -> Result<Vec<(PathBuf, PathBuf)>, String> {
+  if lines.len() != sources.len() {
+    return Err(format!(
+      "expected {} lines, got {}",
+      sources.len(),
+      lines.len()
+    ));
+  }
+  let mut targets: HashMap<PathBuf, usize> = HashMap::new();
+  let mut renames = Vec::new();
+  for (i, (src, line)) in sources.iter().zip(lines).enumerate() {
+    let line = line.trim_end();
+    if line.is_empty() {
+      return Err(format!("line {} is empty", i + 1));
+    }
+    let dst = base.join(line);
+    if let Some(j) = targets.insert(dst.clone(), i) {
+      return Err(format!(
+        "lines {} and {} both rename to {}",
+        j + 1,
+        i + 1,
+        line
+      ));
+    }
+    if dst != *src {
+      renames.push((src.clone(), dst));
+    }
+  }
+  for (_, dst) in &renames {
+    if dst.exists() && !sources.contains(dst) {
+      return Err(format!("{} already exists", dst.display()));
+    }
+    match dst.parent() {
+      Some(dir) if !dir.is_dir() => return Err(format!("{}: not a directory", dir.display())),
+      _ => {}
+    }
+  }
+  Ok(renames)
+}
+
+/// Apply renames from `plan_renames`.
+///
+/// Every source is first moved to a temporary name next to it, so swaps and
+/// chains like a→b, b→a can't overwrite each other. If any move fails,
+/// everything is put back where it was.
+pub fn apply_renames(renames: &[(PathBuf, PathBuf)]) -> Result<(), String> {
+  let mut staged = Vec::new();
+  for (i, (src, dst)) in renames.iter().enumerate() {
+    let tmp = staging_path(src, i);
+    if let Err(e) = std::fs::rename(src, &tmp) {
+      unstage(&staged, renames);
+      return Err(format!("{}: {}", src.display(), e));
+    }
+    staged.push((tmp, dst));
+  }
+  for (i, (tmp, dst)) in staged.iter().enumerate() {
+    if let Err(e) = std::fs::rename(tmp, dst) {
+      for (tmp, dst) in staged[..i].iter().rev() {
+        let _ = std::fs::rename(dst, tmp);
+      }
+      unstage(&staged, renames);
+      return Err(format!("{}: {}", dst.display(), e));
+    }
+  }
+  Ok(())
+}
+
+/// Move the `staged` temporary names back to the sources they came from
+fn unstage(staged: &[(PathBuf, &PathBuf)], renames: &[(PathBuf, PathBuf)]) {
+  for ((tmp, _), (src, _)) in staged.iter().zip(renames) {
+    let _ = std::fs::rename(tmp, src);
+  }
+}
+
+/// The temporary name of the `i`th source while renaming
+fn staging_path(src: &Path, i: usize) -> PathBuf {
+  src.with_file_name(format!(".sidetree-rename-{}-{}", std::process::id(), i))
+}
+
+/// The moves `apply_renames` makes, for `undo`. Undoing them goes through the
+/// same temporary names, so swaps can be undone too
+pub fn rename_actions(renames: &[(PathBuf, PathBuf)]) -> Vec<Action> {
+  let staged: Vec<_> = renames.iter().enumerate().map(|(i, (src, _))| staging_path(src, i)).collect();
+  let stage = renames.iter().zip(&staged).map(|((src, _), tmp)| Action::Move {
+    from: src.clone(),
+    to: tmp.clone(),
+  });
+  let unstage = renames.iter().zip(&staged).map(|((_, dst), tmp)| Action::Move {
+    from: tmp.clone(),
+    to: dst.clone(),
+  });
+  stage.chain(unstage).collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::bulk_rename::*;
+  use crate::history::History;
+
+  #[test]
+  fn rename_duplicate_targets() {
+    let dir = tempfile::tempdir().unwrap();
+    let base = dir.path();
+    let sources = vec![base.join("a"), base.join("b"), base.join("c")];
+    let res = plan_renames(base, &sources, &["x", "b", "x"]);
+    assert_eq!(res, Err("lines 1 and 3 both rename to x".to_string()));
+  }
+
+  #[test]
+  fn rename_to_existing() {
+    let dir = tempfile::tempdir().unwrap();
+    let base = dir.path();
+    std::fs::write(base.join("a"), "").unwrap();
+    std::fs::write(base.join("other"), "").unwrap();
+    let res = plan_renames(base, &[base.join("a")], &["other"]);
+    assert!(res.is_err());
+  }
+
+  #[test]
+  fn rename_into_missing_dir() {
+    let dir = tempfile::tempdir().unwrap();
+    let base = dir.path();
+    std::fs::write(base.join("a"), "").unwrap();
+    std::fs::write(base.join("f"), "").unwrap();
+    assert!(plan_renames(base, &[base.join("a")], &["nodir/a"]).is_err());
+    assert!(plan_renames(base, &[base.join("a")], &["f/a"]).is_err());
+  }
+
+  #[test]
+  fn rename_failure_rolls_back() {
+    let dir = tempfile::tempdir().unwrap();
+    let base = dir.path();
+    std::fs::write(base.join("a"), "a").unwrap();
+    std::fs::write(base.join("b"), "b").unwrap();
+    // The directory went away after planning
+    let renames = vec![(base.join("a"), base.join("x")), (base.join("b"), base.join("nodir/b"))];
+    assert!(apply_renames(&renames).is_err());
+    assert_eq!(std::fs::read_to_string(base.join("a")).unwrap(), "a");
+    assert_eq!(std::fs::read_to_string(base.join("b")).unwrap(), "b");
+    assert_eq!(std::fs::read_dir(base).unwrap().count(), 2);
+  }
+
+  #[test]
+  fn rename_swap() {
+    let dir = tempfile::tempdir().unwrap();
+    let base = dir.path();
+    std::fs::write(base.join("a"), "a").unwrap();
+    std::fs::write(base.join("b"), "b").unwrap();
+    std::fs::write(base.join("c"), "c").unwrap();
+    let sources = vec![base.join("a"), base.join("b"), base.join("c")];
+    let renames = plan_renames(base, &sources, &["b", "a", "c"]).unwrap();
+    assert_eq!(renames.len(), 2);
+    apply_renames(&renames).unwrap();
+    assert_eq!(std::fs::read_to_string(base.join("a")).unwrap(), "b");
+    assert_eq!(std::fs::read_to_string(base.join("b")).unwrap(), "a");
+    assert_eq!(std::fs::read_to_string(base.join("c")).unwrap(), "c");
+    assert_eq!(std::fs::read_dir(base).unwrap().count(), 3);
+
+    let mut history = History::default();
+    history.record("bulkrename", rename_actions(&renames));
+    history.undo().unwrap();
+    assert_eq!(std::fs::read_to_string(base.join("a")).unwrap(), "a");
+    assert_eq!(std::fs::read_to_string(base.join("b")).unwrap(), "b");
+    assert_eq!(std::fs::read_dir(base).unwrap().count(), 3);
+  }
+}
@@ -1,4 +1,5 @@
 use crate::file_tree::ExpandedPaths;
+use std::collections::HashMap;
 use std::fs::File;
 use std::path::{Path, PathBuf};
 
@@ -9,6 +10,14 @@ pub struct Cache {
   
   #[serde(default)]
   pub expanded_paths: ExpandedPaths,
+
+  /// Prompt histories by prompt text, most recent first
+  #[serde(default)]
+  pub histories: HashMap<String, Vec<String>>,
+
+  /// Paths saved with `bookmark`, by name
+  #[serde(default)]
+  pub bookmarks: HashMap<String, PathBuf>,
 }
 
 impl Cache {
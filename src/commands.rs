@@ -1,9 +1,11 @@
-use crate::keymap::parse_key;
+use crate::keymap::{format_keys, parse_keys};
 use combine::Parser;
 use std::collections::HashMap;
 use std::path::Path;
 use std::path::PathBuf;
+use std::str::FromStr;
 use crate::app::KeyPress;
+use crate::config::{AssocKind, Association, EntryAction, NewIn, Template};
 
 #[allow(dead_code)]
 pub struct CmdManager {
@@ -19,38 +21,970 @@ pub enum Argument {
 #[derive(Debug, Clone, PartialEq)]
 pub enum Command {
   Quit,
+  Accept,
   Shell(String),
-  Open(Option<PathBuf>),
+  /// Like `Shell`, without waiting for it to finish
+  ShellAsync(String),
+  Open(Option<PathBuf>, Option<Position>),
+  OpenAll { prompt: bool },
   CmdStr(String),
   Echo(String),
   Set(String, String),
   Cd(Option<PathBuf>),
-  MapKey(KeyPress, Box<Command>),
-  Rename(Option<String>),
-  NewFile(Option<String>),
+  MapKey(Vec<KeyPress>, Box<Command>),
+  Unmap(Vec<KeyPress>),
+  Keys,
+  /// `overwrite` is only set once replacing an existing entry was confirmed
+  Rename { name: Option<String>, overwrite: bool },
+  /// Without a `place`, `new_in` decides where the entry goes
+  NewFile { name: Option<String>, place: Option<NewIn> },
   NewDir(Option<String>),
   Delete { prompt: bool },
+  Trash,
+  Help(Option<String>),
+  Reveal(PathBuf),
+  /// Select the path if shown, or the closest shown directory above it
+  Select(PathBuf),
+  Find(Option<String>),
+  ToggleMark,
+  InvertMarks,
+  MarkAll,
+  ClearMarks,
+  BulkRename,
+  ShowDir(OwnFile),
+  EditFile(OwnFile),
+  AddAction(Box<EntryAction>),
+  Narrow,
+  Widen,
+  ExpandAll,
+  CollapseAll,
+  ExpandLevel(usize),
+  Search(Option<String>),
+  SearchNext,
+  SearchPrev,
+  RootUp,
+  RootDown,
+  Bookmark(Option<String>),
+  GotoBookmark(String),
+  Bookmarks,
+  Reload,
+  SelectCwd,
+  Palette,
+  ToggleHidden,
+  Cancel,
+  Badges,
+  Jump(Option<char>),
+  AddTemplate(String, Template),
+  Diff,
+  NextDir,
+  PrevDir,
+  DumpKeys(Option<PathBuf>),
+  WriteSelection(Option<PathBuf>),
+  Undo,
+  Redo,
+  Repeat,
+  AddAssoc(Association),
+  PreviewWrap,
+  PreviewFocus,
+  Yank,
+  /// Copy the selected path to the system clipboard, relative to the root or not
+  YankPath { relative: bool },
+  Cut,
+  Paste(Conflicts),
+  Active(Option<PathBuf>),
+  TopDir,
+  ToggleHiddenHere,
+  /// `ToggleHidden` or `ToggleHiddenHere`, depending on `hidden_scope`, or
+  /// the other one
+  ToggleHiddenScoped { other: bool },
+  ToggleDirsOnly,
+  Actions,
+  Down,
+  Up,
+  First,
+  Last,
+  HalfPageDown,
+  HalfPageUp,
+  PageDown,
+  PageUp,
+  Left,
+  Right,
+  Enter,
+  /// Open the command prompt with the given text
+  Prompt(Option<String>),
+  Escape,
+  MarkNext,
   // NamedCmd(String, Vec<String>)
 }
 
+/// Quote `arg` for `parse_cmds` if it isn't a plain word
+fn quote(arg: &str) -> String {
+  let plain = !arg.is_empty()
+    && !arg.starts_with(['"', '\''])
+    && !arg.contains(|c: char| c.is_whitespace() || c == '#' || c == ';');
+  if plain {
+    return arg.to_string();
+  }
+  let mut res = String::from("'");
+  for c in arg.chars() {
+    match c {
+      '\\' => res.push_str("\\\\"),
+      '\'' => res.push_str("\\'"),
+      '\n' => res.push_str("\\n"),
+      '\r' => res.push_str("\\r"),
+      '\t' => res.push_str("\\t"),
+      c => res.push(c),
+    }
+  }
+  res.push('\'');
+  res
+}
+
+/// Formats the command so that `parse_cmds` reads it back
+impl std::fmt::Display for Command {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    use Command::*;
+    let path = |p: &Path| quote(&p.to_string_lossy());
+    let opt = |name: &str, arg: &Option<String>| match arg {
+      Some(arg) => format!("{} {}", name, quote(arg)),
+      None => name.to_string(),
+    };
+    let own_file = |file: &OwnFile| match file {
+      OwnFile::Config => "config",
+      OwnFile::Cache => "cache",
+    };
+    let text = match self {
+      Quit => "quit".to_string(),
+      Accept => "accept".to_string(),
+      Shell(cmd) if cmd.is_empty() => "shell".to_string(),
+      Shell(cmd) => format!("shell {}", quote(cmd)),
+      ShellAsync(cmd) => format!("shellasync {}", quote(cmd)),
+      Open(p, pos) => {
+        let mut text = "open".to_string();
+        if let Some(p) = p {
+          text += &format!(" {}", path(p));
+        }
+        if let Some(pos) = pos {
+          text += &format!(" {}", pos);
+        }
+        text
+      }
+      OpenAll { .. } => "openall".to_string(),
+      CmdStr(cmd) => cmd.clone(),
+      Echo(msg) => format!("echo {}", quote(msg)),
+      Set(opt, val) => format!("set {} {}", quote(opt), quote(val)),
+      Cd(p) => match p {
+        Some(p) => format!("cd {}", path(p)),
+        None => "cd".to_string(),
+      },
+      MapKey(keys, cmd) => format!("map {} {}", quote(&format_keys(keys)), cmd),
+      Unmap(keys) => format!("unmap {}", quote(&format_keys(keys))),
+      Keys => "keys".to_string(),
+      Rename { name, .. } => opt("rename", name),
+      NewFile { name, place } => match place {
+        Some(NewIn::Sibling) => opt("newsibling", name),
+        Some(NewIn::Child) => opt("newchild", name),
+        _ => opt("mkfile", name),
+      },
+      // Names ending in / create directories
+      NewDir(name) => opt("mkfile", &name.as_ref().map(|n| format!("{}/", n))),
+      Delete { .. } => "rm".to_string(),
+      Trash => "trash".to_string(),
+      Help(name) => opt("help", name),
+      Reveal(p) => format!("reveal {}", path(p)),
+      Select(p) => format!("select {}", path(p)),
+      Find(query) => opt("find", query),
+      ToggleMark => "mark".to_string(),
+      MarkNext => "marknext".to_string(),
+      InvertMarks => "invertmarks".to_string(),
+      MarkAll => "markall".to_string(),
+      ClearMarks => "clearmarks".to_string(),
+      BulkRename => "bulkrename".to_string(),
+      ShowDir(file) => format!("{} dir", own_file(file)),
+      EditFile(file) => format!("{} edit", own_file(file)),
+      AddAction(action) => format!(
+        "action {} {} {}",
+        quote(&action.pattern),
+        quote(&action.label),
+        action.cmd
+      ),
+      Actions => "actions".to_string(),
+      Narrow => "narrow".to_string(),
+      ExpandAll => "expandall".to_string(),
+      CollapseAll => "collapseall".to_string(),
+      ExpandLevel(depth) => format!("expand {}", depth),
+      Search(pattern) => opt("search", pattern),
+      SearchNext => "searchnext".to_string(),
+      SearchPrev => "searchprev".to_string(),
+      RootUp => "rootup".to_string(),
+      RootDown => "rootdown".to_string(),
+      Bookmark(name) => opt("bookmark", name),
+      GotoBookmark(name) => format!("gotobookmark {}", quote(name)),
+      Bookmarks => "bookmarks".to_string(),
+      Reload => "reload".to_string(),
+      SelectCwd => "selectcwd".to_string(),
+      Palette => "palette".to_string(),
+      Widen => "widen".to_string(),
+      ToggleHidden => "togglehidden".to_string(),
+      ToggleHiddenHere => "togglehiddenhere".to_string(),
+      ToggleHiddenScoped { other: false } => "togglehiddenscoped".to_string(),
+      ToggleHiddenScoped { other: true } => "togglehiddenscoped other".to_string(),
+      ToggleDirsOnly => "toggledirsonly".to_string(),
+      Cancel => "cancel".to_string(),
+      Down => "down".to_string(),
+      Up => "up".to_string(),
+      First => "first".to_string(),
+      Last => "last".to_string(),
+      HalfPageDown => "halfpagedown".to_string(),
+      HalfPageUp => "halfpageup".to_string(),
+      PageDown => "pagedown".to_string(),
+      PageUp => "pageup".to_string(),
+      Left => "left".to_string(),
+      Right => "right".to_string(),
+      Enter => "enter".to_string(),
+      Prompt(text) => opt("prompt", text),
+      Escape => "escape".to_string(),
+      Badges => "badges".to_string(),
+      Jump(c) => opt("jump", &c.map(String::from)),
+      AddTemplate(ext, Template::Text(text)) => format!("template {} {}", quote(ext), quote(text)),
+      AddTemplate(ext, Template::File(p)) => format!("template {} --file {}", quote(ext), path(p)),
+      Diff => "diff".to_string(),
+      NextDir => "nextdir".to_string(),
+      TopDir => "topdir".to_string(),
+      PrevDir => "prevdir".to_string(),
+      AddAssoc(assoc) => {
+        let kind = match assoc.kind {
+          AssocKind::File => "file",
+          AssocKind::Ext => "ext",
+          AssocKind::Mime => "mime",
+        };
+        format!("assoc {} {} {}", kind, quote(&assoc.pattern), quote(&assoc.cmd))
+      }
+      Active(p) => match p {
+        Some(p) => format!("active {}", path(p)),
+        None => "active".to_string(),
+      },
+      Yank => "yank".to_string(),
+      YankPath { relative: false } => "yankpath".to_string(),
+      YankPath { relative: true } => "yankpath relative".to_string(),
+      Cut => "cut".to_string(),
+      Paste(Conflicts::Ask) => "paste".to_string(),
+      Paste(Conflicts::Overwrite) => "paste overwrite".to_string(),
+      Paste(Conflicts::Keep) => "paste keep".to_string(),
+      PreviewWrap => "previewwrap".to_string(),
+      PreviewFocus => "previewfocus".to_string(),
+      DumpKeys(p) => match p {
+        Some(p) => format!("dumpkeys {}", path(p)),
+        None => "dumpkeys".to_string(),
+      },
+      Undo => "undo".to_string(),
+      Redo => "redo".to_string(),
+      Repeat => "repeat".to_string(),
+      WriteSelection(p) => match p {
+        Some(p) => format!("writeselection {}", path(p)),
+        None => "writeselection".to_string(),
+      },
+    };
+    f.write_str(&text)
+  }
+}
+
+/// Documentation for a built-in command
+pub struct CommandInfo {
+  pub name: &'static str,
+  pub args: &'static str,
+  pub desc: &'static str,
+}
+
+pub const COMMANDS: &[CommandInfo] = &[
+  CommandInfo {
+    name: "quit",
+    args: "",
+    desc: "Quit sidetree",
+  },
+  CommandInfo {
+    name: "accept",
+    args: "",
+    desc: "Quit sidetree, printing the selected path",
+  },
+  CommandInfo {
+    name: "open",
+    args: "[path] [line[:col]]",
+    desc: "Open the given path or the selected one using open_cmd",
+  },
+  CommandInfo {
+    name: "openall",
+    args: "",
+    desc: "Open every marked file using open_cmd",
+  },
+  CommandInfo {
+    name: "set",
+    args: "<option> <value>",
+    desc: "Set a config option",
+  },
+  CommandInfo {
+    name: "echo",
+    args: "[args...]",
+    desc: "Echo the arguments, joined by spaces, to the status line",
+  },
+  CommandInfo {
+    name: "shell",
+    args: "[command...]",
+    desc: "Execute a command in a shell, prompting for it if none is given",
+  },
+  CommandInfo {
+    name: "shellasync",
+    args: "<command...>",
+    desc: "Execute a command in a shell in the background, reporting when it's done",
+  },
+  CommandInfo {
+    name: "prompt",
+    args: "[text]",
+    desc: "Prompt for a sidetree command, starting with text",
+  },
+  CommandInfo {
+    name: "down",
+    args: "",
+    desc: "Select the next line",
+  },
+  CommandInfo {
+    name: "up",
+    args: "",
+    desc: "Select the previous line",
+  },
+  CommandInfo {
+    name: "first",
+    args: "",
+    desc: "Select the first line, or the line given by a count",
+  },
+  CommandInfo {
+    name: "last",
+    args: "",
+    desc: "Select the last line, or the line given by a count",
+  },
+  CommandInfo {
+    name: "halfpagedown",
+    args: "",
+    desc: "Move the selection down by half the height of the tree",
+  },
+  CommandInfo {
+    name: "halfpageup",
+    args: "",
+    desc: "Move the selection up by half the height of the tree",
+  },
+  CommandInfo {
+    name: "pagedown",
+    args: "",
+    desc: "Move the selection down by the height of the tree",
+  },
+  CommandInfo {
+    name: "pageup",
+    args: "",
+    desc: "Move the selection up by the height of the tree",
+  },
+  CommandInfo {
+    name: "left",
+    args: "",
+    desc: "Collapse the selected directory, or select its parent",
+  },
+  CommandInfo {
+    name: "right",
+    args: "",
+    desc: "Expand or move into the selected directory, see right_key",
+  },
+  CommandInfo {
+    name: "enter",
+    args: "",
+    desc: "Expand or collapse the selected directory, or open the selected file",
+  },
+  CommandInfo {
+    name: "escape",
+    args: "",
+    desc: "Do what esc_action says",
+  },
+  CommandInfo {
+    name: "cd",
+    args: "[path]",
+    desc: "Change root directory to the given path or the selected folder",
+  },
+  CommandInfo {
+    name: "map",
+    args: "<keys> <command> [args...]",
+    desc: "Map a key, or a sequence of keys, to another sidetree command",
+  },
+  CommandInfo {
+    name: "keys",
+    args: "",
+    desc: "List the key mappings and the commands they run",
+  },
+  CommandInfo {
+    name: "unmap",
+    args: "<keys>",
+    desc: "Remove the mapping of a key or sequence of keys",
+  },
+  CommandInfo {
+    name: "rename",
+    args: "[name]",
+    desc: "Rename the selected entry, prompting for a name if none is given",
+  },
+  CommandInfo {
+    name: "bulkrename",
+    args: "",
+    desc: "Rename the marked entries, or the selected directory's entries, in $EDITOR",
+  },
+  CommandInfo {
+    name: "mk",
+    args: "[name]",
+    desc: "Create a file, or a directory if the name ends with '/'",
+  },
+  CommandInfo {
+    name: "mkfile",
+    args: "[name]",
+    desc: "Same as mk",
+  },
+  CommandInfo {
+    name: "newsibling",
+    args: "[name]",
+    desc: "Like mk, but always next to the selected entry",
+  },
+  CommandInfo {
+    name: "newchild",
+    args: "[name]",
+    desc: "Like mk, but always inside the selected directory",
+  },
+  CommandInfo {
+    name: "rm",
+    args: "",
+    desc: "Delete the selected entry after confirmation",
+  },
+  CommandInfo {
+    name: "trash",
+    args: "",
+    desc: "Move the marked entries, or the selected one, to the trash",
+  },
+  CommandInfo {
+    name: "reveal",
+    args: "<path>",
+    desc: "Expand the tree up to the given path and select it",
+  },
+  CommandInfo {
+    name: "select",
+    args: "<path>",
+    desc: "Select the given path, or the closest directory above it that is shown",
+  },
+  CommandInfo {
+    name: "find",
+    args: "[query]",
+    desc: "Reveal the best fuzzy match for query, prompting for it if not given",
+  },
+  CommandInfo {
+    name: "mark",
+    args: "",
+    desc: "Toggle the mark on the selected entry",
+  },
+  CommandInfo {
+    name: "marknext",
+    args: "",
+    desc: "Toggle the mark on the selected entry and select the next line",
+  },
+  CommandInfo {
+    name: "invertmarks",
+    args: "",
+    desc: "Toggle the mark on every visible entry",
+  },
+  CommandInfo {
+    name: "markall",
+    args: "",
+    desc: "Mark every visible entry",
+  },
+  CommandInfo {
+    name: "clearmarks",
+    args: "",
+    desc: "Remove all marks",
+  },
+  CommandInfo {
+    name: "config",
+    args: "dir|edit",
+    desc: "Show the config directory in the tree, or edit the config file in $EDITOR",
+  },
+  CommandInfo {
+    name: "cache",
+    args: "dir|edit",
+    desc: "Show the cache directory in the tree, or edit the cache file in $EDITOR",
+  },
+  CommandInfo {
+    name: "action",
+    args: "<pattern> <label> <command> [args...]",
+    desc: "Offer a command in :actions for entries whose name matches pattern",
+  },
+  CommandInfo {
+    name: "actions",
+    args: "",
+    desc: "Choose one of the actions matching the selected entry",
+  },
+  CommandInfo {
+    name: "assoc",
+    args: "file|ext|mime <pattern> <command...>",
+    desc: "Open matching files with a shell command instead of open_cmd",
+  },
+  CommandInfo {
+    name: "active",
+    args: "[path]",
+    desc: "Highlight and reveal the file open in the editor, or clear it",
+  },
+  CommandInfo {
+    name: "yank",
+    args: "",
+    desc: "Remember the marked entries, or the selected one, to paste copies of",
+  },
+  CommandInfo {
+    name: "yankpath",
+    args: "[relative]",
+    desc: "Copy the selected path, or with relative the path from the root, to the clipboard",
+  },
+  CommandInfo {
+    name: "cut",
+    args: "",
+    desc: "Like yank, but paste moves the entries",
+  },
+  CommandInfo {
+    name: "paste",
+    args: "[overwrite|keep]",
+    desc: "Copy or move the yanked or cut entries to the selected directory",
+  },
+  CommandInfo {
+    name: "previewwrap",
+    args: "",
+    desc: "Toggle wrapping long lines in the preview",
+  },
+  CommandInfo {
+    name: "previewfocus",
+    args: "",
+    desc: "Toggle whether keys scroll the preview or move in the tree",
+  },
+  CommandInfo {
+    name: "dumpkeys",
+    args: "[file]",
+    desc: "Write the key mappings as map commands to file, or show them",
+  },
+  CommandInfo {
+    name: "repeat",
+    args: "",
+    desc: "Run the last shell command again, for the current selection",
+  },
+  CommandInfo {
+    name: "undo",
+    args: "",
+    desc: "Undo the last rename, rm, trash, mk or paste",
+  },
+  CommandInfo {
+    name: "redo",
+    args: "",
+    desc: "Redo the last undone file operation",
+  },
+  CommandInfo {
+    name: "writeselection",
+    args: "[file]",
+    desc: "Write the selected path to file, selection_file or $SIDETREE_SELECTION_FILE",
+  },
+  CommandInfo {
+    name: "diff",
+    args: "",
+    desc: "Show the differences between the two marked entries",
+  },
+  CommandInfo {
+    name: "template",
+    args: "<extension> <text> | <extension> --file <path>",
+    desc: "Set the initial content of new files with the extension",
+  },
+  CommandInfo {
+    name: "nextdir",
+    args: "",
+    desc: "Select the next directory, skipping files",
+  },
+  CommandInfo {
+    name: "prevdir",
+    args: "",
+    desc: "Select the previous directory, skipping files",
+  },
+  CommandInfo {
+    name: "topdir",
+    args: "",
+    desc: "Select the top level directory containing the selection",
+  },
+  CommandInfo {
+    name: "jump",
+    args: "[letter]",
+    desc: "Cycle through the siblings of the selected entry starting with letter",
+  },
+  CommandInfo {
+    name: "badges",
+    args: "",
+    desc: "Refresh the badges shown after entries from badge_cmd",
+  },
+  CommandInfo {
+    name: "cancel",
+    args: "",
+    desc: "Stop the most recently started background operation",
+  },
+  CommandInfo {
+    name: "togglehidden",
+    args: "",
+    desc: "Toggle showing hidden files in the whole tree",
+  },
+  CommandInfo {
+    name: "togglehiddenhere",
+    args: "",
+    desc: "Toggle showing hidden files in the selected directory only",
+  },
+  CommandInfo {
+    name: "togglehiddenscoped",
+    args: "[other]",
+    desc: "Toggle showing hidden files where hidden_scope says, or the other way with other",
+  },
+  CommandInfo {
+    name: "toggledirsonly",
+    args: "",
+    desc: "Toggle showing only directories",
+  },
+  CommandInfo {
+    name: "narrow",
+    args: "",
+    desc: "Only show the selected directory, without changing the root",
+  },
+  CommandInfo {
+    name: "search",
+    args: "[pattern]",
+    desc: "Select the next shown entry whose name contains pattern and highlight the matches",
+  },
+  CommandInfo {
+    name: "searchnext",
+    args: "",
+    desc: "Select the next entry matching the last search",
+  },
+  CommandInfo {
+    name: "searchprev",
+    args: "",
+    desc: "Select the previous entry matching the last search",
+  },
+  CommandInfo {
+    name: "rootup",
+    args: "",
+    desc: "Make the parent of the root the root, selecting the old one",
+  },
+  CommandInfo {
+    name: "rootdown",
+    args: "",
+    desc: "Make the selected directory, or the one containing the selected file, the root",
+  },
+  CommandInfo {
+    name: "bookmark",
+    args: "[name]",
+    desc: "Save the selected path under name, asking for it when not given",
+  },
+  CommandInfo {
+    name: "gotobookmark",
+    args: "<name>",
+    desc: "Select the path saved under name, moving the root if it's outside the tree",
+  },
+  CommandInfo {
+    name: "bookmarks",
+    args: "",
+    desc: "Choose a bookmark to go to",
+  },
+  CommandInfo {
+    name: "selectcwd",
+    args: "",
+    desc: "Select the directory sidetree was started in, moving the root if outside the tree",
+  },
+  CommandInfo {
+    name: "palette",
+    args: "",
+    desc: "Choose a command to run from all of them, by name or description",
+  },
+  CommandInfo {
+    name: "reload",
+    args: "",
+    desc: "Read the config file again, starting from the defaults",
+  },
+  CommandInfo {
+    name: "expandall",
+    args: "",
+    desc: "Expand the selected directory and those below it, up to max_expand_depth levels",
+  },
+  CommandInfo {
+    name: "expand",
+    args: "<depth>",
+    desc: "Expand the selected directory depth levels deep and collapse what's deeper",
+  },
+  CommandInfo {
+    name: "collapseall",
+    args: "",
+    desc: "Collapse the selected directory and every directory below it",
+  },
+  CommandInfo {
+    name: "widen",
+    args: "",
+    desc: "Undo the last narrow",
+  },
+  CommandInfo {
+    name: "help",
+    args: "[command]",
+    desc: "List all commands, or show help for a single command",
+  },
+];
+
+pub fn command_info(name: &str) -> Option<&'static CommandInfo> {
+  COMMANDS.iter().find(|c| c.name == name)
+}
+
+/// A line and optional column to open a file at, written `line[:col]`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Position {
+  pub line: usize,
+  pub col: Option<usize>,
+}
+
+impl std::fmt::Display for Position {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    match self.col {
+      Some(col) => write!(f, "{}:{}", self.line, col),
+      None => write!(f, "{}", self.line),
+    }
+  }
+}
+
+impl FromStr for Position {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let invalid = || format!("invalid position {}", s);
+    let (line, col) = match s.split_once(':') {
+      Some((line, col)) => (line, Some(col.parse().map_err(|_| invalid())?)),
+      None => (s, None),
+    };
+    let line = line.parse().map_err(|_| invalid())?;
+    Ok(Position { line, col })
+  }
+}
+
+/// What `paste` does about names that are already taken
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Conflicts {
+  /// Ask whether to overwrite them
+  Ask,
+  Overwrite,
+  /// Give the pasted entries a numeric suffix
+  Keep,
+}
+
+/// Sidetree's own files, for the `config` and `cache` commands
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OwnFile {
+  Config,
+  Cache,
+}
+
 type CmdBlock = Vec<Command>;
 
 pub fn build_cmd(cmd: String, args: Vec<String>) -> Result<Command, String> {
   match cmd.as_str() {
     "quit" => Ok(Command::Quit),
-    "open" => Ok(Command::Open(None)),
+    "accept" => Ok(Command::Accept),
+    "open" => match args.as_slice() {
+      [] => Ok(Command::Open(None, None)),
+      // A lone position applies to the selected entry
+      [arg] => Ok(match arg.parse() {
+        Ok(pos) => Command::Open(None, Some(pos)),
+        Err(_) => Command::Open(Some(PathBuf::from(arg)), None),
+      }),
+      [path, pos] => Ok(Command::Open(Some(PathBuf::from(path)), Some(pos.parse()?))),
+      _ => Err("usage: open [path] [line[:col]]".to_string()),
+    },
+    "openall" => Ok(Command::OpenAll { prompt: true }),
+    // `set open_rule <pattern> <command...>` is short for `assoc`, with the
+    // kind told by the pattern
+    "set" if args.first().map(String::as_str) == Some("open_rule") => match &args[1..] {
+      [pattern, cmd @ ..] if !cmd.is_empty() => Ok(Command::AddAssoc(Association {
+        kind: match pattern.contains(['*', '?']) {
+          true => AssocKind::File,
+          false => AssocKind::Ext,
+        },
+        pattern: match pattern.contains(['*', '?']) {
+          true => pattern.clone(),
+          false => pattern.trim_start_matches('.').to_string(),
+        },
+        cmd: cmd.join(" "),
+      })),
+      _ => Err("usage: set open_rule <extension|glob> <command...>".to_string()),
+    },
     "set" => Ok(Command::Set(args[0].clone(), args[1].clone())),
     "echo" => Ok(Command::Echo(args.join(" "))),
     "shell" => Ok(Command::Shell(args.join(" "))),
-    "cd" => Ok(Command::Cd(args.get(0).map(PathBuf::from))),
+    "shellasync" if args.is_empty() => Err("usage: shellasync <command...>".to_string()),
+    "shellasync" => Ok(Command::ShellAsync(args.join(" "))),
+    "cd" => Ok(Command::Cd(args.first().map(PathBuf::from))),
     "map" => Ok(Command::MapKey(
-      parse_key(args[0].as_str()).map_err(|_| "could not parse key")?,
+      parse_keys(args[0].as_str())?,
       Box::new(build_cmd(args[1].clone(), args[2..].to_vec())?),
     )),
-    "rename" => Ok(Command::Rename(args.get(0).cloned())),
-    "mkfile" => Ok(Command::NewFile(args.get(0).cloned())),
-    "mk" => Ok(Command::NewFile(args.get(0).cloned())),
+    "keys" => Ok(Command::Keys),
+    "unmap" => Ok(Command::Unmap(parse_keys(
+      args.first().ok_or("usage: unmap <keys>")?,
+    )?)),
+    "rename" => Ok(Command::Rename {
+      name: args.first().cloned(),
+      overwrite: false,
+    }),
+    "bulkrename" => Ok(Command::BulkRename),
+    "mkfile" | "mk" => Ok(Command::NewFile {
+      name: args.first().cloned(),
+      place: None,
+    }),
+    "newsibling" => Ok(Command::NewFile {
+      name: args.first().cloned(),
+      place: Some(NewIn::Sibling),
+    }),
+    "newchild" => Ok(Command::NewFile {
+      name: args.first().cloned(),
+      place: Some(NewIn::Child),
+    }),
     "rm" => Ok(Command::Delete { prompt: true }),
+    "trash" => Ok(Command::Trash),
+    "help" => Ok(Command::Help(args.first().cloned())),
+    "mark" => Ok(Command::ToggleMark),
+    "marknext" => Ok(Command::MarkNext),
+    "invertmarks" => Ok(Command::InvertMarks),
+    "markall" => Ok(Command::MarkAll),
+    "clearmarks" => Ok(Command::ClearMarks),
+    "config" | "cache" => {
+      let file = if cmd == "config" {
+        OwnFile::Config
+      } else {
+        OwnFile::Cache
+      };
+      match args.first().map(String::as_str) {
+        Some("dir") => Ok(Command::ShowDir(file)),
+        Some("edit") => Ok(Command::EditFile(file)),
+        _ => Err(format!("usage: {} dir|edit", cmd)),
+      }
+    }
+    "action" => match args.as_slice() {
+      [pattern, label, cmd, cmd_args @ ..] => Ok(Command::AddAction(Box::new(EntryAction {
+        pattern: pattern.clone(),
+        label: label.clone(),
+        cmd: build_cmd(cmd.clone(), cmd_args.to_vec())?,
+      }))),
+      _ => Err("usage: action <pattern> <label> <command> [args...]".to_string()),
+    },
+    "actions" => Ok(Command::Actions),
+    "assoc" => match args.as_slice() {
+      [kind, pattern, cmd @ ..] if !cmd.is_empty() => {
+        let kind = match kind.as_str() {
+          "file" => AssocKind::File,
+          "ext" => AssocKind::Ext,
+          "mime" => AssocKind::Mime,
+          _ => return Err(format!("unknown association kind {}", kind)),
+        };
+        Ok(Command::AddAssoc(Association {
+          kind,
+          pattern: pattern.clone(),
+          cmd: cmd.join(" "),
+        }))
+      }
+      _ => Err("usage: assoc file|ext|mime <pattern> <command...>".to_string()),
+    },
+    "narrow" => Ok(Command::Narrow),
+    "expandall" => Ok(Command::ExpandAll),
+    "collapseall" => Ok(Command::CollapseAll),
+    "search" => Ok(Command::Search((!args.is_empty()).then(|| args.join(" ")))),
+    "searchnext" => Ok(Command::SearchNext),
+    "searchprev" => Ok(Command::SearchPrev),
+    "rootup" => Ok(Command::RootUp),
+    "rootdown" => Ok(Command::RootDown),
+    "bookmark" => Ok(Command::Bookmark(args.first().cloned())),
+    "gotobookmark" => match args.first() {
+      Some(name) => Ok(Command::GotoBookmark(name.clone())),
+      None => Err("usage: gotobookmark <name>".to_string()),
+    },
+    "bookmarks" => Ok(Command::Bookmarks),
+    "reload" => Ok(Command::Reload),
+    "selectcwd" => Ok(Command::SelectCwd),
+    "palette" => Ok(Command::Palette),
+    "expand" => args
+      .first()
+      .and_then(|a| a.parse().ok())
+      .map(Command::ExpandLevel)
+      .ok_or_else(|| "usage: expand <depth>".to_string()),
+    "cancel" => Ok(Command::Cancel),
+    "down" => Ok(Command::Down),
+    "up" => Ok(Command::Up),
+    "first" => Ok(Command::First),
+    "last" => Ok(Command::Last),
+    "halfpagedown" => Ok(Command::HalfPageDown),
+    "halfpageup" => Ok(Command::HalfPageUp),
+    "pagedown" => Ok(Command::PageDown),
+    "pageup" => Ok(Command::PageUp),
+    "left" => Ok(Command::Left),
+    "right" => Ok(Command::Right),
+    "enter" => Ok(Command::Enter),
+    "prompt" => Ok(Command::Prompt((!args.is_empty()).then(|| args.join(" ")))),
+    "escape" => Ok(Command::Escape),
+    "badges" => Ok(Command::Badges),
+    "diff" => Ok(Command::Diff),
+    "dumpkeys" => Ok(Command::DumpKeys(args.first().map(PathBuf::from))),
+    "undo" => Ok(Command::Undo),
+    "redo" => Ok(Command::Redo),
+    "repeat" => Ok(Command::Repeat),
+    "writeselection" => Ok(Command::WriteSelection(args.first().map(PathBuf::from))),
+    "nextdir" => Ok(Command::NextDir),
+    "topdir" => Ok(Command::TopDir),
+    "active" => Ok(Command::Active(args.first().map(PathBuf::from))),
+    "yank" => Ok(Command::Yank),
+    "yankpath" => match args.first().map(String::as_str) {
+      None => Ok(Command::YankPath { relative: false }),
+      Some("relative") => Ok(Command::YankPath { relative: true }),
+      Some(_) => Err("usage: yankpath [relative]".to_string()),
+    },
+    "cut" => Ok(Command::Cut),
+    "paste" => match args.first().map(String::as_str) {
+      None => Ok(Command::Paste(Conflicts::Ask)),
+      Some("overwrite") => Ok(Command::Paste(Conflicts::Overwrite)),
+      Some("keep") => Ok(Command::Paste(Conflicts::Keep)),
+      Some(_) => Err("usage: paste [overwrite|keep]".to_string()),
+    },
+    "previewwrap" => Ok(Command::PreviewWrap),
+    "previewfocus" => Ok(Command::PreviewFocus),
+    "prevdir" => Ok(Command::PrevDir),
+    "template" => match args.as_slice() {
+      [ext, flag, path] if flag == "--file" => Ok(Command::AddTemplate(
+        ext.clone(),
+        Template::File(PathBuf::from(path)),
+      )),
+      [ext, text] => Ok(Command::AddTemplate(ext.clone(), Template::Text(text.clone()))),
+      _ => Err("usage: template <extension> <text> | template <extension> --file <path>".to_string()),
+    },
+    "jump" => match args.first().map(|a| a.chars().collect::<Vec<_>>()).as_deref() {
+      None => Ok(Command::Jump(None)),
+      Some(&[c]) => Ok(Command::Jump(Some(c))),
+      Some(_) => Err("usage: jump [letter]".to_string()),
+    },
+    "togglehidden" => Ok(Command::ToggleHidden),
+    "togglehiddenhere" => Ok(Command::ToggleHiddenHere),
+    "togglehiddenscoped" => match args.first().map(String::as_str) {
+      None => Ok(Command::ToggleHiddenScoped { other: false }),
+      Some("other") => Ok(Command::ToggleHiddenScoped { other: true }),
+      Some(_) => Err("usage: togglehiddenscoped [other]".to_string()),
+    },
+    "toggledirsonly" => Ok(Command::ToggleDirsOnly),
+    "widen" => Ok(Command::Widen),
+    "reveal" => Ok(Command::Reveal(
+      args.first().map(PathBuf::from).ok_or("reveal needs a path")?,
+    )),
+    "select" => Ok(Command::Select(
+      args.first().map(PathBuf::from).ok_or("select needs a path")?,
+    )),
+    "find" => Ok(Command::Find((!args.is_empty()).then(|| args.join(" ")))),
     _ => Err(format!("unknown command {}", cmd)),
   }
 }
@@ -191,14 +1125,120 @@ mod test {
     );
   }
   #[test]
+  fn parse_cmd_help() {
+    assert_eq!(parse_cmds("help"), Ok(vec![Command::Help(None)]));
+    assert_eq!(
+      parse_cmds("help map"),
+      Ok(vec![Command::Help(Some("map".to_string()))])
+    );
+  }
+  #[test]
   fn parse_cmd_multiple() {
     assert_eq!(
       parse_cmds("quit; open"),
-      Ok(vec![Command::Quit, Command::Open(None)])
+      Ok(vec![Command::Quit, Command::Open(None, None)])
     );
     assert_eq!(
       parse_cmds("quit\nopen"),
-      Ok(vec![Command::Quit, Command::Open(None)])
+      Ok(vec![Command::Quit, Command::Open(None, None)])
+    );
+  }
+  #[test]
+  fn display_round_trip() {
+    let cmds = [
+      "quit",
+      "shell 'echo \"$sidetree_entry\" # not a comment'",
+      "open src/main.rs 4:2",
+      "open 42",
+      "set open_cmd 'it\\'s'",
+      "cd ..",
+      "map <c-x> action '*.sh' Run shell 'sh \"$1\"'",
+      "map '#' jump a",
+      "map <gt> narrow",
+      "map <a-ret> open",
+      "map gg topdir",
+      "map '<c-x>k' rm",
+      "unmap gg",
+      "shell",
+      "template sh '#!/bin/sh\\n'",
+      "template rs --file ~/rs.tmpl",
+      "assoc mime 'image/*' 'feh \"$sidetree_entry\"'",
+      "config edit",
+      "rename ''",
+      "mk",
+      "newsibling a.txt",
+      "newchild sub/",
+      "yankpath relative",
+      "togglehiddenscoped",
+      "togglehiddenscoped other",
+      "shellasync 'make -j4'",
+      "paste",
+      "paste keep",
+      "bookmark 'my project'",
+      "gotobookmark 'my project'",
+      "prompt 'rename '",
+      "palette",
+    ];
+    for text in cmds {
+      let cmd = parse_cmds(text).unwrap().remove(0);
+      assert_eq!(parse_cmds(&cmd.to_string()), Ok(vec![cmd.clone()]), "{}", cmd);
+    }
+  }
+  #[test]
+  fn parse_cmd_action() {
+    assert_eq!(
+      parse_cmds("action '*.sh' Run shell 'sh \"$sidetree_entry\"'"),
+      Ok(vec![Command::AddAction(Box::new(EntryAction {
+        pattern: "*.sh".to_string(),
+        label: "Run".to_string(),
+        cmd: Command::Shell("sh \"$sidetree_entry\"".to_string()),
+      }))])
+    );
+    assert!(parse_cmds("action '*.sh' Run").is_err());
+  }
+  #[test]
+  fn parse_cmd_open_rule() {
+    let assoc = |kind, pattern: &str| {
+      Ok(vec![Command::AddAssoc(Association {
+        kind,
+        pattern: pattern.to_string(),
+        cmd: "feh \"$1\"".to_string(),
+      })])
+    };
+    assert_eq!(parse_cmds("set open_rule png feh '\"$1\"'"), assoc(AssocKind::Ext, "png"));
+    assert_eq!(parse_cmds("set open_rule .png feh '\"$1\"'"), assoc(AssocKind::Ext, "png"));
+    assert_eq!(parse_cmds("set open_rule '*.png' feh '\"$1\"'"), assoc(AssocKind::File, "*.png"));
+    assert!(parse_cmds("set open_rule png").is_err());
+  }
+  #[test]
+  fn parse_cmd_template() {
+    assert_eq!(
+      parse_cmds("template sh \"#!/bin/sh\\n\""),
+      Ok(vec![Command::AddTemplate(
+        "sh".to_string(),
+        Template::Text("#!/bin/sh\n".to_string())
+      )])
+    );
+    assert_eq!(
+      parse_cmds("template rs --file ~/rs.tmpl"),
+      Ok(vec![Command::AddTemplate(
+        "rs".to_string(),
+        Template::File(PathBuf::from("~/rs.tmpl"))
+      )])
+    );
+  }
+  #[test]
+  fn parse_cmd_open() {
+    let pos = |line, col| Some(Position { line, col });
+    assert_eq!(parse_cmds("open 42"), Ok(vec![Command::Open(None, pos(42, None))]));
+    assert_eq!(
+      parse_cmds("open src/main.rs 42:7"),
+      Ok(vec![Command::Open(Some(PathBuf::from("src/main.rs")), pos(42, Some(7)))])
+    );
+    assert_eq!(
+      parse_cmds("open src/main.rs"),
+      Ok(vec![Command::Open(Some(PathBuf::from("src/main.rs")), None)])
     );
+    assert!(parse_cmds("open src/main.rs x").is_err());
   }
 }
@@ -1,4 +1,8 @@
+use crate::commands::Command;
 use combine::parser::EasyParser;
+use crate::util::{edit_distance, glob_match};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use config_macros::ConfParsable;
 use ratatui::style::{Color, Modifier, Style};
 
@@ -6,7 +10,9 @@ use ratatui::style::{Color, Modifier, Style};
 #[derive(Default, ConfParsable)]
 pub struct Config {
   pub show_hidden: bool,
+  pub dirs_only: bool,
   pub open_cmd: String,
+  pub is_open_cmd: String,
   pub quit_on_open: bool,
   pub file_icons: bool,
   pub icon_style: Style,
@@ -14,16 +20,297 @@ pub struct Config {
   pub file_name_style: Style,
   pub highlight_style: Style,
   pub link_style: Style,
+  pub mark_style: Style,
+  pub search_match_style: Style,
+  pub dim_unfocused: bool,
+  pub unfocused_style: Style,
+  pub on_change: String,
+  pub strict_names: bool,
+  pub show_modes: bool,
+  pub statusline_format: String,
+  pub prompt_reveal: bool,
+  pub esc_action: EscAction,
+  pub nav_acceleration: bool,
+  pub read_timeout: Ranged<0, 60_000>,
+  pub max_expand_depth: Ranged<1, 100, 5>,
+  pub scrolloff: Ranged<0, 1000>,
+  pub show_guides: bool,
+  pub number: bool,
+  pub relativenumber: bool,
+  pub number_style: Style,
+  pub follow_symlinks: bool,
+  pub guide_style: Style,
+  pub group_dirs: GroupDirs,
+  pub sort: SortBy,
+  pub dir_sort: GroupSort,
+  pub file_sort: GroupSort,
+  pub sort_reverse: bool,
+  pub sort_case_sensitive: bool,
+  pub show_size: bool,
+  pub show_mtime: bool,
+  pub error_log: String,
+  pub on_cd: String,
+  pub selection_file: String,
+  pub trash_dir: String,
+  pub use_trash: bool,
+  pub clipboard_cmd: String,
+  pub write_selection: bool,
+  pub right_key: RightKey,
+  pub left_key: LeftKey,
+  pub hidden_scope: HiddenScope,
+  pub new_in: NewIn,
+  pub minimal: bool,
+  pub history_limit: Ranged<0, 100_000, 100>,
+  pub diff_cmd: String,
+  pub badge_cmd: String,
+  pub badge_interval: Ranged<0, 86_400>,
+  pub badge_style: Style,
+  pub git_status: bool,
+  pub git_status_interval: Ranged<0, 86_400, 1>,
+  pub respect_gitignore: bool,
+  pub git_staged_style: Style,
+  pub git_modified_style: Style,
+  pub git_untracked_style: Style,
+  pub git_ignored_style: Style,
+  pub active_file_style: Style,
+  pub show_root: ShowRoot,
+  pub show_parent_entry: bool,
+  pub preview: bool,
+  pub preview_max_bytes: Ranged<0, 1_000_000_000>,
+  /// Added with the `action` command
+  #[skip_opt]
+  pub actions: Vec<EntryAction>,
+  /// New file contents by extension, added with the `template` command
+  #[skip_opt]
+  pub templates: HashMap<String, Template>,
+  /// Open commands by file name, extension or MIME type, added with `assoc`
+  #[skip_opt]
+  pub associations: Vec<Association>,
+}
+
+/// Initial content of new files
+#[derive(Debug, Clone, PartialEq)]
+pub enum Template {
+  Text(String),
+  File(PathBuf),
+}
+
+/// What an `Association` pattern is matched against
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AssocKind {
+  /// The file name, as a glob
+  File,
+  /// The extension, ignoring case
+  Ext,
+  /// The MIME type, as a glob
+  Mime,
+}
+
+/// A shell command to open matching files with instead of `open_cmd`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Association {
+  pub kind: AssocKind,
+  pub pattern: String,
+  pub cmd: String,
+}
+
+/// A command offered by `:actions` for entries whose name matches `pattern`
+#[derive(Debug, Clone, PartialEq)]
+pub struct EntryAction {
+  pub pattern: String,
+  pub label: String,
+  pub cmd: Command,
+}
+
+conf_enum! {
+  /// Where directories are placed among files
+  GroupDirs {
+    #[default]
+    First => "first",
+    Last => "last",
+    /// Sorted together with files
+    Mixed => "mixed",
+  }
+}
+
+conf_enum! {
+  /// What entries in a directory are ordered by, before `group_dirs` applies
+  SortBy {
+    #[default]
+    Name => "name",
+    /// Smallest first
+    Size => "size",
+    /// Oldest first
+    Mtime => "mtime",
+    /// By extension, then by name
+    Extension => "extension",
+    /// By name, with numbers in them compared by value
+    Natural => "natural",
+  }
+}
+
+conf_enum! {
+  /// What directories or files are ordered by instead of `sort`, when
+  /// `group_dirs` keeps them apart
+  GroupSort {
+    /// The same as everything else
+    #[default]
+    Sort => "sort",
+    Name => "name",
+    Size => "size",
+    Mtime => "mtime",
+    Extension => "extension",
+    Natural => "natural",
+  }
+}
+
+impl GroupSort {
+  /// The order to use, with `sort` as the fallback
+  pub fn or(self, sort: SortBy) -> SortBy {
+    match self {
+      GroupSort::Sort => sort,
+      GroupSort::Name => SortBy::Name,
+      GroupSort::Size => SortBy::Size,
+      GroupSort::Mtime => SortBy::Mtime,
+      GroupSort::Extension => SortBy::Extension,
+      GroupSort::Natural => SortBy::Natural,
+    }
+  }
+}
+
+conf_enum! {
+  /// How the root directory of the tree is shown
+  ShowRoot {
+    /// Its directory name
+    #[default]
+    Name => "name",
+    Absolute => "absolute",
+    /// Its absolute path, with the home directory as `~`
+    Abbreviated => "abbreviated",
+    /// Not at all, its contents are shown at the top level
+    Hidden => "hidden",
+  }
+}
+
+conf_enum! {
+  /// What `l`/`<right>` does on an expanded directory
+  RightKey {
+    /// Move to its first child, if any
+    #[default]
+    Drill => "drill",
+    /// Move to the next line
+    Next => "next",
+  }
+}
+
+conf_enum! {
+  /// What `h`/`<left>` does on a top level entry that isn't expanded
+  LeftKey {
+    /// Nothing, there is no line above it to move to
+    #[default]
+    Up => "up",
+    /// Change the root to its parent, or widen the view if it's narrowed
+    Ascend => "ascend",
+  }
+}
+
+conf_enum! {
+  /// Where `togglehiddenscoped` toggles hidden files
+  HiddenScope {
+    /// The whole tree, by flipping `show_hidden`
+    #[default]
+    Global => "global",
+    /// The selected directory only
+    Local => "local",
+  }
+}
+
+conf_enum! {
+  /// Where `mk` creates entries, relative to the selection
+  NewIn {
+    /// Inside the selected directory, or next to the selected file
+    #[default]
+    Auto => "auto",
+    /// Next to the selected entry, directory or not
+    Sibling => "sibling",
+    /// Inside the selected entry, which has to be a directory
+    Child => "child",
+  }
+}
+
+conf_enum! {
+  /// What `<esc>` does when no prompt is open
+  EscAction {
+    /// Cancel the latest background task if there is one, otherwise clear
+    /// marks if there are any, otherwise the status message
+    #[default]
+    Auto => "auto",
+    None => "none",
+    ClearMarks => "clear-marks",
+    Cancel => "cancel",
+    Quit => "quit",
+  }
 }
 
 impl Config {
   pub fn set_opt(&mut self, name: &str, val: &str) -> Result<(), String> {
+    if !self.names().contains(&name) {
+      return Err(self.unknown_option(name));
+    }
     self.get_child_mut(name)?.set_opt(val)
   }
+
+  /// The error for setting `name`, with the closest option name if it looks
+  /// like a typo of one, or else all of them
+  fn unknown_option(&self, name: &str) -> String {
+    let closest = self
+      .names()
+      .iter()
+      .map(|n| (edit_distance(name, n), n))
+      .min()
+      .filter(|&(d, n)| d <= 2.max(n.len() / 4));
+    match closest {
+      Some((_, n)) => format!("unknown option {}, did you mean {}?", name, n),
+      None => format!("unknown option {}, options are: {}", name, self.names().join(", ")),
+    }
+  }
   #[allow(dead_code)]
   pub fn get_opt(&self, name: &str) -> Result<String, String> {
     Ok(self.get_child(name)?.get_opt())
   }
+
+  /// Add an association, replacing one of the same kind and pattern
+  pub fn add_association(&mut self, assoc: Association) {
+    match self
+      .associations
+      .iter_mut()
+      .find(|a| a.kind == assoc.kind && a.pattern == assoc.pattern)
+    {
+      Some(old) => *old = assoc,
+      None => self.associations.push(assoc),
+    }
+  }
+
+  /// The command to open `path` with: the first matching file name
+  /// association, then extension, then MIME type, then `open_cmd`.
+  /// `mime` is only called when there are MIME associations
+  pub fn open_cmd_for(&self, path: &Path, mime: impl FnOnce() -> Option<String>) -> &str {
+    let of_kind = |kind| self.associations.iter().filter(move |a| a.kind == kind);
+    let name = path.file_name().map(|n| n.to_string_lossy()).unwrap_or_default();
+    let ext = path.extension().map(|e| e.to_string_lossy()).unwrap_or_default();
+    let found = of_kind(AssocKind::File)
+      .find(|a| glob_match(&a.pattern, &name))
+      .or_else(|| {
+        of_kind(AssocKind::Ext).find(|a| !ext.is_empty() && a.pattern.eq_ignore_ascii_case(&ext))
+      })
+      .or_else(|| {
+        let mut rules = of_kind(AssocKind::Mime).peekable();
+        rules.peek()?;
+        let mime = mime()?;
+        rules.find(|a| glob_match(&a.pattern, &mime))
+      });
+    found.map_or(self.open_cmd.as_str(), |a| a.cmd.as_str())
+  }
 }
 
 // Lib functions
@@ -34,6 +321,8 @@ trait ConfOpt {
 }
 
 trait ConfTree {
+  /// The names of the options, in declaration order
+  fn names(&self) -> &'static [&'static str];
   fn get_child(&self, name: &str) -> Result<&dyn ConfOpt, String>;
   fn get_child_mut(&mut self, name: &str) -> Result<&mut dyn ConfOpt, String>;
 }
@@ -41,13 +330,51 @@ trait ConfTree {
 fn parse_opt<T: std::str::FromStr>(val: &str) -> Result<T, String> {
   match val.parse::<T>() {
     Ok(res) => Ok(res),
-    Err(_) => Err("Could not parse option value".to_string()),
+    Err(_) => Err(format!("invalid value {}", val)),
   }
 }
 
+/// Define an enum option, parsed from and displayed as the given names
+macro_rules! conf_enum {
+  ($(#[$meta:meta])* $name:ident { $($(#[$vmeta:meta])* $var:ident => $str:literal,)* }) => {
+    $(#[$meta])*
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+    pub enum $name {
+      $($(#[$vmeta])* $var,)*
+    }
+
+    impl ConfOpt for $name {
+      fn set_opt(&mut self, val: &str) -> Result<(), String> {
+        *self = match val {
+          $($str => $name::$var,)*
+          _ => {
+            return Err(format!(
+              "invalid value {}, expected one of: {}",
+              val,
+              [$($str),*].join(", ")
+            ))
+          }
+        };
+        Ok(())
+      }
+      fn get_opt(&self) -> String {
+        match self {
+          $($name::$var => $str,)*
+        }
+        .to_string()
+      }
+    }
+  };
+}
+use conf_enum;
+
 impl ConfOpt for bool {
   fn set_opt(&mut self, val: &str) -> Result<(), String> {
-    *self = parse_opt(val)?;
+    *self = match val.to_ascii_lowercase().as_str() {
+      "true" | "yes" | "on" | "1" => true,
+      "false" | "no" | "off" | "0" => false,
+      _ => return Err(format!("invalid value {}, expected true or false", val)),
+    };
     Ok(())
   }
   fn get_opt(&self) -> String {
@@ -61,7 +388,7 @@ impl ConfOpt for bool {
 
 impl ConfOpt for i32 {
   fn set_opt(&mut self, val: &str) -> Result<(), String> {
-    *self = parse_opt(val)?;
+    *self = parse_opt(val).map_err(|e| format!("{}, expected a number", e))?;
     Ok(())
   }
   fn get_opt(&self) -> String {
@@ -69,6 +396,40 @@ impl ConfOpt for i32 {
   }
 }
 
+/// A number option that has to be within `MIN..=MAX`. Defaults to `DEFAULT`,
+/// or `MIN` when not given
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ranged<const MIN: usize, const MAX: usize, const DEFAULT: usize = MIN>(usize);
+
+impl<const MIN: usize, const MAX: usize, const DEFAULT: usize> Ranged<MIN, MAX, DEFAULT> {
+  pub fn get(self) -> usize {
+    self.0
+  }
+}
+
+impl<const MIN: usize, const MAX: usize, const DEFAULT: usize> Default
+  for Ranged<MIN, MAX, DEFAULT>
+{
+  fn default() -> Self {
+    Ranged(DEFAULT)
+  }
+}
+
+impl<const MIN: usize, const MAX: usize, const DEFAULT: usize> ConfOpt
+  for Ranged<MIN, MAX, DEFAULT>
+{
+  fn set_opt(&mut self, val: &str) -> Result<(), String> {
+    match val.parse() {
+      Ok(n) if (MIN..=MAX).contains(&n) => *self = Ranged(n),
+      _ => return Err(format!("invalid value {}, expected a number from {} to {}", val, MIN, MAX)),
+    }
+    Ok(())
+  }
+  fn get_opt(&self) -> String {
+    self.0.to_string()
+  }
+}
+
 impl ConfOpt for String {
   fn set_opt(&mut self, val: &str) -> Result<(), String> {
     *self = val.to_string();
@@ -274,6 +635,86 @@ mod tests {
   use ratatui::style::Modifier;
   use ratatui::style::Style;
 
+  #[test]
+  fn set_opt_errors() {
+    let mut cfg = Config::default();
+    for (val, expected) in [("yes", true), ("Off", false), ("1", true), ("false", false)] {
+      cfg.set_opt("show_hidden", val).unwrap();
+      assert_eq!(cfg.show_hidden, expected, "{}", val);
+    }
+    assert!(cfg.set_opt("show_hidden", "maybe").is_err());
+    assert_eq!(
+      cfg.set_opt("show_hiden", "true"),
+      Err("unknown option show_hiden, did you mean show_hidden?".to_string())
+    );
+    let err = cfg.set_opt("colour", "red").unwrap_err();
+    assert!(err.starts_with("unknown option colour, options are: "), "{}", err);
+    assert!(err.contains("show_hidden"));
+    assert_eq!(
+      cfg.set_opt("history_limit", "x"),
+      Err("invalid value x, expected a number from 0 to 100000".to_string())
+    );
+    // The code defaults are the documented ones, without any sidetreerc
+    assert_eq!(cfg.max_expand_depth.get(), 5);
+    assert_eq!(cfg.history_limit.get(), 100);
+    assert_eq!(cfg.git_status_interval.get(), 1);
+    assert_eq!(cfg.scrolloff.get(), 0);
+    cfg.set_opt("max_expand_depth", "100").unwrap();
+    assert_eq!(cfg.max_expand_depth.get(), 100);
+    for val in ["0", "101", "-1", "deep"] {
+      assert_eq!(
+        cfg.set_opt("max_expand_depth", val),
+        Err(format!("invalid value {}, expected a number from 1 to 100", val))
+      );
+    }
+    assert_eq!(cfg.get_opt("max_expand_depth"), Ok("100".to_string()));
+    assert!(cfg.set_opt("group_dirs", "sideways").unwrap_err().contains("expected one of"));
+  }
+
+  #[test]
+  fn open_cmd_precedence() {
+    let mut cfg = Config {
+      open_cmd: "default".to_string(),
+      ..Default::default()
+    };
+    let assoc = |kind, pattern: &str, cmd: &str| Association {
+      kind,
+      pattern: pattern.to_string(),
+      cmd: cmd.to_string(),
+    };
+    cfg.add_association(assoc(AssocKind::Mime, "text/*", "mime"));
+    cfg.add_association(assoc(AssocKind::Ext, "md", "ext"));
+    cfg.add_association(assoc(AssocKind::File, "README*", "file"));
+    let text = || Some("text/plain".to_string());
+    let no_mime = || -> Option<String> { panic!("MIME type looked up") };
+
+    assert_eq!(cfg.open_cmd_for(Path::new("/x/README.md"), no_mime), "file");
+    assert_eq!(cfg.open_cmd_for(Path::new("/x/notes.MD"), no_mime), "ext");
+    assert_eq!(cfg.open_cmd_for(Path::new("/x/notes.txt"), text), "mime");
+    assert_eq!(cfg.open_cmd_for(Path::new("/x/a.png"), || Some("image/png".into())), "default");
+    assert_eq!(cfg.open_cmd_for(Path::new("/x/a.png"), || None), "default");
+
+    // Same kind and pattern replaces, without changing the order
+    cfg.add_association(assoc(AssocKind::Ext, "md", "ext2"));
+    assert_eq!(cfg.associations.len(), 3);
+    assert_eq!(cfg.open_cmd_for(Path::new("/x/notes.md"), no_mime), "ext2");
+
+    // Without MIME associations the type is never looked up
+    cfg.associations.retain(|a| a.kind != AssocKind::Mime);
+    assert_eq!(cfg.open_cmd_for(Path::new("/x/notes.txt"), no_mime), "default");
+  }
+
+  #[test]
+  fn enum_options() {
+    let mut cfg = Config::default();
+    assert_eq!(cfg.esc_action, EscAction::Auto);
+    assert_eq!(cfg.set_opt("esc_action", "quit"), Ok(()));
+    assert_eq!(cfg.esc_action, EscAction::Quit);
+    assert_eq!(cfg.get_opt("esc_action"), Ok("quit".to_string()));
+    assert!(cfg.set_opt("esc_action", "bogus").is_err());
+    assert_eq!(cfg.esc_action, EscAction::Quit);
+  }
+
   #[test]
   fn style_parsing() {
     assert!(parse_color(",").is_err());
@@ -2,7 +2,8 @@ use proc_macro2::TokenStream;
 use quote::quote;
 use syn::spanned::Spanned;
 
-#[proc_macro_derive(ConfParsable)]
+/// Fields marked `#[skip_opt]` are not settable with `set`
+#[proc_macro_derive(ConfParsable, attributes(skip_opt))]
 pub fn derive_conf_tree(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
   let ast = syn::parse_macro_input!(input as syn::DeriveInput);
 
@@ -23,6 +24,7 @@ fn conf_tree(ast: &syn::DeriveInput) -> syn::Result<TokenStream> {
 
   let field_names = fields
     .iter()
+    .filter(|f| !f.attrs.iter().any(|a| a.path().is_ident("skip_opt")))
     .map(|f| {
       f.ident
         .as_ref()
@@ -37,6 +39,9 @@ fn conf_tree(ast: &syn::DeriveInput) -> syn::Result<TokenStream> {
 
   Ok(quote! {
     impl #impl_generics crate::config::ConfTree for #name #ty_generics #where_clause {
+      fn names(&self) -> &'static [&'static str] {
+        &[#(#field_strs),*]
+      }
 
       fn get_child(&self, name: &str) -> Result<&dyn crate::config::ConfOpt, String> {
         match name {
@@ -1,11 +1,17 @@
-use crate::config::Config;
+use crate::config::{Config, GroupDirs, ShowRoot, SortBy};
+use crate::git::{GitStatuses, Ignores};
 use crate::icons;
-use crate::util::StatefulList;
+use crate::util::{abbreviate_home, human_size, match_ranges, natural_cmp, relative_time, StatefulList};
 use path_absolutize::Absolutize;
-use std::collections::HashSet;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::fs::Metadata;
 use std::iter;
 use std::path::Path;
 use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use ratatui::{
   buffer::Buffer, layout::Rect, style::Style, text::Line, widgets::List,
   widgets::ListItem, widgets::StatefulWidget,
@@ -36,16 +42,188 @@ impl ExpandedPaths {
     self.expanded_paths.insert(PathBuf::from(path));
   }
 
-  #[allow(dead_code)]
   pub fn is_expanded(&self, path: &Path) -> bool {
     self.expanded_paths.contains(path)
   }
+
+  pub fn retain(&mut self, f: impl FnMut(&PathBuf) -> bool) {
+    self.expanded_paths.retain(f);
+  }
+
+  pub fn iter(&self) -> impl Iterator<Item = &Path> {
+    self.expanded_paths.iter().map(PathBuf::as_path)
+  }
+}
+
+/// How long the UI waits for a directory listing before showing a spinner
+const READ_WAIT: Duration = Duration::from_millis(30);
+
+const SPINNER: [char; 8] = ['⣾', '⣽', '⣻', '⢿', '⡿', '⣟', '⣯', '⣷'];
+
+type Listing = std::io::Result<Vec<TreeEntry>>;
+/// Where requests to read a directory go, and where the listings come back
+type Workers = (Sender<PathBuf>, Receiver<(PathBuf, Listing)>);
+
+/// Threads reading directories. A file system that stops responding holds up
+/// one of them per directory read from it
+const READ_WORKERS: usize = 4;
+
+/// The result of asking `DirReader` for a directory listing
+enum DirRead {
+  Done(Vec<TreeEntry>),
+  Loading,
+  TimedOut,
+}
+
+/// Reads and stats directory entries on background threads, so slow file
+/// systems don't freeze the UI
+#[derive(Default)]
+pub struct DirReader {
+  /// Feeds the workers, started with the first read
+  workers: Option<Workers>,
+  /// Reads in progress, with their start time. A directory is only read by
+  /// one worker at a time
+  pending: HashMap<PathBuf, Instant>,
+  /// Reads that finished while waiting for another
+  finished: HashMap<PathBuf, Listing>,
+}
+
+impl DirReader {
+  /// List `path`. Without a timeout, the directory is read on the current
+  /// thread. A read that timed out is waited for again on the next call, and
+  /// used once it's done
+  fn read(&mut self, path: &Path, timeout: Option<Duration>) -> DirRead {
+    let timeout = match timeout {
+      Some(t) => t,
+      None => return DirRead::Done(read_entries(path).unwrap_or_default()),
+    };
+    self.collect(Duration::ZERO, None);
+    if let Some(res) = self.finished.remove(path) {
+      return DirRead::Done(res.unwrap_or_default());
+    }
+    if let Some(start) = self.pending.get(path) {
+      return match start.elapsed() > timeout {
+        true => DirRead::TimedOut,
+        false => DirRead::Loading,
+      };
+    }
+    let (requests, _) = self.workers.get_or_insert_with(start_workers);
+    if requests.send(path.to_path_buf()).is_err() {
+      return DirRead::Done(read_entries(path).unwrap_or_default());
+    }
+    self.pending.insert(path.to_path_buf(), Instant::now());
+    self.collect(READ_WAIT, Some(path));
+    match self.finished.remove(path) {
+      Some(res) => DirRead::Done(res.unwrap_or_default()),
+      None => DirRead::Loading,
+    }
+  }
+
+  /// Move finished reads to `finished`, waiting up to `wait` for `path`
+  fn collect(&mut self, wait: Duration, path: Option<&Path>) {
+    let Some((_, results)) = &self.workers else {
+      return;
+    };
+    let deadline = Instant::now() + wait;
+    loop {
+      let res = match deadline.checked_duration_since(Instant::now()) {
+        Some(left) if !left.is_zero() => results.recv_timeout(left).ok(),
+        _ => results.try_recv().ok(),
+      };
+      let Some((dir, listing)) = res else {
+        return;
+      };
+      self.pending.remove(&dir);
+      let found = path == Some(dir.as_path());
+      self.finished.insert(dir, listing);
+      if found {
+        return;
+      }
+    }
+  }
+}
+
+fn start_workers() -> Workers {
+  let (requests, jobs) = mpsc::channel::<PathBuf>();
+  let (done, results) = mpsc::channel();
+  let jobs = Arc::new(Mutex::new(jobs));
+  for _ in 0..READ_WORKERS {
+    let jobs = jobs.clone();
+    let done = done.clone();
+    std::thread::spawn(move || loop {
+      let dir = match jobs.lock().map(|jobs| jobs.recv()) {
+        Ok(Ok(dir)) => dir,
+        _ => return,
+      };
+      let listing = read_entries(&dir);
+      if done.send((dir, listing)).is_err() {
+        return;
+      }
+    });
+  }
+  (requests, results)
+}
+
+/// The entries of the directory at `path`, with their metadata
+fn read_entries(path: &Path) -> Listing {
+  Ok(list_dir(path)?.into_iter().map(TreeEntry::new).collect())
+}
+
+fn spinner_frame() -> char {
+  let millis = std::time::SystemTime::now()
+    .duration_since(std::time::UNIX_EPOCH)
+    .map(|d| d.as_millis())
+    .unwrap_or(0);
+  SPINNER[(millis / 100) as usize % SPINNER.len()]
+}
+
+fn list_dir(path: &Path) -> std::io::Result<Vec<PathBuf>> {
+  std::fs::read_dir(path)?
+    .map(|e| e.map(|e| e.path()))
+    .collect()
+}
+
+/// A `git status` running in the background
+struct GitRead {
+  root: PathBuf,
+  /// The directories asked about, everything shown when `full`
+  dirs: Vec<PathBuf>,
+  full: bool,
+  result: Receiver<GitStatuses>,
 }
 
 pub struct FileTreeState {
   pub root_entry: TreeEntry,
   pub expanded_paths: ExpandedPaths,
+  /// The expanded directories in the tree, whose changes show up in it, as of
+  /// the last rescan
+  shown_dirs: HashSet<PathBuf>,
+  /// Marked entries, for operating on several entries at once
+  pub selected_paths: HashSet<PathBuf>,
+  reader: DirReader,
+  /// Directories the view has been narrowed to, innermost last
+  view_roots: Vec<PathBuf>,
+  /// Directories showing hidden files regardless of `show_hidden`
+  show_hidden_in: HashSet<PathBuf>,
+  /// Annotations from `badge_cmd`, shown after the names
+  pub badges: HashMap<PathBuf, String>,
+  /// The file open in the editor, shown with `active_file_style`
+  pub active_path: Option<PathBuf>,
+  /// The pattern of the last `search`, highlighted in the names
+  pub search: Option<String>,
+  /// From `git status` when `git_status` is set
+  git_status: GitStatuses,
+  /// What git ignores, hidden when `respect_gitignore` is set
+  git_ignores: Ignores,
+  /// When the above were last read in full
+  git_read: Option<Instant>,
+  /// Directories with changes git hasn't been asked about yet
+  git_stale: Vec<PathBuf>,
+  /// The `git status` running in the background, if any
+  git_reading: Option<GitRead>,
   lines: StatefulList<TreeEntryLine>,
+  /// How many lines fit, as last drawn
+  height: usize,
 }
 
 impl FileTreeState {
@@ -53,7 +231,21 @@ impl FileTreeState {
     let mut res = FileTreeState {
       root_entry: TreeEntry::new(path),
       lines: StatefulList::new(),
+      height: 0,
       expanded_paths: ExpandedPaths::default(),
+      shown_dirs: HashSet::new(),
+      selected_paths: HashSet::new(),
+      reader: DirReader::default(),
+      view_roots: Vec::new(),
+      show_hidden_in: HashSet::new(),
+      badges: HashMap::new(),
+      active_path: None,
+      search: None,
+      git_status: GitStatuses::default(),
+      git_ignores: Ignores::default(),
+      git_read: None,
+      git_stale: Vec::new(),
+      git_reading: None,
     };
     res.expanded_paths.expand(&res.root_entry.path);
     res.lines.state.select(Some(0));
@@ -75,24 +267,273 @@ impl FileTreeState {
     self.expanded_paths.expand(path)
   }
 
-  #[allow(dead_code)]
+  #[cfg(test)]
   pub fn is_expanded(&self, path: &Path) -> bool {
     self.expanded_paths.is_expanded(path)
   }
 
+  pub fn toggle_mark(&mut self, path: &Path) {
+    if !self.selected_paths.remove(path) {
+      self.selected_paths.insert(path.to_path_buf());
+    }
+  }
+
+  pub fn clear_marks(&mut self) {
+    self.selected_paths.clear();
+  }
+
+  /// Toggle the mark of every visible entry except the root
+  pub fn invert_marks(&mut self) {
+    for p in self.markable_paths() {
+      self.toggle_mark(&p);
+    }
+  }
+
+  /// Mark every visible entry except the root
+  pub fn mark_all(&mut self) {
+    self.selected_paths.extend(self.markable_paths());
+  }
+
+  /// Visible entries directly inside `dir`
+  pub fn visible_children(&self, dir: &Path) -> Vec<PathBuf> {
+    self
+      .lines
+      .items
+      .iter()
+      .filter(|l| l.path.parent() == Some(dir))
+      .map(|l| l.path.clone())
+      .collect()
+  }
+
+  fn markable_paths(&self) -> Vec<PathBuf> {
+    let root = self.view_root().unwrap_or(&self.root_entry.path);
+    self
+      .lines
+      .items
+      .iter()
+      .filter(|l| l.path != root && !l.is_parent)
+      .map(|l| l.path.clone())
+      .collect()
+  }
+
+  /// Toggle showing hidden files directly inside `dir` only
+  pub fn toggle_hidden_in(&mut self, dir: &Path) {
+    if !self.show_hidden_in.remove(dir) {
+      self.show_hidden_in.insert(dir.to_path_buf());
+    }
+  }
+
+  /// Only show `dir` and what's below it, without changing the root
+  pub fn narrow(&mut self, cfg: &Config, dir: PathBuf) {
+    self.expand(&dir);
+    self.view_roots.push(dir);
+    self.update(cfg);
+  }
+
+  /// Undo the last `narrow`. Returns false if the view wasn't narrowed
+  pub fn widen(&mut self, cfg: &Config) -> bool {
+    let widened = self.view_roots.pop().is_some();
+    self.update(cfg);
+    widened
+  }
+
+  /// The directory the view is narrowed to, if any
+  pub fn view_root(&self) -> Option<&Path> {
+    self.view_roots.last().map(PathBuf::as_path)
+  }
+
   pub fn change_root(&mut self, cfg: &Config, path: PathBuf) {
+    self.view_roots.clear();
     self.root_entry = TreeEntry::new(path);
     self.root_entry.expanded = true;
+    self.git_read = None;
     self.update(cfg);
   }
 
   /// Rescan the file system and rebuild the list
   pub fn update(&mut self, cfg: &Config) {
+    self.rebuild(cfg, true);
+  }
+
+  /// Rebuild the list from what was read before, for changes that don't need
+  /// a rescan, like the git status or the age of entries
+  pub fn refresh(&mut self, cfg: &Config) {
+    self.rebuild(cfg, false);
+  }
+
+  /// Whether directory listings are still being read in the background
+  pub fn is_loading(&self) -> bool {
+    !self.reader.pending.is_empty()
+  }
+
+  /// The expanded directories in the tree, whose changes show up in it
+  pub fn shown_dirs(&self) -> &HashSet<PathBuf> {
+    &self.shown_dirs
+  }
+
+  fn rebuild(&mut self, cfg: &Config, rescan: bool) {
     let selected = self.line().map(|x| x.path.clone());
-    self.root_entry.update(&self.expanded_paths);
+    let selected_idx = self.lines.index().unwrap_or(0);
+    if cfg.show_root == ShowRoot::Hidden {
+      // Without a root line there would be no way to expand it again
+      let root = self.view_root().unwrap_or(&self.root_entry.path).to_path_buf();
+      self.expand(&root);
+    }
+    if rescan {
+      self
+        .root_entry
+        .update(cfg, &self.expanded_paths, &mut self.reader, &mut Vec::new());
+      let root = &self.root_entry.path;
+      self.shown_dirs = self
+        .expanded_paths
+        .iter()
+        .filter(|p| p.starts_with(root) && p.is_dir())
+        .map(Path::to_path_buf)
+        .collect();
+    }
+    self.update_git_status(cfg);
     self.rebuild_list(cfg);
     if let Some(x) = selected {
-      self.select_path(&x);
+      if !self.select_path(&x) {
+        self.select_nearest(&x, selected_idx);
+      }
+    }
+    // With the root hidden there may be no lines at all, and nothing to select
+    match self.lines.items.is_empty() {
+      true => self.lines.state.select(None),
+      false if self.lines.index().is_none() => self.lines.first(),
+      false => {}
+    }
+  }
+
+  /// Have git look at the directory containing `path` again on the next update
+  pub fn invalidate_git(&mut self, path: &Path) {
+    if let Some(dir) = path.parent() {
+      self.git_stale.push(dir.to_path_buf());
+    }
+  }
+
+  /// Ask git about the status again in the background, every
+  /// `git_status_interval` seconds for everything shown, or sooner for
+  /// directories changed in the meantime. The ignore rules are read again as
+  /// often, and for newly expanded directories. Outside of a repository,
+  /// nothing has a status or is ignored
+  fn update_git_status(&mut self, cfg: &Config) {
+    if !cfg.git_status && !cfg.respect_gitignore {
+      self.git_status = GitStatuses::default();
+      self.git_ignores = Ignores::default();
+      self.git_read = None;
+      self.git_stale.clear();
+      self.git_reading = None;
+      return;
+    }
+    let interval = Duration::from_secs(cfg.git_status_interval.get() as u64);
+    let due = match self.git_read {
+      None => true,
+      Some(t) => cfg.git_status_interval.get() > 0 && t.elapsed() >= interval,
+    };
+    let root = self.root_entry.path.clone();
+    // Collapsed directories need their contents read too, to show their state,
+    // so the scope is whatever the view is narrowed to
+    let shown = self.view_root().unwrap_or(&root).to_path_buf();
+    // Only the expanded directories changes were in are asked about again
+    let expanded = &self.expanded_paths;
+    let mut dirs: Vec<PathBuf> = std::mem::take(&mut self.git_stale)
+      .iter()
+      .filter_map(|d| d.ancestors().find(|a| expanded.is_expanded(a)))
+      .filter(|d| d.starts_with(&shown))
+      .map(Path::to_path_buf)
+      .collect();
+    if due {
+      self.git_read = Some(Instant::now());
+    }
+
+    if !cfg.respect_gitignore {
+      self.git_ignores = Ignores::default();
+    } else {
+      if due {
+        self.git_ignores = Ignores::new(&root);
+      }
+      for dir in &dirs {
+        self.git_ignores.forget(dir);
+      }
+      let expanded = self.expanded_paths.iter().filter(|p| p.starts_with(&root));
+      for dir in expanded {
+        self.git_ignores.load(dir);
+      }
+    }
+
+    if !cfg.git_status {
+      self.git_status = GitStatuses::default();
+      self.git_reading = None;
+      return;
+    }
+    self.receive_git_status();
+    if due {
+      dirs = vec![shown];
+    } else if dirs.is_empty() {
+      return;
+    }
+    dirs.sort();
+    dirs.dedup_by(|d, prev| d.starts_with(prev));
+    if self.git_reading.is_some() {
+      // Asked about once the running one is done
+      self.git_stale.extend(dirs);
+      return;
+    }
+    let (tx, result) = mpsc::channel();
+    let (read_root, read_dirs) = (root.clone(), dirs.clone());
+    std::thread::spawn(move || {
+      let _ = tx.send(GitStatuses::read(&read_root, &read_dirs).unwrap_or_default());
+    });
+    self.git_reading = Some(GitRead {
+      root,
+      dirs,
+      full: due,
+      result,
+    });
+  }
+
+  /// Take in what the `git status` running in the background found, if it's
+  /// done, unless the root changed in the meantime
+  fn receive_git_status(&mut self) {
+    let Some(read) = self.git_reading.take() else {
+      return;
+    };
+    let status = match read.result.try_recv() {
+      Ok(status) => status,
+      Err(TryRecvError::Empty) => {
+        self.git_reading = Some(read);
+        return;
+      }
+      Err(TryRecvError::Disconnected) => return,
+    };
+    if read.root != self.root_entry.path {
+      return;
+    }
+    if read.full {
+      self.git_status = status;
+    } else {
+      self.git_status.merge(status, &read.dirs);
+    }
+  }
+
+  /// Select the sibling of the vanished `path` closest to its old index `idx`,
+  /// or its parent when it has no siblings left
+  fn select_nearest(&mut self, path: &Path, idx: usize) {
+    let parent = path.parent();
+    let lines = &self.lines.items;
+    let nearest = lines
+      .iter()
+      .enumerate()
+      .filter(|(_, l)| l.path.parent() == parent)
+      .min_by_key(|(i, _)| i.abs_diff(idx))
+      .map(|(i, _)| i)
+      .or_else(|| lines.iter().position(|l| Some(l.path.as_path()) == parent));
+    match nearest {
+      Some(i) => self.lines.select_index(i),
+      None if !lines.is_empty() => self.lines.nth(idx),
+      None => {}
     }
   }
 
@@ -100,6 +541,23 @@ impl FileTreeState {
     self.lines.nth(n)
   }
 
+  pub fn select_first(&mut self) {
+    self.lines.first()
+  }
+
+  pub fn select_last(&mut self) {
+    self.lines.last()
+  }
+
+  /// Move the selection by the height of the tree, or half of it
+  pub fn select_page(&mut self, down: bool, half: bool) {
+    let lines = if half { self.height / 2 } else { self.height }.max(1);
+    match down {
+      true => self.lines.forward(lines),
+      false => self.lines.back(lines),
+    }
+  }
+
   pub fn select_next(&mut self) {
     self.lines.next()
   }
@@ -107,13 +565,82 @@ impl FileTreeState {
     self.lines.previous()
   }
 
-  pub fn select_path(&mut self, path: &Path) {
+  /// Select the next directory below the selection, skipping files
+  pub fn select_next_dir(&mut self) {
+    let start = self.lines.index().map_or(0, |i| i + 1);
+    if let Some(i) = (start..self.lines.items.len()).find(|&i| self.lines.items[i].is_dir) {
+      self.lines.select_index(i);
+    }
+  }
+
+  /// Select the previous directory above the selection, skipping files
+  pub fn select_prev_dir(&mut self) {
+    let end = self.lines.index().unwrap_or(0).min(self.lines.items.len());
+    if let Some(i) = (0..end).rev().find(|&i| self.lines.items[i].is_dir) {
+      self.lines.select_index(i);
+    }
+  }
+
+  /// Select the top level directory the selection is in, below the root
+  pub fn select_top_ancestor(&mut self) {
+    let (Some(current), Some(line)) = (self.lines.index(), self.line()) else {
+      return;
+    };
+    let path = line.path.clone();
+    let root = self.view_root().unwrap_or(&self.root_entry.path);
+    // Ancestors come before the selection, the top one last when going backwards
+    let top = (0..current)
+      .rev()
+      .map(|i| (i, &self.lines.items[i]))
+      .take_while(|(_, l)| l.path != root && !l.is_parent)
+      .filter(|(_, l)| path.starts_with(&l.path))
+      .last();
+    if let Some((i, _)) = top {
+      self.lines.select_index(i);
+    }
+  }
+
+  /// Select the line for `path`. Returns false if it isn't shown
+  pub fn select_path(&mut self, path: &Path) -> bool {
     let path = path.absolutize().expect("Error absolutizing path");
-    if let Some(idx) = self.lines.items.iter().position(|line| line.path == path) {
-      self.lines.select_index(idx);
+    match self.lines.items.iter().position(|line| line.path == path) {
+      Some(idx) => {
+        self.lines.select_index(idx);
+        true
+      }
+      None => false,
     }
   }
 
+  /// Select `path`, or if it isn't shown, the closest shown directory above it
+  pub fn select_closest(&mut self, path: &Path) {
+    for anc in path.ancestors() {
+      if self.select_path(anc) {
+        return;
+      }
+    }
+  }
+
+  /// All entries below the view root read so far, whether their directory is
+  /// expanded or not, except those hidden by the config
+  pub fn known_paths(&self, cfg: &Config) -> Vec<PathBuf> {
+    fn walk(entry: &TreeEntry, state: &FileTreeState, cfg: &Config, out: &mut Vec<PathBuf>) {
+      for child in &entry.children {
+        if child.should_show_item(cfg, &state.show_hidden_in, &state.git_ignores, 1) {
+          out.push(child.path.clone());
+          walk(child, state, cfg, out);
+        }
+      }
+    }
+    let view = self
+      .view_root()
+      .and_then(|dir| self.root_entry.find_path(dir))
+      .unwrap_or(&self.root_entry);
+    let mut paths = Vec::new();
+    walk(view, self, cfg, &mut paths);
+    paths
+  }
+
   /// Expand parents to reveal <path>
   pub fn expand_to_path(&mut self, path: &Path) {
     let path = path.absolutize().expect("Error absolutizing path");
@@ -125,9 +652,110 @@ impl FileTreeState {
     }
   }
 
-  /// Select the next entry up
+  /// Expand `dir` and the directories below it, `max_depth` levels deep with
+  /// `dir` as the first. Directories that wouldn't be shown, like hidden ones,
+  /// and links to directories are left alone
+  pub fn expand_all(&mut self, cfg: &Config, dir: &Path, max_depth: usize) {
+    if max_depth == 0 {
+      return;
+    }
+    self.expand(dir);
+    for path in list_dir(dir).unwrap_or_default() {
+      let entry = TreeEntry::new(path);
+      let shown = entry.should_show_item(cfg, &self.show_hidden_in, &self.git_ignores, 1);
+      // A link back up would be expanded until `max_depth`, even if the
+      // tree then refuses to show it
+      if entry.is_dir && (!entry.is_link || cfg.follow_symlinks) && shown {
+        self.expand_all(cfg, &entry.path, max_depth - 1);
+      }
+    }
+  }
+
+  /// Collapse `dir` and every directory below it
+  pub fn collapse_all(&mut self, dir: &Path) {
+    self.expanded_paths.retain(|p| !p.starts_with(dir));
+  }
+
+  /// Expand `dir` and the directories below it `depth` levels deep, at most
+  /// `max_expand_depth`, like `expand_all`, and collapse everything deeper
+  pub fn expand_level(&mut self, cfg: &Config, dir: &Path, depth: usize) {
+    self.collapse_all(dir);
+    self.expand_all(cfg, dir, depth.min(cfg.max_expand_depth.get()));
+  }
+
+  /// Select the next shown entry after the selected one whose name contains
+  /// `pattern`, or the one before it when not `forward`, wrapping around.
+  /// Returns false if there is none
+  pub fn select_match(&mut self, pattern: &str, forward: bool) -> bool {
+    let n = self.lines.items.len();
+    let current = self.lines.index().unwrap_or(0);
+    let next = (1..=n)
+      .map(|i| match forward {
+        true => (current + i) % n,
+        false => (current + n - i) % n,
+      })
+      .find(|&i| {
+        let line = &self.lines.items[i];
+        let name = line.path.file_name().unwrap_or_default().to_string_lossy();
+        !line.is_parent && !match_ranges(&name, pattern).is_empty()
+      });
+    match next {
+      Some(i) => {
+        self.lines.select_index(i);
+        true
+      }
+      None => false,
+    }
+  }
+
+  /// Select the next entry in the same directory as the selected one whose
+  /// name starts with `c`, ignoring case. Returns false if there is none
+  pub fn jump_sibling(&mut self, c: char) -> bool {
+    let (Some(current), Some(line)) = (self.lines.index(), self.line()) else {
+      return false;
+    };
+    let (parent, level) = (line.path.parent().map(Path::to_path_buf), line.level);
+    let lower: String = c.to_lowercase().collect();
+    let n = self.lines.items.len();
+    let next = (1..=n).map(|i| (current + i) % n).find(|&i| {
+      let l = &self.lines.items[i];
+      l.level == level
+        && l.path.parent() == parent.as_deref()
+        && l
+          .path
+          .file_name()
+          .is_some_and(|name| name.to_string_lossy().to_lowercase().starts_with(&lower))
+    });
+    match next {
+      Some(i) => {
+        self.lines.select_index(i);
+        true
+      }
+      None => false,
+    }
+  }
+
+  /// Expand the selected directory, or move to its first child if it's
+  /// already expanded. Does nothing on files and the `..` line
+  pub fn drill_down(&mut self) {
+    let (path, expanded) = match self.line() {
+      Some(l) if l.is_dir && !l.is_parent => (l.path.clone(), l.is_expanded),
+      _ => return,
+    };
+    if !expanded {
+      self.expand(&path);
+    } else if let Some(child) = self.visible_children(&path).first() {
+      self.select_path(&child.clone());
+    }
+  }
+
+  /// Select the next entry up. Top level entries have no parent line
+  /// when the root is hidden, so they stay selected
   pub fn select_up(&mut self) -> Option<()> {
     let level = self.lines.selected()?.level;
+    if level == 0 {
+      return Some(());
+    }
     while self.lines.index()? != 0 {
       self.select_prev();
       if self.lines.selected()?.level < level {
@@ -147,13 +775,20 @@ impl FileTreeState {
   }
 
   /// Currently selected line
-  fn line(&self) -> Option<&TreeEntryLine> {
+  pub fn line(&self) -> Option<&TreeEntryLine> {
     self.lines.selected()
   }
 
-  /// Currently selected line index
-  pub fn selected_idx(&self) -> Option<usize> {
-    self.lines.index()
+  /// The 1-based index of the selected line, and the number of lines shown
+  pub fn position(&self) -> (usize, usize) {
+    let index = self.lines.index().map_or(0, |i| i + 1);
+    (index, self.lines.items.len())
+  }
+
+  /// The index of the line shown `row` lines from the top, as last drawn
+  pub fn index_at_row(&self, row: usize) -> Option<usize> {
+    let index = self.lines.state.offset() + row;
+    (index < self.lines.items.len()).then_some(index)
   }
 
   /// Currently selected entry
@@ -174,7 +809,38 @@ impl FileTreeState {
   /// Rebuild the list from the file tree.
   /// Does not rescan the filesystem
   fn rebuild_list(&mut self, cfg: &Config) {
-    self.lines.items = self.root_entry.build_lines_rec(cfg, 0).collect();
+    // Views of directories that went away fall back to the enclosing view
+    while let Some(dir) = self.view_roots.last() {
+      if self.root_entry.find_path(dir).is_some() {
+        break;
+      }
+      self.view_roots.pop();
+    }
+    let view = self
+      .view_root()
+      .and_then(|dir| self.root_entry.find_path(dir))
+      .unwrap_or(&self.root_entry);
+    let ignored = &self.git_ignores;
+    self.lines.items = view.build_lines_rec(cfg, &self.show_hidden_in, ignored, 0).collect();
+    set_guides(&mut self.lines.items);
+    for line in &mut self.lines.items {
+      if let Some(status) = self.git_status.get(&line.path).filter(|_| !line.is_parent) {
+        for (_, style) in line.line.iter_mut().skip(1) {
+          *style = style.patch(status.style(cfg));
+        }
+      }
+      if self.active_path.as_ref() == Some(&line.path) && !line.is_parent {
+        for (_, style) in line.line.iter_mut().skip(1) {
+          *style = style.patch(cfg.active_file_style);
+        }
+      }
+      if let Some(pattern) = self.search.as_deref().filter(|_| !line.is_parent) {
+        line.highlight_matches(pattern, cfg.search_match_style);
+      }
+      if let Some(badge) = self.badges.get(&line.path) {
+        line.line.push((format!(" {}", badge), cfg.badge_style));
+      }
+    }
   }
 
   pub fn current_dir(&self) -> PathBuf {
@@ -196,16 +862,49 @@ pub struct FileTree<'a> {
 }
 
 impl<'a> FileTree<'a> {
-  pub fn new(cfg: &'a Config) -> FileTree {
+  pub fn new(cfg: &'a Config) -> FileTree<'a> {
     FileTree { cfg }
   }
 }
 
+/// The number shown next to the line at `index` with `number` or
+/// `relativenumber`: its distance from the `selected` line, or its own number
+fn line_number(cfg: &Config, index: usize, selected: usize) -> Option<usize> {
+  match (cfg.number, cfg.relativenumber) {
+    (_, true) if index != selected => Some(index.abs_diff(selected)),
+    (true, _) => Some(index + 1),
+    (false, true) => Some(0),
+    (false, false) => None,
+  }
+}
+
 impl<'a> StatefulWidget for FileTree<'a> {
   type State = FileTreeState;
 
   fn render(self, area: Rect, buf: &mut Buffer, state: &mut Self::State) {
-    let items: Vec<ListItem> = state.lines.items.iter().map(|x| x.make_line()).collect();
+    state.height = area.height as usize;
+    state.lines.scroll_to_selected(area.height as usize, self.cfg.scrolloff.get());
+    let selected = state.lines.index().unwrap_or(0);
+    // Wide enough for the largest number
+    let digits = match self.cfg.number || self.cfg.relativenumber {
+      true => state.lines.items.len().max(1).to_string().len(),
+      false => 0,
+    };
+    let width = (area.width as usize).saturating_sub(digits + (digits > 0) as usize);
+    let items: Vec<ListItem> = state
+      .lines
+      .items
+      .iter()
+      .enumerate()
+      .map(|(i, x)| {
+        let number = line_number(self.cfg, i, selected).map(|n| format!("{:>digits$} ", n));
+        if state.selected_paths.contains(&x.path) {
+          x.make_line(self.cfg, self.cfg.mark_style, width, number)
+        } else {
+          x.make_line(self.cfg, Style::default(), width, number)
+        }
+      })
+      .collect();
     let list = List::new(items).highlight_style(self.cfg.highlight_style);
     list.render(area, buf, &mut state.lines.state);
   }
@@ -216,8 +915,20 @@ pub struct TreeEntry {
   pub path: PathBuf,
   pub is_dir: bool,
   pub is_link: bool,
+  /// Read again with the directory it is in, for sorting
+  metadata: Option<Metadata>,
   pub children: Vec<TreeEntry>,
   expanded: bool,
+  read_state: ReadState,
+}
+
+/// Progress of reading a directory in the background
+#[derive(Clone, Copy, PartialEq)]
+enum ReadState {
+  Done,
+  Loading,
+  /// Still loading after `read_timeout`
+  TimedOut,
 }
 
 /// A line in the FileTree widget.
@@ -226,23 +937,102 @@ pub struct TreeEntryLine {
   pub path: PathBuf,
   pub line: Vec<(String, Style)>,
   pub level: usize,
+  pub is_dir: bool,
+  pub is_expanded: bool,
+  /// The `..` line above the root, standing for its parent directory
+  pub is_parent: bool,
+  /// Size and age, aligned to the right edge when there is room
+  pub columns: Vec<(String, Style)>,
+  /// For each level of indentation, whether more lines of that level follow
+  /// below, so a guide line goes on. Set by `set_guides`
+  pub guides: Vec<bool>,
+}
+
+/// Work out the `guides` of `lines`, going from the bottom up
+fn set_guides(lines: &mut [TreeEntryLine]) {
+  // Whether a line of each level was seen since the last shallower one
+  let mut seen: Vec<bool> = Vec::new();
+  for line in lines.iter_mut().rev() {
+    seen.resize(line.level + 1, false);
+    line.guides = seen[1..].to_vec();
+    seen[line.level] = true;
+  }
 }
 
 impl TreeEntryLine {
-  fn make_line(&self) -> ListItem {
-    ListItem::new(Line::from(
-      iter::once(
-        Span::styled(
-        "  ".repeat(self.level),
-          self.line.first().map(|(_, s)| *s).unwrap_or_default(),
-        )
-      )
+  /// Split up the name so the places `pattern` appears in it have `style`
+  /// patched on
+  fn highlight_matches(&mut self, pattern: &str, style: Style) {
+    let Some((text, base)) = self.line.get(1).cloned() else {
+      return;
+    };
+    // Only the name, without the space separating it from the icon
+    let name = text.strip_prefix(' ').unwrap_or(&text);
+    let offset = text.len() - name.len();
+    let ranges = match_ranges(name, pattern);
+    if ranges.is_empty() {
+      return;
+    }
+    let mut parts = Vec::new();
+    let mut start = 0;
+    for range in ranges {
+      let range = range.start + offset..range.end + offset;
+      if start < range.start {
+        parts.push((text[start..range.start].to_string(), base));
+      }
+      parts.push((text[range.clone()].to_string(), base.patch(style)));
+      start = range.end;
+    }
+    parts.push((text[start..].to_string(), base));
+    self.line.splice(1..2, parts);
+  }
+
+  /// The indentation, with guide lines when `show_guides` is set
+  fn indent(&self, conf: &Config) -> String {
+    if !conf.show_guides {
+      return "  ".repeat(self.level);
+    }
+    let last = self.guides.len().saturating_sub(1);
+    self
+      .guides
+      .iter()
+      .enumerate()
+      .map(|(i, &more)| match (i == last, more) {
+        (false, false) => "  ",
+        (false, true) => "│ ",
+        (true, false) => "└ ",
+        (true, true) => "├ ",
+      })
+      .collect()
+  }
+
+  /// Build the list item for a list `width` columns wide, not counting the
+  /// `number` in front of it, with `extra` patched on top of all styles
+  fn make_line(&self, conf: &Config, extra: Style, width: usize, number: Option<String>) -> ListItem<'_> {
+    let indent_style = match conf.show_guides {
+      true => conf.guide_style,
+      false => self.line.first().map(|(_, s)| *s).unwrap_or_default(),
+    };
+    let number = number.map(|n| Span::styled(n, conf.number_style.patch(extra)));
+    let mut spans: Vec<_> = number
+      .into_iter()
+      .chain(iter::once(Span::styled(self.indent(conf), indent_style.patch(extra))))
       .chain(self.line.iter().map(|(x, s)|
-          Span::styled(x, *s))
+          Span::styled(x, s.patch(extra)))
       )
-      .collect::<Vec<_>>(),
-    ))
-    .style(self.line.last().map(|(_, s)| *s).unwrap_or_default())
+      .collect();
+    let len = |parts: &[(String, Style)]| -> usize {
+      parts.iter().map(|(x, _)| x.chars().count()).sum()
+    };
+    let used = self.level * 2 + len(&self.line);
+    let columns = len(&self.columns);
+    // Leave the columns out rather than cut off the name
+    if !self.columns.is_empty() && used + columns < width {
+      spans.push(Span::raw(" ".repeat(width - used - columns)));
+      spans.extend(self.columns.iter().map(|(x, s)| Span::styled(x, s.patch(extra))));
+    }
+    ListItem::new(Line::from(spans))
+    .style(self.line.last().map(|(_, s)| s.patch(extra)).unwrap_or_default())
   }
 }
 
@@ -253,61 +1043,154 @@ impl TreeEntry {
       .absolutize()
       .map(PathBuf::from)
       .unwrap_or(path);
-    let md = path.metadata();
+    let metadata = path.metadata().ok();
     let is_link = path.as_path().read_link().is_ok();
     TreeEntry {
       path,
-      is_dir: md.map(|m| m.is_dir()).unwrap_or(false),
+      is_dir: metadata.as_ref().is_some_and(|m| m.is_dir()),
       is_link,
+      metadata,
       children: vec![],
       expanded: false,
+      read_state: ReadState::Done,
     }
   }
 
-  fn update(&mut self, expanded: &ExpandedPaths) {
-    self.expanded = expanded.is_expanded(&self.path);
+  /// Read the expanded directories again. `ancestors` are the resolved paths of
+  /// the expanded directories above this one
+  fn update(
+    &mut self,
+    cfg: &Config,
+    expanded: &ExpandedPaths,
+    reader: &mut DirReader,
+    ancestors: &mut Vec<PathBuf>,
+  ) {
+    let real = match expanded.is_expanded(&self.path) {
+      true => self.expandable_path(cfg, ancestors),
+      false => None,
+    };
+    self.expanded = real.is_some();
+    // Collapsed directories aren't read, so they aren't waited for either
+    self.read_state = ReadState::Done;
     if self.expanded {
-      self.read_fs()
+      let ms = cfg.read_timeout.get() as u64;
+      let timeout = (ms > 0).then(|| Duration::from_millis(ms));
+      self.read_state = match reader.read(&self.path, timeout) {
+        DirRead::Done(entries) => {
+          self.set_children(cfg, entries);
+          ReadState::Done
+        }
+        DirRead::Loading => ReadState::Loading,
+        DirRead::TimedOut => ReadState::TimedOut,
+      };
+    }
+    if let Some(real) = real {
+      ancestors.push(real);
     }
     for child in &mut self.children {
-      child.update(expanded)
-    }
-  }
-
-  pub fn read_fs(&mut self) {
-    self.children = std::fs::read_dir(&self.path)
-      .map(|paths| {
-        paths
-          .filter_map(|p| {
-            p.map(|p| p.path())
-              .map(|p| {
-                self
-                  .children
-                  .iter()
-                  .position(|e| e.path == p)
-                  .map(|i| self.children.remove(i))
-                  .unwrap_or_else(|| TreeEntry::new(p))
-              })
-              .ok()
-          })
-          .collect()
+      child.update(cfg, expanded, reader, ancestors)
+    }
+    if self.expanded {
+      ancestors.pop();
+    }
+  }
+
+  /// The resolved path of this directory if it can be expanded below
+  /// `ancestors`. Links are only followed with `follow_symlinks`, and never into
+  /// a directory above them, which would repeat forever
+  fn expandable_path(&self, cfg: &Config, ancestors: &[PathBuf]) -> Option<PathBuf> {
+    // The root is shown even when it's a link
+    if self.is_link && !cfg.follow_symlinks && !ancestors.is_empty() {
+      return None;
+    }
+    let real = self.path.canonicalize().unwrap_or_else(|_| self.path.clone());
+    (!ancestors.contains(&real)).then_some(real)
+  }
+
+  #[cfg(test)]
+  pub fn read_fs(&mut self, cfg: &Config) {
+    self.set_children(cfg, read_entries(&self.path).unwrap_or_default());
+  }
+
+  /// Replace the children with `entries`, keeping the existing entries with
+  /// what was read below them
+  fn set_children(&mut self, cfg: &Config, entries: Vec<TreeEntry>) {
+    self.children = entries
+      .into_iter()
+      .map(|new| {
+        let Some(i) = self.children.iter().position(|e| e.path == new.path) else {
+          return new;
+        };
+        // Keep what was read below it, with what was just read about it
+        TreeEntry {
+          children: std::mem::take(&mut self.children[i].children),
+          expanded: self.children[i].expanded,
+          read_state: self.children[i].read_state,
+          ..new
+        }
       })
-      .unwrap_or_default();
-    self.children.sort_by(|a, b| a.path.cmp(&b.path));
-    self.children.sort_by(|a, b| b.is_dir.cmp(&a.is_dir));
+      .collect();
+    let group = |e: &TreeEntry| match cfg.group_dirs {
+      GroupDirs::First => !e.is_dir,
+      GroupDirs::Last => e.is_dir,
+      GroupDirs::Mixed => false,
+    };
+    self.children.sort_by(|a, b| {
+      // Within a group, both are directories or both are files
+      let sort = match cfg.group_dirs {
+        GroupDirs::Mixed => cfg.sort,
+        _ if a.is_dir => cfg.dir_sort.or(cfg.sort),
+        _ => cfg.file_sort.or(cfg.sort),
+      };
+      let case_sensitive = cfg.sort_case_sensitive;
+      let order = a
+        .sort_key_cmp(b, sort, case_sensitive)
+        .then_with(|| a.sort_key_cmp(b, SortBy::Name, case_sensitive))
+        .then_with(|| a.path.cmp(&b.path));
+      let order = if cfg.sort_reverse {
+        order.reverse()
+      } else {
+        order
+      };
+      group(a).cmp(&group(b)).then(order)
+    });
+  }
+
+  /// Compare by `sort`, leaving ties to the caller
+  fn sort_key_cmp(&self, other: &TreeEntry, sort: SortBy, case_sensitive: bool) -> Ordering {
+    let size = |e: &TreeEntry| e.metadata.as_ref().map(|m| m.len());
+    let mtime = |e: &TreeEntry| e.metadata.as_ref().and_then(|m| m.modified().ok());
+    let name = |e: &TreeEntry| e.path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+    match sort {
+      SortBy::Name if case_sensitive => name(self).cmp(&name(other)),
+      SortBy::Name => name(self).to_lowercase().cmp(&name(other).to_lowercase()),
+      SortBy::Natural => natural_cmp(&name(self), &name(other), case_sensitive),
+      SortBy::Size => size(self).cmp(&size(other)),
+      SortBy::Mtime => mtime(self).cmp(&mtime(other)),
+      SortBy::Extension => self.path.extension().cmp(&other.path.extension()),
+    }
   }
 
-  fn should_show_item(&self, conf: &Config, level: usize) -> bool {
+  fn should_show_item(
+    &self,
+    conf: &Config,
+    hidden_in: &HashSet<PathBuf>,
+    ignored: &Ignores,
+    level: usize,
+  ) -> bool {
     // Always show root dir
     if level == 0 {
       return true;
     }
-    let hidden = !conf.show_hidden
+    if ignored.is_ignored(&self.path, self.is_dir) || (conf.dirs_only && !self.is_dir) {
+      return false;
+    }
+    let show_hidden = conf.show_hidden || self.path.parent().is_some_and(|p| hidden_in.contains(p));
+    let hidden = !show_hidden
       && self
         .path
         .file_name()
-        .and_then(|s| s.to_str())
-        .map(|x| x.starts_with('.'))
+        .map(|x| x.to_string_lossy().starts_with('.'))
         .unwrap_or(false);
     if hidden {
       return false;
@@ -334,72 +1217,159 @@ impl TreeEntry {
     }
   }
 
-  pub fn build_line(&self, conf: &Config, level: usize) -> Option<TreeEntryLine> {
-    if !self.should_show_item(conf, level) {
+  pub fn build_line(
+    &self,
+    conf: &Config,
+    hidden_in: &HashSet<PathBuf>,
+    ignored: &Ignores,
+    level: usize,
+  ) -> Option<TreeEntryLine> {
+    if !self.should_show_item(conf, hidden_in, ignored, level) {
       return None;
     }
-    self.path.file_name().and_then(|s| s.to_str()).map(|name| {
-      let prefix = {
-        let icon = self.icon(conf);
-        let arrow = if self.is_dir {
-          if self.expanded {
-            '▾'
-          } else {
-            '▸'
-          }
+    let name = match (level, conf.show_root) {
+      (0, ShowRoot::Absolute) => self.path.clone().into_os_string(),
+      (0, ShowRoot::Abbreviated) => match std::env::var_os("HOME") {
+        Some(home) => abbreviate_home(&self.path, Path::new(&home)).into_os_string(),
+        None => self.path.clone().into_os_string(),
+      },
+      _ => self.path.file_name()?.to_os_string(),
+    };
+    let prefix = {
+      let icon = self.icon(conf);
+      let arrow = if self.is_dir {
+        if self.read_state != ReadState::Done {
+          spinner_frame()
+        } else if self.expanded {
+          '▾'
         } else {
-          ' '
-        };
-        format!("{arrow} {icon}")
-      };
-      let mainstyle = if self.is_dir {
-        conf.dir_name_style
+          '▸'
+        }
       } else {
-        conf.file_name_style
+        ' '
       };
-      let mainstyle = if self.is_link {
-        mainstyle.patch(conf.link_style)
-      } else {
-        mainstyle
-      };
-      TreeEntryLine {
-        path: self.path.clone(),
-        line: vec![
-          (prefix, conf.icon_style),
-          (" ".to_string() + name, mainstyle),
-        ],
-        level,
-      }
+      format!("{arrow} {icon}")
+    };
+    let mainstyle = if self.is_dir {
+      conf.dir_name_style
+    } else {
+      conf.file_name_style
+    };
+    let mainstyle = if self.is_link {
+      mainstyle.patch(conf.link_style)
+    } else {
+      mainstyle
+    };
+    let mut line = vec![
+      (prefix, conf.icon_style),
+      (" ".to_string() + &name.to_string_lossy(), mainstyle),
+    ];
+    if name.to_str().is_none() {
+      line.push((" (non-UTF-8)".to_string(), conf.icon_style));
+    }
+    if self.read_state == ReadState::TimedOut {
+      line.push((" (not responding)".to_string(), conf.icon_style));
+    }
+    Some(TreeEntryLine {
+      path: self.path.clone(),
+      line,
+      level,
+      is_dir: self.is_dir,
+      is_expanded: self.expanded,
+      is_parent: false,
+      columns: self.columns(conf),
+      guides: Vec::new(),
+    })
+  }
+
+  /// The `show_size` and `show_mtime` columns, padded so they line up
+  fn columns(&self, conf: &Config) -> Vec<(String, Style)> {
+    let md = self.metadata.as_ref();
+    let mut columns = Vec::new();
+    if conf.show_size {
+      // The size of a directory says nothing about its contents
+      let size = md.filter(|m| !m.is_dir()).map(|m| human_size(m.len()));
+      columns.push((format!(" {:>5}", size.unwrap_or_default()), conf.icon_style));
+    }
+    if conf.show_mtime {
+      let age = md
+        .and_then(|m| m.modified().ok())
+        .map(|t| relative_time(t.elapsed().unwrap_or_default()));
+      columns.push((format!(" {:>4}", age.unwrap_or_default()), conf.icon_style));
+    }
+    columns
+  }
+
+  /// The `..` line for the parent directory, if enabled and there is one
+  fn parent_line(&self, conf: &Config) -> Option<TreeEntryLine> {
+    if !conf.show_parent_entry {
+      return None;
+    }
+    Some(TreeEntryLine {
+      path: self.path.parent()?.to_path_buf(),
+      line: vec![
+        ("  ".to_string(), conf.icon_style),
+        (" ..".to_string(), conf.dir_name_style),
+      ],
+      level: 0,
+      is_dir: true,
+      is_expanded: false,
+      is_parent: true,
+      columns: Vec::new(),
+      guides: Vec::new(),
     })
   }
 
   pub fn build_lines_rec<'a>(
     &'a self,
     conf: &'a Config,
+    hidden_in: &'a HashSet<PathBuf>,
+    ignored: &'a Ignores,
     level: usize,
   ) -> Box<dyn Iterator<Item = TreeEntryLine> + 'a> {
-    let line = self.build_line(conf, level);
-    if line.is_some() && self.expanded {
-      Box::new(
-        line.into_iter().chain(
+    let parent = if level == 0 { self.parent_line(conf) } else { None };
+    if level == 0 && conf.show_root == ShowRoot::Hidden {
+      // The children take the place of the root at the top level
+      return Box::new(
+        parent.into_iter().chain(
           self
             .children
             .iter()
-            .flat_map(move |n| n.build_lines_rec(conf, level + 1)),
+            .flat_map(move |n| n.build_lines_rec(conf, hidden_in, ignored, 1))
+            .map(|line| TreeEntryLine {
+              level: line.level - 1,
+              ..line
+            }),
         ),
+      );
+    }
+    let line = self.build_line(conf, hidden_in, ignored, level);
+    let expanded = line.is_some() && self.expanded;
+    let children: Box<dyn Iterator<Item = TreeEntryLine> + 'a> = if expanded {
+      Box::new(
+        self
+          .children
+          .iter()
+          .flat_map(move |n| n.build_lines_rec(conf, hidden_in, ignored, level + 1)),
       )
     } else {
-      Box::new(line.into_iter())
-    }
+      Box::new(iter::empty())
+    };
+    Box::new(parent.into_iter().chain(line).chain(children))
   }
 
   /// Find the tree entry corresponding to a `TreeEntryLine`
   pub fn find(&self, e: &TreeEntryLine) -> Option<&TreeEntry> {
-    if e.path == self.path {
+    self.find_path(&e.path)
+  }
+
+  /// Find the tree entry for `path`
+  pub fn find_path(&self, path: &Path) -> Option<&TreeEntry> {
+    if path == self.path {
       return Some(self);
     }
     for child in &self.children {
-      let res = child.find(e);
+      let res = child.find_path(path);
       if res.is_some() {
         return res;
       }
@@ -420,9 +1390,683 @@ impl TreeEntry {
     }
     None
   }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::config::{Config, GroupDirs, GroupSort, ShowRoot, SortBy};
+  use crate::file_tree::{line_number, DirRead, DirReader, FileTreeState, TreeEntry};
+  use crate::git::{GitStatus, Ignores};
+  use std::collections::HashSet;
+  use ratatui::style::{Color, Style};
+  use std::path::{Path, PathBuf};
+  use std::time::Duration;
+
+  /// Update until `n` lines are shown, as directories are read in the background
+  fn load(state: &mut FileTreeState, cfg: &Config, n: usize) {
+    for _ in 0..100 {
+      state.update(cfg);
+      if state.lines.items.len() == n {
+        return;
+      }
+      std::thread::sleep(Duration::from_millis(10));
+    }
+    panic!("expected {} lines, got {}", n, state.lines.items.len());
+  }
+
+  fn child_names(entry: &TreeEntry) -> Vec<String> {
+    entry
+      .children
+      .iter()
+      .map(|c| c.path.file_name().unwrap().to_string_lossy().to_string())
+      .collect()
+  }
+
+  fn mixed_dir(path: &Path) {
+    std::fs::create_dir(path.join("b_dir")).unwrap();
+    std::fs::create_dir(path.join("d_dir")).unwrap();
+    std::fs::write(path.join("a_file"), "").unwrap();
+    std::fs::write(path.join("c_file"), "").unwrap();
+  }
+
+  #[test]
+  fn group_dirs() {
+    let dir = tempfile::tempdir().unwrap();
+    mixed_dir(dir.path());
+    let mut entry = TreeEntry::new(dir.path().to_path_buf());
+    let cfg = |group_dirs| Config {
+      group_dirs,
+      ..Default::default()
+    };
+
+    entry.read_fs(&cfg(GroupDirs::First));
+    assert_eq!(child_names(&entry), ["b_dir", "d_dir", "a_file", "c_file"]);
+
+    entry.read_fs(&cfg(GroupDirs::Last));
+    assert_eq!(child_names(&entry), ["a_file", "c_file", "b_dir", "d_dir"]);
+
+    entry.read_fs(&cfg(GroupDirs::Mixed));
+    assert_eq!(child_names(&entry), ["a_file", "b_dir", "c_file", "d_dir"]);
+  }
+
+  #[test]
+  fn sort_options() {
+    let dir = tempfile::tempdir().unwrap();
+    let base = dir.path();
+    let now = std::time::SystemTime::now();
+    for (name, size, age) in [("a.txt", 3, 1), ("b.rs", 1, 3), ("c.md", 2, 2)] {
+      let file = std::fs::File::create(base.join(name)).unwrap();
+      file.set_len(size).unwrap();
+      file.set_modified(now - Duration::from_secs(age * 60)).unwrap();
+    }
+    std::fs::create_dir(base.join("dir")).unwrap();
+    let mut entry = TreeEntry::new(base.to_path_buf());
+    let mut sorted = |sort, sort_reverse| {
+      entry.read_fs(&Config {
+        sort,
+        sort_reverse,
+        ..Default::default()
+      });
+      child_names(&entry)
+    };
+
+    assert_eq!(sorted(SortBy::Name, false), ["dir", "a.txt", "b.rs", "c.md"]);
+    assert_eq!(sorted(SortBy::Name, true), ["dir", "c.md", "b.rs", "a.txt"]);
+    assert_eq!(sorted(SortBy::Size, false), ["dir", "b.rs", "c.md", "a.txt"]);
+    assert_eq!(sorted(SortBy::Mtime, false), ["dir", "b.rs", "c.md", "a.txt"]);
+    assert_eq!(sorted(SortBy::Mtime, true), ["dir", "a.txt", "c.md", "b.rs"]);
+    assert_eq!(sorted(SortBy::Extension, false), ["dir", "c.md", "b.rs", "a.txt"]);
+
+    // Entries read before are sorted by what they are now
+    std::fs::File::options().write(true).open(base.join("b.rs")).unwrap().set_len(9).unwrap();
+    assert_eq!(sorted(SortBy::Size, false), ["dir", "c.md", "a.txt", "b.rs"]);
+
+    for name in ["B.txt", "file2", "file10"] {
+      std::fs::File::create(base.join(name)).unwrap();
+    }
+    let mut sorted = |sort, sort_case_sensitive| {
+      entry.read_fs(&Config {
+        sort,
+        sort_case_sensitive,
+        ..Default::default()
+      });
+      child_names(&entry)
+    };
+    let names = ["dir", "a.txt", "b.rs", "B.txt", "c.md", "file10", "file2"];
+    assert_eq!(sorted(SortBy::Name, false), names);
+    let names = ["dir", "B.txt", "a.txt", "b.rs", "c.md", "file10", "file2"];
+    assert_eq!(sorted(SortBy::Name, true), names);
+    let names = ["dir", "a.txt", "b.rs", "B.txt", "c.md", "file2", "file10"];
+    assert_eq!(sorted(SortBy::Natural, false), names);
+  }
+
+  #[test]
+  fn separate_dir_and_file_sort() {
+    let dir = tempfile::tempdir().unwrap();
+    let base = dir.path();
+    let now = std::time::SystemTime::now();
+    for (name, age) in [("a.txt", 1), ("b.txt", 3), ("c.txt", 2)] {
+      let file = std::fs::File::create(base.join(name)).unwrap();
+      file.set_modified(now - Duration::from_secs(age * 60)).unwrap();
+    }
+    for name in ["x_dir", "y_dir", "z_dir"] {
+      std::fs::create_dir(base.join(name)).unwrap();
+    }
+    let mut entry = TreeEntry::new(base.to_path_buf());
+    let mut sorted = |dir_sort, file_sort, group_dirs| {
+      entry.read_fs(&Config {
+        sort: SortBy::Name,
+        dir_sort,
+        file_sort,
+        group_dirs,
+        ..Default::default()
+      });
+      child_names(&entry)
+    };
+
+    // Files by mtime, while directories keep the global name order
+    let names = sorted(GroupSort::Sort, GroupSort::Mtime, GroupDirs::First);
+    assert_eq!(names[..3], ["x_dir", "y_dir", "z_dir"]);
+    assert_eq!(names[3..], ["b.txt", "c.txt", "a.txt"]);
+    let names = sorted(GroupSort::Name, GroupSort::Mtime, GroupDirs::Last);
+    assert_eq!(names[..3], ["b.txt", "c.txt", "a.txt"]);
+    assert_eq!(names[3..], ["x_dir", "y_dir", "z_dir"]);
+    // Without groups, everything is sorted by `sort`
+    let names = sorted(GroupSort::Sort, GroupSort::Mtime, GroupDirs::Mixed);
+    assert_eq!(names, ["a.txt", "b.txt", "c.txt", "x_dir", "y_dir", "z_dir"]);
+  }
+
+  #[test]
+  fn keep_selection_after_external_rename() {
+    let dir = tempfile::tempdir().unwrap();
+    let base = dir.path();
+    for name in ["a", "b", "c"] {
+      std::fs::write(base.join(name), "").unwrap();
+    }
+    let cfg = Config::default();
+    let mut state = FileTreeState::new(base.to_path_buf());
+    load(&mut state, &cfg, 4);
+    assert!(state.select_path(&base.join("b")));
+
+    std::fs::rename(base.join("b"), base.join("z")).unwrap();
+    // Wait for the new listing to come in
+    for _ in 0..100 {
+      state.update(&cfg);
+      if state.lines.items.iter().any(|l| l.path == base.join("z")) {
+        break;
+      }
+      std::thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(state.entry().path, base.join("c"));
+  }
+
+  #[test]
+  fn line_numbers() {
+    let numbers = |number, relativenumber| {
+      let cfg = Config {
+        number,
+        relativenumber,
+        ..Default::default()
+      };
+      (0..4).map(|i| line_number(&cfg, i, 1)).collect::<Vec<_>>()
+    };
+    assert_eq!(numbers(false, false), [None; 4]);
+    assert_eq!(numbers(true, false), [Some(1), Some(2), Some(3), Some(4)]);
+    assert_eq!(numbers(false, true), [Some(1), Some(0), Some(1), Some(2)]);
+    assert_eq!(numbers(true, true), [Some(1), Some(2), Some(1), Some(2)]);
+  }
+
+  #[test]
+  fn background_reads() {
+    let dir = tempfile::tempdir().unwrap();
+    let base = dir.path();
+    for name in ["a", "b"] {
+      std::fs::write(base.join(name), name).unwrap();
+    }
+    let mut reader = DirReader::default();
+    let timeout = Some(Duration::from_secs(10));
+    let mut entries = None;
+    for _ in 0..100 {
+      match reader.read(base, timeout) {
+        DirRead::Done(e) => {
+          entries = Some(e);
+          break;
+        }
+        // Asking again doesn't start another read
+        _ => assert_eq!(reader.pending.len(), 1),
+      }
+      std::thread::sleep(Duration::from_millis(10));
+    }
+    let mut entries = entries.expect("no listing");
+    entries.sort_by(|a, b| a.path.cmp(&b.path));
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0].path, base.join("a"));
+    assert_eq!(entries[1].metadata.as_ref().map(|m| m.len()), Some(1));
+    assert!(reader.pending.is_empty());
+  }
+
+  #[test]
+  fn dirs_only() {
+    let dir = tempfile::tempdir().unwrap();
+    let base = dir.path();
+    std::fs::create_dir_all(base.join("sub/inner")).unwrap();
+    for name in ["a", "sub/b", "sub/c"] {
+      std::fs::write(base.join(name), "").unwrap();
+    }
+    let mut cfg = Config::default();
+    let mut state = FileTreeState::new(base.to_path_buf());
+    state.expand(&base.join("sub"));
+    load(&mut state, &cfg, 6);
+    assert!(state.select_path(&base.join("sub/c")));
+
+    cfg.dirs_only = true;
+    state.refresh(&cfg);
+    let paths: Vec<_> = state.lines.items.iter().map(|l| l.path.clone()).collect();
+    assert_eq!(paths, [base.to_path_buf(), base.join("sub"), base.join("sub/inner")]);
+    assert_eq!(state.entry().path, base.join("sub/inner"));
+  }
+
+  #[test]
+  fn expand_and_collapse_all() {
+    let dir = tempfile::tempdir().unwrap();
+    let base = dir.path();
+    std::fs::create_dir_all(base.join("a/b/c/d")).unwrap();
+    std::fs::create_dir_all(base.join("a/.hidden/x")).unwrap();
+    std::fs::create_dir(base.join("e")).unwrap();
+    std::os::unix::fs::symlink(base.join("a"), base.join("a/b/link")).unwrap();
+    let mut cfg = Config::default();
+    cfg.set_opt("max_expand_depth", "2").unwrap();
+    let mut state = FileTreeState::new(base.to_path_buf());
+
+    state.expand_all(&cfg, &base.join("a"), 3);
+    for expanded in ["a", "a/b", "a/b/c"] {
+      assert!(state.is_expanded(&base.join(expanded)), "{}", expanded);
+    }
+    for collapsed in ["a/b/c/d", "a/.hidden", "a/b/link", "e"] {
+      assert!(!state.is_expanded(&base.join(collapsed)), "{}", collapsed);
+    }
+
+    // No deeper than max_expand_depth
+    state.expand_level(&cfg, &base.join("a"), 3);
+    assert!(state.is_expanded(&base.join("a/b")));
+    assert!(!state.is_expanded(&base.join("a/b/c")));
+
+    state.expand(&base.join("e"));
+    state.collapse_all(&base.join("a"));
+    assert!(!state.is_expanded(&base.join("a")));
+    assert!(!state.is_expanded(&base.join("a/b")));
+    assert!(state.is_expanded(&base.join("e")));
+    assert!(state.is_expanded(base));
+  }
+
+  #[test]
+  fn git_status_in_background() {
+    let dir = tempfile::tempdir().unwrap();
+    let base = dir.path();
+    let init = std::process::Command::new("git").args(["init", "-q"]).current_dir(base).status();
+    if !init.is_ok_and(|s| s.success()) {
+      // No git here
+      return;
+    }
+    std::fs::write(base.join("new"), "").unwrap();
+    let cfg = Config {
+      git_status: true,
+      ..Default::default()
+    };
+    let mut state = FileTreeState::new(base.to_path_buf());
+    state.update(&cfg);
+    let start = std::time::Instant::now();
+    while state.git_status.get(&base.join("new")).is_none() {
+      assert!(start.elapsed() < Duration::from_secs(5), "git status did not finish");
+      std::thread::sleep(Duration::from_millis(10));
+      state.refresh(&cfg);
+    }
+    assert_eq!(state.git_status.get(&base.join("new")), Some(GitStatus::Untracked));
+  }
+
+  #[test]
+  fn symlink_cycles() {
+    let dir = tempfile::tempdir().unwrap();
+    let base = dir.path();
+    std::fs::create_dir_all(base.join("a/b/c")).unwrap();
+    std::os::unix::fs::symlink(base.join("a"), base.join("a/b/up")).unwrap();
+    std::os::unix::fs::symlink(base.join("a/b"), base.join("across")).unwrap();
+    let mut cfg = Config::default();
+    let mut state = FileTreeState::new(base.to_path_buf());
+    for path in ["a", "a/b", "a/b/up", "across"] {
+      state.expand(&base.join(path));
+    }
+    let shown = |state: &FileTreeState, path: &str| {
+      state.lines.items.iter().any(|l| l.path == base.join(path))
+    };
+
+    state.update(&cfg);
+    assert!(shown(&state, "a/b/up"));
+    assert!(!shown(&state, "a/b/up/b"));
+    assert!(!shown(&state, "across/c"));
+
+    // Links are followed, except into a directory they are in
+    cfg.follow_symlinks = true;
+    state.update(&cfg);
+    assert!(!shown(&state, "a/b/up/b"));
+    assert!(shown(&state, "across/c"));
+  }
+
+  #[test]
+  fn search_matches() {
+    // The temporary directory's random name could match
+    let dir = tempfile::tempdir().unwrap();
+    let base = &dir.path().join("fruit");
+    std::fs::create_dir(base).unwrap();
+    for name in ["apple", "banana", "cherry"] {
+      std::fs::write(base.join(name), "").unwrap();
+    }
+    let cfg = Config::default();
+    let mut state = FileTreeState::new(base.to_path_buf());
+    load(&mut state, &cfg, 4);
+
+    // Forward and back, wrapping around
+    assert!(!state.select_match("A", true));
+    assert!(state.select_match("a", true));
+    assert_eq!(state.entry().path, base.join("apple"));
+    assert!(state.select_match("a", true));
+    assert_eq!(state.entry().path, base.join("banana"));
+    assert!(state.select_match("a", true));
+    assert_eq!(state.entry().path, base.join("apple"));
+    assert!(state.select_match("a", false));
+    assert_eq!(state.entry().path, base.join("banana"));
+    assert!(!state.select_match("kiwi", true));
+
+    state.search = Some("an".to_string());
+    state.update(&cfg);
+    let line = state.line().unwrap();
+    let parts: Vec<_> = line.line[1..].iter().map(|(text, _)| text.as_str()).collect();
+    assert_eq!(parts, [" b", "an", "an", "a"]);
+    assert_eq!(line.line[2].1, cfg.file_name_style.patch(cfg.search_match_style));
+
+    // The space in front of the name isn't part of it
+    state.search = Some(" b".to_string());
+    state.update(&cfg);
+    assert_eq!(state.line().unwrap().line[1].0, " banana");
+    state.search = Some("b".to_string());
+    state.update(&cfg);
+    let line = state.line().unwrap();
+    let parts: Vec<_> = line.line[1..].iter().map(|(text, _)| text.as_str()).collect();
+    assert_eq!(parts, [" ", "b", "anana"]);
+  }
+
+  #[test]
+  fn guides() {
+    let dir = tempfile::tempdir().unwrap();
+    let base = dir.path();
+    std::fs::create_dir_all(base.join("a/b")).unwrap();
+    std::fs::write(base.join("a/b/f"), "").unwrap();
+    std::fs::write(base.join("a/g"), "").unwrap();
+    std::fs::write(base.join("h"), "").unwrap();
+    std::fs::write(base.join(".hidden"), "").unwrap();
+    let cfg = Config {
+      show_guides: true,
+      ..Config::default()
+    };
+    let mut state = FileTreeState::new(base.to_path_buf());
+    state.expand(&base.join("a"));
+    state.expand(&base.join("a/b"));
+    load(&mut state, &cfg, 6);
+    let indents: Vec<_> = state.lines.items.iter().map(|l| l.indent(&cfg)).collect();
+    // The hidden file after h doesn't count as a sibling
+    assert_eq!(indents, ["", "├ ", "│ ├ ", "│ │ └ ", "│ └ ", "└ "]);
+  }
+
+  #[test]
+  fn drill_down() {
+    let dir = tempfile::tempdir().unwrap();
+    let base = dir.path();
+    std::fs::create_dir_all(base.join("d/x")).unwrap();
+    std::fs::create_dir(base.join("e")).unwrap();
+    std::fs::write(base.join("f"), "").unwrap();
+    let cfg = Config::default();
+    let mut state = FileTreeState::new(base.to_path_buf());
+    load(&mut state, &cfg, 4);
+
+    // Collapsed directory: expand
+    state.select_path(&base.join("d"));
+    state.drill_down();
+    load(&mut state, &cfg, 5);
+    assert_eq!(state.entry().path, base.join("d"));
+    // Expanded directory: move to the first child
+    state.drill_down();
+    assert_eq!(state.entry().path, base.join("d/x"));
+
+    // Expanded empty directory: stay
+    state.select_path(&base.join("e"));
+    state.drill_down();
+    state.update(&cfg);
+    state.drill_down();
+    assert_eq!(state.entry().path, base.join("e"));
+
+    // File: stay
+    state.select_path(&base.join("f"));
+    state.drill_down();
+    assert_eq!(state.entry().path, base.join("f"));
+  }
+
+  #[test]
+  fn parent_entry() {
+    let dir = tempfile::tempdir().unwrap();
+    let base = dir.path().join("root");
+    std::fs::create_dir_all(base.join("d")).unwrap();
+    let cfg = Config {
+      show_parent_entry: true,
+      ..Default::default()
+    };
+    let mut state = FileTreeState::new(base.clone());
+    load(&mut state, &cfg, 3);
+    let parent = &state.lines.items[0];
+    assert!(parent.is_parent);
+    assert_eq!((parent.path.as_path(), parent.level), (dir.path(), 0));
+    assert_eq!(state.lines.items[1].path, base);
+
+    state.mark_all();
+    assert_eq!(state.selected_paths.len(), 1);
+    // Going down from `..` doesn't expand its directory
+    state.select_nth(0);
+    state.drill_down();
+    assert!(!state.is_expanded(dir.path()));
+
+    // Also shown without a root line, but not for a root without a parent
+    let cfg = Config {
+      show_root: ShowRoot::Hidden,
+      ..cfg
+    };
+    state.update(&cfg);
+    assert!(state.lines.items[0].is_parent);
+    assert_eq!(state.lines.items[1].path, base.join("d"));
+    let mut state = FileTreeState::new(PathBuf::from("/"));
+    state.update(&cfg);
+    assert!(state.lines.items.iter().all(|l| !l.is_parent));
+  }
+
+  #[test]
+  fn active_file_style() {
+    let dir = tempfile::tempdir().unwrap();
+    let base = dir.path();
+    std::fs::write(base.join("a"), "").unwrap();
+    std::fs::write(base.join("b"), "").unwrap();
+    let cfg = Config {
+      active_file_style: Style::default().fg(Color::Red),
+      ..Default::default()
+    };
+    let mut state = FileTreeState::new(base.to_path_buf());
+    state.active_path = Some(base.join("b"));
+    load(&mut state, &cfg, 3);
+    let name_style = |i: usize| state.lines.items[i].line[1].1;
+    assert_eq!(name_style(1), Style::default());
+    assert_eq!(name_style(2), Style::default().fg(Color::Red));
+  }
+
+  #[test]
+  fn hidden_root() {
+    let dir = tempfile::tempdir().unwrap();
+    let base = dir.path();
+    std::fs::create_dir_all(base.join("d/x")).unwrap();
+    std::fs::write(base.join("f"), "").unwrap();
+    let cfg = Config {
+      show_root: ShowRoot::Hidden,
+      ..Default::default()
+    };
+    let mut state = FileTreeState::new(base.to_path_buf());
+    state.collapse(base);
+    load(&mut state, &cfg, 2);
+    let levels: Vec<_> = state.lines.items.iter().map(|l| (l.path.clone(), l.level)).collect();
+    assert_eq!(levels, [(base.join("d"), 0), (base.join("f"), 0)]);
+
+    state.expand(&base.join("d"));
+    load(&mut state, &cfg, 3);
+    assert_eq!(state.lines.items[1].level, 1);
+    // Up from a child selects its directory, but there is nothing above the top level
+    state.select_path(&base.join("d/x"));
+    state.select_up();
+    assert_eq!(state.entry().path, base.join("d"));
+    state.select_path(&base.join("f"));
+    state.select_up();
+    assert_eq!(state.entry().path, base.join("f"));
+
+    state.mark_all();
+    assert_eq!(state.selected_paths.len(), 3);
+
+    // An empty root leaves no lines, and nothing selected to move from
+    let empty = tempfile::tempdir().unwrap();
+    let mut state = FileTreeState::new(empty.path().to_path_buf());
+    load(&mut state, &cfg, 0);
+    assert!(state.line().is_none());
+    state.select_next();
+    state.select_prev();
+    assert!(!state.jump_sibling('a'));
+    state.select_top_ancestor();
+    state.select_prev_dir();
+    assert!(state.line().is_none());
+    assert_eq!(state.entry().path, empty.path().to_path_buf());
+  }
+
+  #[test]
+  fn show_hidden_in_dir() {
+    let dir = tempfile::tempdir().unwrap();
+    let base = dir.path();
+    std::fs::create_dir(base.join("d")).unwrap();
+    std::fs::write(base.join(".top"), "").unwrap();
+    std::fs::write(base.join("d/.inner"), "").unwrap();
+    let cfg = Config::default();
+    let mut state = FileTreeState::new(base.to_path_buf());
+    state.expand(&base.join("d"));
+    load(&mut state, &cfg, 2);
+
+    state.toggle_hidden_in(&base.join("d"));
+    load(&mut state, &cfg, 3);
+    let paths: Vec<_> = state.lines.items.iter().map(|l| l.path.clone()).collect();
+    assert_eq!(paths, [base.to_path_buf(), base.join("d"), base.join("d/.inner")]);
+
+    state.toggle_hidden_in(&base.join("d"));
+    load(&mut state, &cfg, 2);
+  }
+
+  #[test]
+  fn dir_motions() {
+    let dir = tempfile::tempdir().unwrap();
+    let base = dir.path();
+    std::fs::create_dir_all(base.join("a/b")).unwrap();
+    for name in ["a/f1", "a/f2", "f3"] {
+      std::fs::write(base.join(name), "").unwrap();
+    }
+    let cfg = Config {
+      group_dirs: GroupDirs::Last,
+      ..Default::default()
+    };
+    let mut state = FileTreeState::new(base.to_path_buf());
+    state.expand(&base.join("a"));
+    // root, f3, a, a/f1, a/f2, a/b
+    load(&mut state, &cfg, 6);
+
+    state.select_path(&base.join("f3"));
+    state.select_next_dir();
+    assert_eq!(state.entry().path, base.join("a"));
+    state.select_next_dir();
+    assert_eq!(state.entry().path, base.join("a/b"));
+    // Stays on the last directory
+    state.select_next_dir();
+    assert_eq!(state.entry().path, base.join("a/b"));
+    state.select_prev_dir();
+    assert_eq!(state.entry().path, base.join("a"));
+    state.select_prev_dir();
+    assert_eq!(state.entry().path, base.to_path_buf());
+  }
+
+  #[test]
+  fn top_ancestor() {
+    let dir = tempfile::tempdir().unwrap();
+    let base = dir.path();
+    std::fs::create_dir_all(base.join("a/b/c")).unwrap();
+    std::fs::write(base.join("a/b/c/f"), "").unwrap();
+    std::fs::write(base.join("a/x"), "").unwrap();
+    let cfg = Config::default();
+    let mut state = FileTreeState::new(base.to_path_buf());
+    for d in ["a", "a/b", "a/b/c"] {
+      state.expand(&base.join(d));
+    }
+    // root, a, a/b, a/b/c, a/b/c/f, a/x
+    load(&mut state, &cfg, 6);
+
+    state.select_path(&base.join("a/b/c/f"));
+    state.select_top_ancestor();
+    assert_eq!(state.entry().path, base.join("a"));
+    state.select_path(&base.join("a/x"));
+    state.select_top_ancestor();
+    assert_eq!(state.entry().path, base.join("a"));
+    // Already at the top, or on the root
+    state.select_top_ancestor();
+    assert_eq!(state.entry().path, base.join("a"));
+    state.select_nth(0);
+    state.select_top_ancestor();
+    assert_eq!(state.entry().path, base.to_path_buf());
+
+    // Without a root line, the top level is the first one
+    let cfg = Config {
+      show_root: ShowRoot::Hidden,
+      ..cfg
+    };
+    state.update(&cfg);
+    state.select_path(&base.join("a/b/c"));
+    state.select_top_ancestor();
+    assert_eq!(state.entry().path, base.join("a"));
+    // From an index past the lines there is no ancestor to look for, and the
+    // previous directory is the last one
+    state.lines.select_index(10);
+    state.select_top_ancestor();
+    state.select_prev_dir();
+    assert_eq!(state.entry().path, base.join("a/b/c"));
+  }
+
+  #[test]
+  fn known_and_closest() {
+    let dir = tempfile::tempdir().unwrap();
+    let base = dir.path();
+    std::fs::create_dir_all(base.join("a/b")).unwrap();
+    std::fs::write(base.join("a/b/f"), "").unwrap();
+    std::fs::write(base.join(".hidden"), "").unwrap();
+    let cfg = Config::default();
+    let mut state = FileTreeState::new(base.to_path_buf());
+    state.expand(&base.join("a"));
+    state.expand(&base.join("a/b"));
+    load(&mut state, &cfg, 4);
+    // Read once, then collapsed again
+    state.collapse(&base.join("a"));
+    state.update(&cfg);
+
+    let known = state.known_paths(&cfg);
+    assert_eq!(known, [base.join("a"), base.join("a/b"), base.join("a/b/f")]);
+    state.select_closest(&base.join("a/b/f"));
+    assert_eq!(state.entry().path, base.join("a"));
+    state.select_closest(base);
+    assert_eq!(state.entry().path, base.to_path_buf());
+  }
+
+  #[test]
+  fn jump_sibling() {
+    let dir = tempfile::tempdir().unwrap();
+    let base = dir.path();
+    std::fs::create_dir(base.join("d")).unwrap();
+    for name in ["Apple", "avocado", "banana", "d/almond"] {
+      std::fs::write(base.join(name), "").unwrap();
+    }
+    let cfg = Config::default();
+    let mut state = FileTreeState::new(base.to_path_buf());
+    state.expand(&base.join("d"));
+    load(&mut state, &cfg, 6);
+
+    state.select_path(&base.join("Apple"));
+    assert!(state.jump_sibling('a'));
+    assert_eq!(state.entry().path, base.join("avocado"));
+    // Wraps around, skipping d/almond in another directory
+    assert!(state.jump_sibling('A'));
+    assert_eq!(state.entry().path, base.join("Apple"));
+    assert!(state.jump_sibling('b'));
+    assert_eq!(state.entry().path, base.join("banana"));
+    assert!(!state.jump_sibling('z'));
+    assert_eq!(state.entry().path, base.join("banana"));
+  }
 
-  /// Get the cached variable of whether this entry is expanded.
-  pub fn is_expanded(&self) -> bool {
-    self.expanded
+  #[cfg(unix)]
+  #[test]
+  fn non_utf8_name() {
+    use std::os::unix::ffi::OsStrExt;
+    let dir = tempfile::tempdir().unwrap();
+    let name = std::ffi::OsStr::from_bytes(b"bad\xffname");
+    std::fs::write(dir.path().join(name), "").unwrap();
+    let mut entry = TreeEntry::new(dir.path().to_path_buf());
+    let cfg = Config::default();
+    entry.read_fs(&cfg);
+    let line = entry.children[0].build_line(&cfg, &HashSet::new(), &Ignores::default(), 1).unwrap();
+    assert_eq!(line.path, dir.path().join(name));
+    let text: String = line.line.iter().map(|(s, _)| s.as_str()).collect();
+    assert!(text.contains("bad\u{fffd}name (non-UTF-8)"));
   }
 }
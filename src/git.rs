@@ -0,0 +1,316 @@
+use crate::config::Config;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::Match;
+use ratatui::style::Style;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Run git in `dir`, returning its output
+fn git(dir: &Path, args: &[&str]) -> Result<String, String> {
+  let output = Command::new("git")
+    .args(args)
+    .current_dir(dir)
+    .output()
+    .map_err(|e| e.to_string())?;
+  if !output.status.success() {
+    return Err(String::from_utf8_lossy(&output.stderr).trim().to_string());
+  }
+  Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+/// Arguments limiting git to the directories `dirs` below `root`
+fn pathspecs(root: &Path, dirs: &[PathBuf]) -> Vec<String> {
+  let mut args = vec!["--".to_string()];
+  for dir in dirs {
+    if let Ok(rel) = dir.strip_prefix(root) {
+      let rel = rel.to_string_lossy();
+      // Taken as is, even with glob characters in the name
+      args.push(format!(":(literal){}", if rel.is_empty() { "." } else { &rel }));
+    }
+  }
+  args
+}
+
+/// The ignore rules of the repository a directory is in: `.gitignore` files in
+/// any directory, `.git/info/exclude` and the global excludes file. Read
+/// without running git, and kept until forgotten
+#[derive(Default)]
+pub struct Ignores {
+  /// The top of the repository, if there is one
+  top: Option<PathBuf>,
+  /// `.git/info/exclude`, then the global excludes file
+  excludes: Vec<Gitignore>,
+  /// The `.gitignore` of each directory loaded so far, if it has one
+  dirs: HashMap<PathBuf, Option<Gitignore>>,
+}
+
+impl Ignores {
+  /// The rules for the repository containing `dir`. Outside of a repository,
+  /// nothing is ignored
+  pub fn new(dir: &Path) -> Ignores {
+    let Some(top) = dir.ancestors().find(|d| d.join(".git").exists()) else {
+      return Ignores::default();
+    };
+    let mut exclude = GitignoreBuilder::new(top);
+    exclude.add(top.join(".git/info/exclude"));
+    Ignores {
+      top: Some(top.to_path_buf()),
+      excludes: exclude.build().into_iter().chain([Gitignore::global().0]).collect(),
+      dirs: HashMap::new(),
+    }
+  }
+
+  /// Read the `.gitignore` files of `dir` and the directories above it, unless
+  /// already read
+  pub fn load(&mut self, dir: &Path) {
+    let Some(top) = &self.top else {
+      return;
+    };
+    for dir in dir.ancestors().take_while(|d| d.starts_with(top)) {
+      if self.dirs.contains_key(dir) {
+        break;
+      }
+      let file = dir.join(".gitignore");
+      let rules = file.exists().then(|| Gitignore::new(file).0);
+      self.dirs.insert(dir.to_path_buf(), rules);
+    }
+  }
+
+  /// Read the `.gitignore` of `dir` again on the next `load`
+  pub fn forget(&mut self, dir: &Path) {
+    self.dirs.remove(dir);
+  }
+
+  /// Whether git ignores `path`. Rules in deeper directories take precedence,
+  /// and only the directories loaded so far are taken into account
+  pub fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+    let Some(top) = &self.top else {
+      return false;
+    };
+    let local = path
+      .ancestors()
+      .skip(1)
+      .take_while(|d| d.starts_with(top))
+      .filter_map(|d| self.dirs.get(d)?.as_ref());
+    for rules in local.chain(&self.excludes) {
+      match rules.matched(path, is_dir) {
+        Match::Ignore(_) => return true,
+        Match::Whitelist(_) => return false,
+        Match::None => {}
+      }
+    }
+    false
+  }
+}
+
+/// The state of a path in its git repository, from least to most notable.
+/// Directories take on the most notable state of their contents
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum GitStatus {
+  Ignored,
+  Untracked,
+  /// Changed in the index only
+  Staged,
+  /// Changed in the work tree, or conflicted
+  Modified,
+}
+
+impl GitStatus {
+  /// Parse the `XY` field of `git status --porcelain`
+  fn parse(xy: &str) -> Option<GitStatus> {
+    let mut chars = xy.chars();
+    let (x, y) = (chars.next()?, chars.next()?);
+    Some(match (x, y) {
+      ('?', '?') => GitStatus::Untracked,
+      ('!', '!') => GitStatus::Ignored,
+      (_, ' ') => GitStatus::Staged,
+      _ => GitStatus::Modified,
+    })
+  }
+
+  pub fn style(self, conf: &Config) -> Style {
+    match self {
+      GitStatus::Ignored => conf.git_ignored_style,
+      GitStatus::Untracked => conf.git_untracked_style,
+      GitStatus::Staged => conf.git_staged_style,
+      GitStatus::Modified => conf.git_modified_style,
+    }
+  }
+}
+
+/// The git status of everything below a directory
+#[derive(Default)]
+pub struct GitStatuses {
+  root: PathBuf,
+  /// As reported by git. Untracked or ignored directories are listed as a
+  /// whole, without their contents
+  paths: HashMap<PathBuf, GitStatus>,
+  /// Directories containing reported paths
+  dirs: HashMap<PathBuf, GitStatus>,
+}
+
+impl GitStatuses {
+  /// Run git in `root`, asking only about the directories `dirs` below it, so
+  /// the rest of a large repository isn't scanned. Fails outside of a
+  /// repository
+  pub fn read(root: &Path, dirs: &[PathBuf]) -> Result<GitStatuses, String> {
+    // git reports paths relative to the top of the repository
+    let prefix = git(root, &["rev-parse", "--show-prefix"])?;
+    let mut args = ["status", "--porcelain", "-z", "--ignored"].map(String::from).to_vec();
+    args.extend(pathspecs(root, dirs));
+    let args: Vec<_> = args.iter().map(String::as_str).collect();
+    let status = git(root, &args)?;
+    Ok(GitStatuses::parse(&status, root, prefix.trim_end()))
+  }
+
+  /// Replace what is known about `dirs` with `new`, read for the same root
+  pub fn merge(&mut self, new: GitStatuses, dirs: &[PathBuf]) {
+    self.paths.retain(|p, _| !dirs.iter().any(|d| p.starts_with(d)));
+    self.paths.extend(new.paths);
+    self.aggregate();
+  }
+
+  /// Give directories the most notable state of their contents
+  fn aggregate(&mut self) {
+    let (root, dirs) = (&self.root, &mut self.dirs);
+    dirs.clear();
+    for (path, &status) in &self.paths {
+      if status == GitStatus::Ignored {
+        continue;
+      }
+      for dir in path.ancestors().skip(1).take_while(|d| d.starts_with(root)) {
+        let agg = dirs.entry(dir.to_path_buf()).or_insert(status);
+        *agg = status.max(*agg);
+      }
+    }
+  }
+
+  /// Parse `git status --porcelain -z` output for `root`, which is at `prefix`
+  /// in the repository. Paths outside of `root` are left out
+  fn parse(output: &str, root: &Path, prefix: &str) -> GitStatuses {
+    let mut res = GitStatuses {
+      root: root.to_path_buf(),
+      ..GitStatuses::default()
+    };
+    let mut fields = output.split('\0');
+    while let Some(field) = fields.next() {
+      let (Some(xy), Some(path)) = (field.get(..2), field.get(3..)) else {
+        continue;
+      };
+      // Renames and copies are followed by the original path
+      if xy.starts_with(['R', 'C']) {
+        fields.next();
+      }
+      let (Some(status), Some(path)) = (GitStatus::parse(xy), path.strip_prefix(prefix)) else {
+        continue;
+      };
+      res.paths.insert(root.join(path.trim_end_matches('/')), status);
+    }
+    res.aggregate();
+    res
+  }
+
+  pub fn get(&self, path: &Path) -> Option<GitStatus> {
+    self
+      .paths
+      .get(path)
+      .or_else(|| self.dirs.get(path))
+      // Inside an untracked or ignored directory
+      .or_else(|| path.ancestors().skip(1).find_map(|d| self.paths.get(d)))
+      .copied()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::git::{pathspecs, GitStatus, GitStatuses, Ignores};
+  use std::path::{Path, PathBuf};
+
+  #[test]
+  fn ignore_rules() {
+    let top = tempfile::tempdir().unwrap();
+    let top = top.path();
+    std::fs::create_dir_all(top.join(".git/info")).unwrap();
+    std::fs::create_dir_all(top.join("sub")).unwrap();
+    std::fs::write(top.join(".gitignore"), "target/\n*.tmp\n").unwrap();
+    std::fs::write(top.join(".git/info/exclude"), "local\n").unwrap();
+    std::fs::write(top.join("sub/.gitignore"), "!keep.tmp\n").unwrap();
+
+    let mut ignores = Ignores::new(&top.join("sub"));
+    ignores.load(&top.join("sub"));
+    assert!(ignores.is_ignored(&top.join("target"), true));
+    assert!(!ignores.is_ignored(&top.join("target"), false));
+    assert!(ignores.is_ignored(&top.join("sub/cache.tmp"), false));
+    assert!(ignores.is_ignored(&top.join("sub/local"), false));
+    // Deeper rules take precedence
+    assert!(!ignores.is_ignored(&top.join("sub/keep.tmp"), false));
+    assert!(ignores.is_ignored(&top.join("keep.tmp"), false));
+    assert!(!ignores.is_ignored(&top.join("sub/file"), false));
+
+    std::fs::write(top.join("sub/.gitignore"), "file\n").unwrap();
+    ignores.load(&top.join("sub"));
+    assert!(!ignores.is_ignored(&top.join("sub/file"), false));
+    ignores.forget(&top.join("sub"));
+    ignores.load(&top.join("sub"));
+    assert!(ignores.is_ignored(&top.join("sub/file"), false));
+
+    // Outside of a repository
+    let other = tempfile::tempdir().unwrap();
+    std::fs::write(other.path().join(".gitignore"), "*\n").unwrap();
+    assert!(!Ignores::new(other.path()).is_ignored(&other.path().join("a"), false));
+  }
+
+  #[test]
+  fn parse_porcelain() {
+    let output = [
+      " M sub/dir/changed",
+      "M  sub/staged",
+      "R  sub/new",
+      "sub/old",
+      "?? sub/untracked/",
+      "!! sub/target/",
+      "A  outside",
+      "",
+    ]
+    .join("\0");
+    let root = Path::new("/repo/sub");
+    let statuses = GitStatuses::parse(&output, root, "sub/");
+    let status = |p: &str| statuses.get(&root.join(p));
+
+    assert_eq!(status("dir/changed"), Some(GitStatus::Modified));
+    assert_eq!(status("staged"), Some(GitStatus::Staged));
+    assert_eq!(status("new"), Some(GitStatus::Staged));
+    assert_eq!(status("old"), None);
+    assert_eq!(status("untracked/a/file"), Some(GitStatus::Untracked));
+    assert_eq!(status("target"), Some(GitStatus::Ignored));
+    assert_eq!(status("target/debug"), Some(GitStatus::Ignored));
+    assert_eq!(status("clean"), None);
+    // Directories show the most notable change inside, ignoring ignored files
+    assert_eq!(status("dir"), Some(GitStatus::Modified));
+    assert_eq!(statuses.get(root), Some(GitStatus::Modified));
+    assert_eq!(statuses.get(Path::new("/repo/outside")), None);
+    assert_eq!(statuses.get(Path::new("/repo")), None);
+  }
+
+  #[test]
+  fn merge_scoped() {
+    let root = Path::new("/repo");
+    let mut statuses = GitStatuses::parse(" M a/changed\0?? b/new\0", root, "");
+    assert_eq!(statuses.get(&root.join("b")), Some(GitStatus::Untracked));
+
+    // b was read again on its own, and now only has a staged file
+    let dirs = [root.join("b")];
+    statuses.merge(GitStatuses::parse("A  b/new\0", root, ""), &dirs);
+    assert_eq!(statuses.get(&root.join("a/changed")), Some(GitStatus::Modified));
+    assert_eq!(statuses.get(&root.join("b")), Some(GitStatus::Staged));
+    assert_eq!(statuses.get(root), Some(GitStatus::Modified));
+
+    statuses.merge(GitStatuses::parse("", root, ""), &[root.join("a")]);
+    assert_eq!(statuses.get(&root.join("a")), None);
+    assert_eq!(statuses.get(root), Some(GitStatus::Staged));
+
+    let specs = pathspecs(root, &[root.to_path_buf(), root.join("b*"), PathBuf::from("/x")]);
+    assert_eq!(specs, ["--", ":(literal).", ":(literal)b*"]);
+  }
+}
@@ -0,0 +1,227 @@
+use crate::trash;
+use crate::util::copy::{move_path, remove_path};
+use std::path::{Path, PathBuf};
+
+/// Operations kept for `undo`. Older ones are forgotten for good
+const MAX_UNDO: usize = 50;
+
+/// A single change to the file system
+#[derive(Debug, Clone, PartialEq)]
+pub enum Action {
+  Move { from: PathBuf, to: PathBuf },
+  /// `path` was created. Undoing it deletes it for good, so it can't be redone
+  Create { path: PathBuf },
+  /// `path` was deleted or overwritten for good, which can't be undone
+  Remove { path: PathBuf },
+  /// `path` was moved to `trashed`, in a trash directory
+  Trash { path: PathBuf, trashed: PathBuf },
+}
+
+impl Action {
+  /// Do the action again, after it was undone
+  fn apply(&self) -> Result<(), String> {
+    match self {
+      Action::Move { from, to } => move_to(from, to),
+      Action::Trash { path, trashed } => trash::put_at(path, trashed),
+      Action::Create { path } | Action::Remove { path } => {
+        Err(format!("{}: cannot be redone", path.display()))
+      }
+    }
+  }
+
+  /// Undo the action
+  fn revert(&self) -> Result<(), String> {
+    match self {
+      Action::Move { from, to } => move_to(to, from),
+      Action::Create { path } => remove_path(path),
+      Action::Remove { path } => Err(format!("{}: lost for good", path.display())),
+      Action::Trash { trashed, .. } => trash::restore(trashed).map(|_| ()),
+    }
+  }
+
+  /// Whether the action can be done again once undone. What was deleted for
+  /// good, or created and then deleted by `undo`, can't be brought back
+  fn can_redo(&self) -> bool {
+    !matches!(self, Action::Create { .. } | Action::Remove { .. })
+  }
+
+  /// The path this action leaves changed, for the `on_change` hook
+  pub fn changed_path(&self) -> &Path {
+    match self {
+      Action::Move { to, .. } => to,
+      Action::Create { path } | Action::Remove { path } => path,
+      Action::Trash { path, .. } => path,
+    }
+  }
+}
+
+/// Move `from` to `to`, unless something took its place in the meantime
+fn move_to(from: &Path, to: &Path) -> Result<(), String> {
+  if std::fs::symlink_metadata(to).is_ok() {
+    return Err(format!("{}: already exists", to.display()));
+  }
+  if let Some(dir) = to.parent() {
+    std::fs::create_dir_all(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+  }
+  move_path(from, to)
+}
+
+/// Delete `path` for good. The action records that it can't be undone
+pub fn delete(path: &Path) -> Result<Action, String> {
+  remove_path(path)?;
+  Ok(Action::Remove { path: path.to_path_buf() })
+}
+
+/// Actions done by one command, undone and redone together
+#[derive(Debug, Clone, PartialEq)]
+pub struct Operation {
+  /// What the command did, like "rename"
+  pub name: String,
+  pub actions: Vec<Action>,
+}
+
+/// Undo and redo stacks of file operations
+#[derive(Default)]
+pub struct History {
+  undo: Vec<Operation>,
+  redo: Vec<Operation>,
+}
+
+impl History {
+  /// Add a completed operation. Anything undone before can't be redone anymore
+  pub fn record(&mut self, name: &str, actions: Vec<Action>) {
+    if actions.is_empty() {
+      return;
+    }
+    self.redo.clear();
+    self.undo.push(Operation {
+      name: name.to_string(),
+      actions,
+    });
+    if self.undo.len() > MAX_UNDO {
+      self.undo.remove(0);
+    }
+  }
+
+  /// Undo the last operation, returning it. Actions that fail are skipped, and
+  /// reported once the others are done. An operation that deleted or created
+  /// something can't be redone, and neither can what was undone before it
+  pub fn undo(&mut self) -> Result<Operation, String> {
+    let op = self.undo.pop().ok_or("nothing to undo")?;
+    let res = apply_all(op.actions.iter().rev().map(Action::revert));
+    match op.actions.iter().all(Action::can_redo) {
+      true => self.redo.push(op.clone()),
+      false => self.redo.clear(),
+    }
+    res.map(|_| op.clone()).map_err(|e| format!("could not undo {}: {}", op.name, e))
+  }
+
+  /// Redo the last undone operation, returning it
+  pub fn redo(&mut self) -> Result<Operation, String> {
+    let op = self.redo.pop().ok_or("nothing to redo")?;
+    let res = apply_all(op.actions.iter().map(Action::apply));
+    self.undo.push(op.clone());
+    res.map(|_| op.clone()).map_err(|e| format!("could not redo {}: {}", op.name, e))
+  }
+
+  /// Forget everything
+  pub fn clear(&mut self) {
+    self.undo.clear();
+    self.redo.clear();
+  }
+}
+
+fn apply_all(results: impl Iterator<Item = Result<(), String>>) -> Result<(), String> {
+  let errors: Vec<_> = results.filter_map(Result::err).collect();
+  match errors.is_empty() {
+    true => Ok(()),
+    false => Err(errors.join(", ")),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::history::{delete, Action, History};
+  use std::fs;
+
+  #[test]
+  fn group_undo_redo() {
+    let dir = tempfile::tempdir().unwrap();
+    let base = dir.path();
+    fs::create_dir_all(base.join("sub")).unwrap();
+    for name in ["a", "b", "c", "sub/d"] {
+      fs::write(base.join(name), name).unwrap();
+    }
+    let mut history = History::default();
+
+    // A batch rename is one step
+    for (from, to) in [("a", "a2"), ("b", "b2")] {
+      fs::rename(base.join(from), base.join(to)).unwrap();
+    }
+    let moved = [("a", "a2"), ("b", "b2")]
+      .iter()
+      .map(|(from, to)| Action::Move { from: base.join(from), to: base.join(to) })
+      .collect();
+    history.record("rename", moved);
+    assert_eq!(history.undo().unwrap().name, "rename");
+    assert!(base.join("a").exists() && base.join("b").exists());
+    assert!(history.undo().is_err());
+    assert_eq!(history.redo().unwrap().name, "rename");
+    assert!(base.join("a2").exists() && base.join("b2").exists());
+    assert!(history.redo().is_err());
+
+    // Deleted entries are gone for good, undo only says so
+    let deleted = ["c", "sub"]
+      .iter()
+      .map(|name| delete(&base.join(name)).unwrap())
+      .collect();
+    history.record("rm", deleted);
+    assert!(!base.join("c").exists() && !base.join("sub").exists());
+    let err = history.undo().unwrap_err();
+    assert!(err.contains("lost for good"), "{}", err);
+    assert!(!base.join("c").exists());
+    assert!(history.redo().is_err());
+    // What was done before is still there to undo
+    assert_eq!(history.undo().unwrap().name, "rename");
+    assert!(base.join("a").exists());
+
+    // Undoing a creation deletes it, and something new drops what was undone
+    fs::write(base.join("new"), "").unwrap();
+    history.record("mk", vec![Action::Create { path: base.join("new") }]);
+    assert!(history.redo().is_err());
+    assert_eq!(history.undo().unwrap().name, "mk");
+    assert!(!base.join("new").exists());
+    assert!(history.redo().is_err());
+  }
+
+  #[test]
+  fn delete_missing_path() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut history = History::default();
+    let missing = dir.path().join("missing");
+    let err = delete(&missing).unwrap_err();
+    assert!(err.contains("missing"), "{}", err);
+    history.record("rm", Vec::new());
+    assert!(history.undo().is_err());
+  }
+
+  #[test]
+  fn conflicts_are_reported() {
+    let dir = tempfile::tempdir().unwrap();
+    let base = dir.path();
+    fs::write(base.join("a"), "old").unwrap();
+    fs::write(base.join("b"), "").unwrap();
+    // Renamed over b, which is lost
+    let mut history = History::default();
+    let replaced = delete(&base.join("b")).unwrap();
+    fs::rename(base.join("a"), base.join("b")).unwrap();
+    let moved = Action::Move { from: base.join("a"), to: base.join("b") };
+    history.record("rename", vec![replaced, moved]);
+    // Something took a's place in the meantime
+    fs::write(base.join("a"), "new").unwrap();
+    let err = history.undo().unwrap_err();
+    assert!(err.contains("already exists") && err.contains("lost for good"), "{}", err);
+    assert_eq!(fs::read_to_string(base.join("a")).unwrap(), "new");
+    assert_eq!(fs::read_to_string(base.join("b")).unwrap(), "old");
+  }
+}
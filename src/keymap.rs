@@ -1,6 +1,6 @@
+use crate::commands::parse_cmds;
 use crate::Command;
 use combine::parser::char::char;
-use combine::parser::char::letter;
 use combine::parser::char::string;
 use combine::*;
 use std::collections::HashMap;
@@ -8,8 +8,81 @@ use crossterm::event::{KeyCode, KeyModifiers};
 use crate::app::{KeyPress};
 
 
+/// What the keys typed so far map to
+#[derive(Debug, PartialEq)]
+pub enum KeyMatch {
+  /// No mapping starts with them
+  None,
+  /// The start of longer mappings, with the command of its own mapping if any
+  Prefix(Option<Command>),
+  Command(Command),
+}
+
+/// The mappings sidetree starts with
+const DEFAULT_KEYS: &[(&str, &str)] = &[
+  ("q", "quit"),
+  ("j", "down"),
+  ("<down>", "down"),
+  ("k", "up"),
+  ("<up>", "up"),
+  ("gg", "first"),
+  ("<home>", "first"),
+  ("G", "last"),
+  ("<end>", "last"),
+  ("<c-d>", "halfpagedown"),
+  ("<c-u>", "halfpageup"),
+  ("<c-f>", "pagedown"),
+  ("<pagedown>", "pagedown"),
+  ("<c-b>", "pageup"),
+  ("<pageup>", "pageup"),
+  ("h", "left"),
+  ("<left>", "left"),
+  ("l", "right"),
+  ("<right>", "right"),
+  ("<ret>", "enter"),
+  ("<a-ret>", "accept"),
+  ("<a-l>", "cd"),
+  ("!", "shell"),
+  (":", "prompt"),
+  ("<esc>", "escape"),
+  ("<c-a>", "markall"),
+  ("<space>", "marknext"),
+  ("v", "invertmarks"),
+  ("<tab>", "previewfocus"),
+  ("y", "yank"),
+  ("x", "cut"),
+  ("p", "paste"),
+  ("]", "nextdir"),
+  ("^", "topdir"),
+  ("[", "prevdir"),
+  ("f", "jump"),
+  ("u", "undo"),
+  ("<c-r>", "redo"),
+  ("/", "find"),
+  ("<gt>", "narrow"),
+  ("<lt>", "widen"),
+  ("E", "expandall"),
+  ("C", "collapseall"),
+  ("s", "search"),
+  ("n", "searchnext"),
+  ("N", "searchprev"),
+  ("-", "rootup"),
+  ("<backspace>", "rootup"),
+  ("+", "rootdown"),
+  ("m", "bookmark"),
+  ("'", "bookmarks"),
+  ("R", "reload"),
+  ("gc", "selectcwd"),
+  (".", "togglehiddenscoped"),
+  ("<a-.>", "togglehiddenscoped other"),
+  ("D", "toggledirsonly"),
+  ("<c-p>", "palette"),
+  ("?", "keys"),
+];
+
+/// Mappings from sequences of one or more keys to commands
 pub struct KeyMap {
-  keys: HashMap<KeyPress, Command>,
+  keys: HashMap<Vec<KeyPress>, Command>,
 }
 impl KeyMap {
   pub fn new() -> KeyMap {
@@ -18,76 +91,212 @@ impl KeyMap {
     }
   }
 
-  pub fn add_mapping(&mut self, k: KeyPress, c: Command) {
-    self.keys.insert(k, c);
+  pub fn with_defaults() -> KeyMap {
+    let mut keymap = KeyMap::new();
+    for (keys, cmd) in DEFAULT_KEYS {
+      let cmd = parse_cmds(cmd).expect("invalid default command").remove(0);
+      keymap.add_mapping(parse_keys(keys).expect("invalid default key"), cmd);
+    }
+    keymap
+  }
+
+  pub fn add_mapping(&mut self, keys: Vec<KeyPress>, c: Command) {
+    self.keys.insert(keys, c);
+  }
+
+  /// Remove the mapping of `keys`. Keys that aren't mapped are left alone
+  pub fn remove_mapping(&mut self, keys: &[KeyPress]) {
+    self.keys.remove(keys);
+  }
+
+  pub fn get_mapping(&self, keys: &[KeyPress]) -> Option<Command> {
+    self.keys.get(keys).cloned()
+  }
+
+  /// Look up `keys`. A mapping that longer ones start with is a `Prefix`, so
+  /// the caller decides when to stop waiting for more keys
+  pub fn lookup(&self, keys: &[KeyPress]) -> KeyMatch {
+    let longer = self
+      .keys
+      .keys()
+      .any(|k| k.len() > keys.len() && k.starts_with(keys));
+    match (self.get_mapping(keys), longer) {
+      (cmd, true) => KeyMatch::Prefix(cmd),
+      (Some(cmd), false) => KeyMatch::Command(cmd),
+      (None, false) => KeyMatch::None,
+    }
+  }
+
+  pub fn mappings(&self) -> impl Iterator<Item = (&Vec<KeyPress>, &Command)> {
+    self.keys.iter()
   }
 
-  pub fn get_mapping(&self, k: KeyPress) -> Option<Command> {
-    self.keys.get(&k).cloned()
+  /// A line per mapping like `y → yank`, sorted by key. Sequences are listed
+  /// under their first key, so chords starting alike are found together
+  pub fn help_lines(&self) -> Vec<String> {
+    let mut mappings: Vec<_> = self
+      .keys
+      .iter()
+      .map(|(keys, cmd)| (format_keys(&keys[..1]), format_keys(keys), keys.len(), cmd))
+      .collect();
+    mappings.sort_by(|a, b| (&a.0, a.2, &a.1).cmp(&(&b.0, b.2, &b.1)));
+    let mut lines = Vec::new();
+    let mut group = None;
+    for (first, keys, len, cmd) in &mappings {
+      if *len == 1 {
+        lines.push(format!("{} → {}", keys, cmd));
+        continue;
+      }
+      if group != Some(first) {
+        lines.push(format!("{}…", first));
+        group = Some(first);
+      }
+      lines.push(format!("  {} → {}", keys, cmd));
+    }
+    lines
+  }
+}
+
+/// Formats a key sequence the way `parse_keys` reads it
+pub fn format_keys(keys: &[KeyPress]) -> String {
+  let text: String = keys.iter().map(|k| k.to_string()).collect();
+  // Like "ret" for r, e, t, which would be read as a single key
+  if keys.len() > 1 && parse_key(&text).is_ok() {
+    return keys.iter().map(|k| format!("<{}>", k.to_string().trim_matches(['<', '>']))).collect();
+  }
+  text
+}
+
+/// Formats the key the way `parse_key` reads it
+impl std::fmt::Display for KeyPress {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    let name = match self.0 {
+      KeyCode::Char('\n') => "ret".to_string(),
+      KeyCode::Char(';') => "semicolon".to_string(),
+      KeyCode::Char('>') => "gt".to_string(),
+      KeyCode::Char('<') => "lt".to_string(),
+      KeyCode::Char('%') => "percent".to_string(),
+      KeyCode::Char(' ') => "space".to_string(),
+      KeyCode::Tab => "tab".to_string(),
+      KeyCode::Char(c) if self.has_alt() || self.has_control() => c.to_string(),
+      KeyCode::Char(c) => return write!(f, "{}", c),
+      KeyCode::Esc => "esc".to_string(),
+      KeyCode::BackTab => "backtab".to_string(),
+      KeyCode::Backspace => "backspace".to_string(),
+      KeyCode::Delete => "del".to_string(),
+      KeyCode::Home => "home".to_string(),
+      KeyCode::End => "end".to_string(),
+      KeyCode::Up => "up".to_string(),
+      KeyCode::Down => "down".to_string(),
+      KeyCode::Left => "left".to_string(),
+      KeyCode::Right => "right".to_string(),
+      KeyCode::Insert => "insert".to_string(),
+      KeyCode::PageUp => "pageup".to_string(),
+      KeyCode::PageDown => "pagedown".to_string(),
+      kc => format!("{:?}", kc).to_lowercase(),
+    };
+    let ctrl = if self.has_control() { "c-" } else { "" };
+    let alt = if self.has_alt() { "a-" } else { "" };
+    write!(f, "<{}{}{}>", ctrl, alt, name)
+  }
+}
+
+/// A character key, by itself or by one of the names for characters that
+/// can't be written plainly
+fn char_key(word: &str) -> Option<char> {
+  match word {
+    "return" | "ret" => Some('\n'),
+    "semicolon" => Some(';'),
+    "gt" => Some('>'),
+    "lt" => Some('<'),
+    "percent" => Some('%'),
+    "space" => Some(' '),
+    "tab" => Some('\t'),
+    _ => {
+      let mut chars = word.chars();
+      match (chars.next(), chars.next()) {
+        (Some(c), None) => Some(c),
+        _ => None,
+      }
+    }
+  }
+}
+
+/// A key that isn't a character, by name
+fn named_key(word: &str) -> Option<KeyCode> {
+  match word {
+    "esc" => Some(KeyCode::Esc),
+    "backtab" => Some(KeyCode::BackTab),
+    "backspace" => Some(KeyCode::Backspace),
+    "del" => Some(KeyCode::Delete),
+    "home" => Some(KeyCode::Home),
+    "end" => Some(KeyCode::End),
+    "up" => Some(KeyCode::Up),
+    "down" => Some(KeyCode::Down),
+    "left" => Some(KeyCode::Left),
+    "right" => Some(KeyCode::Right),
+    "insert" => Some(KeyCode::Insert),
+    "pageup" => Some(KeyCode::PageUp),
+    "pagedown" => Some(KeyCode::PageDown),
+    _ => None,
   }
 }
 
+/// Parse a key like `a`, `<ret>`, `<c-x>`, `<a-up>` or `<c-a-space>`. Only
+/// character keys can be written without angle brackets
 pub fn parse_key(input: &str) -> Result<KeyPress, easy::ParseError<&str>> {
-  let char_key = || {
-    many1(none_of(">".chars())).and_then(|word: String| match word.as_str() {
-      "return" => Ok('\n'),
-      "ret" => Ok('\n'),
-      "semicolon" => Ok(';'),
-      "gt" => Ok('>'),
-      "lt" => Ok('<'),
-      "percent" => Ok('%'),
-      "space" => Ok(' '),
-      "tab" => Ok('\t'),
-      c if c.len() == 1 => Ok(c.chars().next().unwrap()),
-      &_ => Err(error::UnexpectedParse::Unexpected),
-    }).map(|c| KeyPress(KeyCode::Char(c),KeyModifiers::NONE))
+  let word = || many1(none_of(">".chars()));
+  // Terminals report tab as a key of its own
+  let code = |c| match c {
+    '\t' => KeyCode::Tab,
+    c => KeyCode::Char(c),
   };
- let modifier = || {
-    optional(choice!(
-      attempt(string("a-").map(|_| -> fn() -> KeyPress {|| {KeyPress(KeyCode::Esc,KeyModifiers::ALT) }})),
-      attempt(string("c-").map(|_| -> fn() -> KeyPress {|| {KeyPress(KeyCode::Esc,KeyModifiers::CONTROL) }}))
-    ))
-    .map(|x| x.unwrap_or(|| {KeyPress(KeyCode::Esc,KeyModifiers::NONE)}))
-  };
-  let non_mod = || {
-    many1(letter()).and_then(|word: String| match word.as_str() {
-      "esc" => Ok(KeyCode::Esc),
-      "backtab" => Ok(KeyCode::BackTab),
-      "backspace" => Ok(KeyCode::Backspace),
-      "del" => Ok(KeyCode::Delete),
-      "home" => Ok(KeyCode::Home),
-      "end" => Ok(KeyCode::End),
-      "up" => Ok(KeyCode::Up),
-      "down" => Ok(KeyCode::Down),
-      "left" => Ok(KeyCode::Left),
-      "right" => Ok(KeyCode::Right),
-      "insert" => Ok(KeyCode::Insert),
-      "pageup" => Ok(KeyCode::PageUp),
-      "pagedown" => Ok(KeyCode::PageDown),
-      &_ => Err(error::UnexpectedParse::Unexpected),
-    }).map(|kc| KeyPress(kc,KeyModifiers::NONE))
-  };
-  let short = || char_key().map(|c|c);
-  let long = || {
-    between(
-      char('<'),
-      char('>'),
-      attempt(
-        modifier()
-            .and(char_key())
-            .map(
-              |(f, c)| f().charize(c)))
-          .or(non_mod()),
-    )
-  };
-  let parser = long().or(short());
+  let short = word().and_then(move |word: String| match char_key(&word) {
+    Some(c) => Ok(code(c)),
+    None => Err(error::UnexpectedParse::Unexpected),
+  });
+  let modifier = choice((
+    attempt(string("a-")).map(|_| KeyModifiers::ALT),
+    attempt(string("c-")).map(|_| KeyModifiers::CONTROL),
+  ));
+  let key = word().and_then(|word: String| {
+    let code = char_key(&word).map(code).or_else(|| named_key(&word));
+    code.ok_or(error::UnexpectedParse::Unexpected)
+  });
+  let long = between(char('<'), char('>'), many::<Vec<_>, _, _>(modifier).and(key))
+    .map(|(mods, code)| KeyPress(code, mods.into_iter().fold(KeyModifiers::NONE, |a, m| a | m)));
+  let parser = long.or(short.map(|code| KeyPress(code, KeyModifiers::NONE)));
 
   parser.skip(eof()).easy_parse(input).map(|(k, _)| k)
 }
 
+/// Parse one key as `parse_key` does, or else a sequence of keys like `gg` or
+/// `<c-x>k`, each a single character or written in angle brackets
+pub fn parse_keys(input: &str) -> Result<Vec<KeyPress>, String> {
+  if let Ok(key) = parse_key(input) {
+    return Ok(vec![key]);
+  }
+  let mut keys = Vec::new();
+  let mut rest = input;
+  while let Some(c) = rest.chars().next() {
+    let len = match rest.find('>') {
+      Some(end) if c == '<' => end + 1,
+      _ => c.len_utf8(),
+    };
+    let key = parse_key(&rest[..len]).map_err(|_| format!("could not parse key {}", &rest[..len]))?;
+    keys.push(key);
+    rest = &rest[len..];
+  }
+  match keys.is_empty() {
+    true => Err("no key given".to_string()),
+    false => Ok(keys),
+  }
+}
+
 #[cfg(test)]
 mod tests {
-  use crate::keymap::parse_key;
+  use crate::commands::Command;
+  use crate::keymap::{format_keys, parse_key, parse_keys, KeyMap, KeyMatch};
 
   use crossterm::event::{KeyCode,KeyModifiers};
   use crate::app::KeyPress;
@@ -101,5 +310,102 @@ mod tests {
     assert_eq!(parse_key("<c-b>"), Ok(KeyPress(KeyCode::Char('b'),KeyModifiers::CONTROL)));
     assert_eq!(parse_key("<return>"), Ok(KeyPress::from('\n')));
     assert_eq!(parse_key("<esc>"), Ok(KeyPress::from(KeyCode::Esc)));
+    assert_eq!(parse_key("<tab>"), Ok(KeyPress::from(KeyCode::Tab)));
+    assert_eq!(parse_key("ret"), Ok(KeyPress::from('\n')));
+    assert!(parse_key("esc").is_err());
+    assert!(parse_key("ab").is_err());
+  }
+
+  #[test]
+  fn modifiers() {
+    let ctrl_alt = KeyModifiers::CONTROL | KeyModifiers::ALT;
+    assert_eq!(parse_key("<c-a-x>"), Ok(KeyPress(KeyCode::Char('x'), ctrl_alt)));
+    assert_eq!(parse_key("<a-c-x>"), Ok(KeyPress(KeyCode::Char('x'), ctrl_alt)));
+    // A modifier alone is the key it names
+    assert_eq!(parse_key("<c-a>"), Ok(KeyPress(KeyCode::Char('a'), KeyModifiers::CONTROL)));
+    assert_eq!(parse_key("<a-->"), Ok(KeyPress(KeyCode::Char('-'), KeyModifiers::ALT)));
+    assert_eq!(parse_key("<c-up>"), Ok(KeyPress(KeyCode::Up, KeyModifiers::CONTROL)));
+    assert_eq!(parse_key("<a-esc>"), Ok(KeyPress(KeyCode::Esc, KeyModifiers::ALT)));
+    assert_eq!(parse_key("<c-a-space>"), Ok(KeyPress(KeyCode::Char(' '), ctrl_alt)));
+    assert!(parse_key("<c-nope>").is_err());
+    assert!(parse_key("<x-a>").is_err());
+  }
+
+  #[test]
+  fn key_display_round_trip() {
+    let keys = [
+      "a", "<a-a>", "<c-b>", "<ret>", "<a-ret>", "<lt>", "<space>", "<esc>", "<pageup>",
+      "<c-a-x>", "<c-up>", "<a-pagedown>", "<tab>",
+    ];
+    for key in keys {
+      let parsed = parse_key(key).unwrap();
+      assert_eq!(parsed.to_string(), key);
+      assert_eq!(parse_key(&parsed.to_string()), Ok(parsed));
+    }
+  }
+
+  #[test]
+  fn key_sequences() {
+    let g = KeyPress::from('g');
+    assert_eq!(parse_keys("g"), Ok(vec![g]));
+    assert_eq!(parse_keys("gg"), Ok(vec![g, g]));
+    assert_eq!(parse_keys("ret"), Ok(vec![KeyPress::from('\n')]));
+    let ctrl_x = KeyPress(KeyCode::Char('x'), KeyModifiers::CONTROL);
+    assert_eq!(parse_keys("<c-x>k"), Ok(vec![ctrl_x, KeyPress::from('k')]));
+    assert!(parse_keys("g<nope>").is_err());
+    assert!(parse_keys("").is_err());
+    for keys in ["gg", "<c-x>k", "<r><e><t>", "<lt>a"] {
+      assert_eq!(format_keys(&parse_keys(keys).unwrap()), keys);
+    }
+  }
+
+  #[test]
+  fn sequence_lookup() {
+    let mut keymap = KeyMap::new();
+    keymap.add_mapping(parse_keys("gg").unwrap(), Command::TopDir);
+    keymap.add_mapping(parse_keys("d").unwrap(), Command::Cut);
+    keymap.add_mapping(parse_keys("dd").unwrap(), Command::Delete { prompt: true });
+    let lookup = |keys: &str| keymap.lookup(&parse_keys(keys).unwrap());
+    assert_eq!(lookup("g"), KeyMatch::Prefix(None));
+    assert_eq!(lookup("gg"), KeyMatch::Command(Command::TopDir));
+    assert_eq!(lookup("gx"), KeyMatch::None);
+    assert_eq!(lookup("d"), KeyMatch::Prefix(Some(Command::Cut)));
+    assert_eq!(lookup("dd"), KeyMatch::Command(Command::Delete { prompt: true }));
+    assert_eq!(lookup("x"), KeyMatch::None);
+  }
+
+  #[test]
+  fn help_lines() {
+    let mut keymap = KeyMap::new();
+    for (keys, cmd) in [("gg", Command::TopDir), ("y", Command::Yank), ("g", Command::Up)] {
+      keymap.add_mapping(parse_keys(keys).unwrap(), cmd);
+    }
+    keymap.add_mapping(parse_keys("gd").unwrap(), Command::Cd(Some("..".into())));
+    keymap.add_mapping(parse_keys("<c-x>k").unwrap(), Command::Delete { prompt: true });
+    assert_eq!(
+      keymap.help_lines(),
+      [
+        "<c-x>…",
+        "  <c-x>k → rm",
+        "g → up",
+        "g…",
+        "  gd → cd ..",
+        "  gg → topdir",
+        "y → yank",
+      ]
+    );
+  }
+
+  #[test]
+  fn defaults_and_unmap() {
+    let mut keymap = KeyMap::with_defaults();
+    let keys = |k: &str| parse_keys(k).unwrap();
+    assert_eq!(keymap.get_mapping(&keys("j")), Some(Command::Down));
+    assert_eq!(keymap.get_mapping(&keys("<ret>")), Some(Command::Enter));
+    keymap.remove_mapping(&keys("j"));
+    assert_eq!(keymap.get_mapping(&keys("j")), None);
+    // Not mapped, nothing happens
+    keymap.remove_mapping(&keys("gg"));
+    assert_eq!(keymap.get_mapping(&keys("k")), Some(Command::Up));
   }
 }
@@ -1,29 +1,41 @@
 mod app;
+mod bulk_rename;
 mod cache;
 mod commands;
 mod config;
 mod file_tree;
+mod git;
+mod history;
 mod icons;
 mod keymap;
+mod popup;
+mod preview;
 mod prompt;
+mod tasks;
+mod trash;
 mod util;
+mod watcher;
 
 use crate::commands::Command;
 use crate::{app::App, cache::Cache};
 use std::{fs::File, path::PathBuf};
+use path_absolutize::Absolutize;
 
 use clap::Parser;
-use commands::parse_cmds;
 use std::{
   error::Error,
-  io,
+  io::{self, IsTerminal},
   time::{Duration, Instant},
 };
 use ratatui::backend::{CrosstermBackend};
 use ratatui::Terminal;
 
 use crossterm::{
-  event::{self, DisableMouseCapture, EnableMouseCapture, Event, },
+  cursor,
+  event::{
+    self, DisableBracketedPaste, DisableFocusChange, DisableMouseCapture, EnableBracketedPaste,
+    EnableFocusChange, EnableMouseCapture, Event,
+  },
   execute,
   terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
@@ -37,7 +49,6 @@ extern crate combine;
   author = env!("CARGO_PKG_AUTHORS"),
 )]
 /// An interactive file tree meant to be used as a side panel for terminal text editors
-
 pub struct Opts {
   /// The base directory to open sidetree to
   #[clap(default_value = ".")]
@@ -52,22 +63,36 @@ pub struct Opts {
   #[clap(long)]
   no_cache: bool,
 
-  /// Preselect a path. Will expand all directories up to the path
+  /// Preselect a path. Will expand all directories up to the path.
+  /// Falls back to $SIDETREE_SELECT when not given
   #[clap(short, long)]
   select: Option<PathBuf>,
 
   /// Commands to run on startup
   #[clap(short, long)]
   exec: Option<String>,
+
+  /// Hide the status line unless a prompt is open. Same as `set minimal true`
+  #[clap(long)]
+  minimal: bool,
+
+  /// Pick a path, for example `cd "$(sidetree --pick)"`. Opening a file or
+  /// running `accept` quits and prints the selected path. Quitting otherwise
+  /// exits with status 1
+  #[clap(long)]
+  pick: bool,
 }
 
 const DEFAULT_CONFIG: &str = include_str!("../sidetreerc");
 
-fn default_conf_file() -> PathBuf {
+/// Directory holding sidetree's config, $XDG_CONFIG_DIR/sidetree
+pub fn config_dir() -> PathBuf {
   let xdg = xdg::BaseDirectories::with_prefix("sidetree").unwrap();
-  let conf_file = xdg
-    .place_config_file("sidetreerc")
-    .expect("Cannot create config directory");
+  xdg.create_config_directory("").expect("Cannot create config directory")
+}
+
+pub fn default_conf_file() -> PathBuf {
+  let conf_file = config_dir().join("sidetreerc");
   if !conf_file.exists() {
     File::create(&conf_file).expect("Cannot create config file");
     std::fs::write(&conf_file, DEFAULT_CONFIG).expect("Couldn't write default config file");
@@ -75,28 +100,76 @@ fn default_conf_file() -> PathBuf {
   conf_file
 }
 
-pub fn run(opts: &Opts,cache: Cache,tick_rate: Duration, enhanced_graphics: bool) -> Result<(), Box<dyn Error>> {
-  // setup terminal
+/// Where the UI is drawn. That's stderr when stdout isn't a terminal, so the
+/// output of `--pick` can be captured
+pub fn ui_output() -> Box<dyn io::Write + Send> {
+  if io::stdout().is_terminal() {
+    Box::new(io::stdout())
+  } else {
+    Box::new(io::stderr())
+  }
+}
+
+/// Put the terminal in raw mode on the alternate screen
+pub fn setup_terminal() -> io::Result<()> {
   enable_raw_mode()?;
-  let mut stdout = io::stdout();
+  execute!(
+    ui_output(),
+    EnterAlternateScreen,
+    EnableMouseCapture,
+    EnableFocusChange,
+    EnableBracketedPaste
+  )
+}
 
-  crossterm::execute!(stdout, EnterAlternateScreen, EnableMouseCapture)?;
+/// Undo `setup_terminal`
+pub fn restore_terminal() -> io::Result<()> {
+  disable_raw_mode()?;
+  execute!(
+    ui_output(),
+    LeaveAlternateScreen,
+    DisableMouseCapture,
+    DisableFocusChange,
+    DisableBracketedPaste,
+    cursor::Show
+  )
+}
+
+/// Restore the terminal and exit when sidetree is told to stop, for example
+/// by the editor it's embedded in closing. The signals are handled on their
+/// own thread, which only has to wait for the draw loop to let go of stdout
+fn exit_on_signals() -> io::Result<()> {
+  use signal_hook::consts::{SIGHUP, SIGINT, SIGQUIT, SIGTERM};
+  let mut signals = signal_hook::iterator::Signals::new([SIGHUP, SIGINT, SIGQUIT, SIGTERM])?;
+  std::thread::spawn(move || {
+    if let Some(signal) = signals.forever().next() {
+      let _ = restore_terminal();
+      std::process::exit(128 + signal);
+    }
+  });
+  Ok(())
+}
+
+pub fn run(opts: &Opts,cache: Cache,tick_rate: Duration) -> Result<(), Box<dyn Error>> {
+  exit_on_signals()?;
+  setup_terminal()?;
 
-  let backend = CrosstermBackend::new(stdout);
+  let backend = CrosstermBackend::new(ui_output());
   let mut terminal = Terminal::new(backend)?;
 
   // create app and run it
-  let mut app = App::new(opts,cache,enhanced_graphics);
-  let conf_file = opts.config.clone().unwrap_or_else(default_conf_file);
-
-  app.run_script_file(&conf_file)?;
-  if opts.exec.is_some() {
-    app.run_commands(&parse_cmds(&opts.exec.clone().unwrap())?)
-  }
+  let mut app = App::new(opts,cache);
+  app.load_config()?;
 
   app.tree.change_root(&app.config, opts.directory.clone());
 
-  if let Some(path) = opts.select.clone() {
+  let select = opts
+    .select
+    .clone()
+    .or_else(|| std::env::var_os("SIDETREE_SELECT").map(PathBuf::from));
+  if let Some(path) = select {
+    // The path to select is usually the file being edited
+    app.tree.active_path = path.absolutize().ok().map(PathBuf::from);
     app.tree.expand_to_path(&path);
     app.tree.update(&app.config);
     app.tree.select_path(&path);
@@ -104,17 +177,14 @@ pub fn run(opts: &Opts,cache: Cache,tick_rate: Duration, enhanced_graphics: bool
 
   let res = run_app(&mut terminal, app, tick_rate);
 
-  // restore terminal
-  disable_raw_mode()?;
-  execute!(
-        terminal.backend_mut(),
-        LeaveAlternateScreen,
-        DisableMouseCapture
-    )?;
-  terminal.show_cursor()?;
-
-  if let Err(err) = res {
-    println!("{err:?}");
+  restore_terminal()?;
+
+  // Only the picked path goes to stdout, it's what a calling script reads
+  match res {
+    Ok(Some(path)) => println!("{}", path.display()),
+    Ok(None) if opts.pick => std::process::exit(1),
+    Ok(None) => {}
+    Err(err) => eprintln!("{err:?}"),
   }
 
   Ok(())
@@ -123,19 +193,35 @@ fn run_app<B: Backend>(
   terminal: &mut Terminal<B>,
   mut app: App,
   tick_rate: Duration,
-) -> io::Result<()> {
+) -> io::Result<Option<PathBuf>> {
   let mut last_tick = Instant::now();
   loop {
+    if app.redraw {
+      terminal.clear()?;
+      app.redraw = false;
+    }
     terminal.draw(|f| app.draw(f))?;
 
-    let timeout = tick_rate.saturating_sub(last_tick.elapsed());
+    // Changes are looked for more often than the tick, to show them quickly
+    let timeout = tick_rate
+      .saturating_sub(last_tick.elapsed())
+      .min(watcher::DEBOUNCE);
     if event::poll(timeout)? {
-      if let Event::Key(key) = event::read()? {
-        app.on_key(key);
-      } else if let Event::Mouse(mouse) = event::read()? {
-        app.on_mouse(mouse);
+      match event::read()? {
+        Event::Key(key) => {
+          app.on_key(key);
+        }
+        Event::Mouse(mouse) => {
+          app.on_mouse(mouse);
+        }
+        Event::Paste(text) => app.on_paste(&text),
+        Event::FocusGained => app.focused = true,
+        Event::FocusLost => app.focused = false,
+        // Some terminals leave artifacts behind when shrinking
+        Event::Resize(..) => app.redraw = true,
       }
     }
+    app.update_changed();
     if last_tick.elapsed() >= tick_rate {
       app.tick();
       last_tick = Instant::now();
@@ -144,7 +230,7 @@ fn run_app<B: Backend>(
       if !app.opts.no_cache {
         app.get_cache().write_file(&Cache::default_file_path())
       }
-      return Ok(());
+      return Ok(app.picked);
     }
   }
 }
@@ -160,7 +246,7 @@ fn main() -> Result<(), Box<dyn Error>> {
     Cache::default()
   };
 
-    run(&opts, cache, tick_rate, true)?;
+    run(&opts, cache, tick_rate)?;
 
 
   Ok(())
@@ -0,0 +1,130 @@
+use crate::app::KeyPress;
+use crate::commands::Command;
+use crossterm::event::KeyCode;
+use ratatui::layout::Rect;
+use ratatui::style::{Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Borders, Clear, Paragraph};
+use ratatui::Frame;
+
+/// A scrollable text box drawn on top of the tree.
+/// Menus additionally have a command per line to choose from
+pub struct Popup {
+  title: String,
+  lines: Vec<String>,
+  scroll: usize,
+  commands: Vec<Command>,
+  selected: usize,
+}
+
+/// What to do after a key was handled by a popup
+pub enum PopupAction {
+  Keep,
+  Close,
+  Run(Command),
+}
+
+impl Popup {
+  pub fn new(title: &str, lines: Vec<String>) -> Popup {
+    Popup {
+      title: title.to_string(),
+      lines,
+      scroll: 0,
+      commands: Vec::new(),
+      selected: 0,
+    }
+  }
+
+  /// A popup to choose one of `items` from, by label
+  pub fn menu(title: &str, items: Vec<(String, Command)>) -> Popup {
+    let (labels, commands): (Vec<_>, Vec<_>) = items.into_iter().unzip();
+    let lines = labels
+      .iter()
+      .enumerate()
+      .map(|(i, label)| format!("{} {}", i + 1, label))
+      .collect();
+    Popup {
+      commands,
+      ..Popup::new(title, lines)
+    }
+  }
+
+  fn is_menu(&self) -> bool {
+    !self.commands.is_empty()
+  }
+
+  pub fn on_key(&mut self, key: KeyPress) -> PopupAction {
+    match key {
+      KeyPress(KeyCode::Esc | KeyCode::Char('q'), _) => return PopupAction::Close,
+      KeyPress(KeyCode::Char('j') | KeyCode::Down, _) => self.move_by(1),
+      KeyPress(KeyCode::Char('k') | KeyCode::Up, _) => self.move_by(-1),
+      KeyPress(KeyCode::PageDown, _) => self.move_by(10),
+      KeyPress(KeyCode::PageUp, _) => self.move_by(-10),
+      KeyPress(KeyCode::Char('\n'), _) if self.is_menu() => {
+        return PopupAction::Run(self.commands[self.selected].clone())
+      }
+      KeyPress(KeyCode::Char(c @ '1'..='9'), _) => {
+        let i = c as usize - '1' as usize;
+        if let Some(cmd) = self.commands.get(i) {
+          return PopupAction::Run(cmd.clone());
+        }
+      }
+      _ => {}
+    }
+    PopupAction::Keep
+  }
+
+  /// Move the selection in menus, scroll otherwise
+  fn move_by(&mut self, n: isize) {
+    let max = self.lines.len().saturating_sub(1);
+    if self.is_menu() {
+      self.selected = self.selected.saturating_add_signed(n).min(max);
+    } else {
+      self.scroll = self.scroll.saturating_add_signed(n).min(max);
+    }
+  }
+
+  pub fn draw(&self, f: &mut Frame, area: Rect) {
+    let area = centered_rect(area);
+    let text: Vec<Line> = self
+      .lines
+      .iter()
+      .enumerate()
+      .map(|(i, l)| {
+        let style = if self.is_menu() && i == self.selected {
+          Style::default().add_modifier(Modifier::REVERSED)
+        } else {
+          Style::default()
+        };
+        Line::from(Span::styled(l.as_str(), style))
+      })
+      .collect();
+    // Keep the selection inside the borders
+    let height = area.height.saturating_sub(2) as usize;
+    let scroll = if self.is_menu() {
+      (self.selected + 1).saturating_sub(height)
+    } else {
+      self.scroll
+    };
+    let block = Block::default()
+      .borders(Borders::ALL)
+      .title(self.title.as_str());
+    let para = Paragraph::new(text)
+      .block(block)
+      .scroll((scroll as u16, 0));
+    f.render_widget(Clear, area);
+    f.render_widget(para, area);
+  }
+}
+
+/// A rect covering most of `area`, leaving a small margin around it
+fn centered_rect(area: Rect) -> Rect {
+  let width = (area.width * 9 / 10).max(area.width.min(20));
+  let height = (area.height * 9 / 10).max(area.height.min(5));
+  Rect {
+    x: area.x + (area.width - width) / 2,
+    y: area.y + (area.height - height) / 2,
+    width,
+    height,
+  }
+}
@@ -0,0 +1,161 @@
+use crate::config::Config;
+use ratatui::layout::Rect;
+use ratatui::style::Modifier;
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Borders, Paragraph, Wrap};
+use ratatui::Frame;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// At most this many lines are shown
+const MAX_LINES: usize = 200;
+/// At most this much of a file is read, so long lines can't read it all
+const MAX_READ: u64 = 64 * 1024;
+/// Files with a NUL byte this close to the start are considered binary
+const BINARY_CHECK: usize = 8 * 1024;
+
+/// The start of the selected file, shown next to the tree with `preview`
+pub struct Preview {
+  /// The file the lines were read from
+  pub path: PathBuf,
+  lines: Vec<String>,
+}
+
+impl Preview {
+  /// Read the start of `path`, unless it's larger than `max_bytes` when that
+  /// isn't 0. Directories list their entries, unreadable files have no lines
+  pub fn load(path: &Path, max_bytes: u64) -> Preview {
+    let lines = match std::fs::metadata(path) {
+      Ok(md) if md.is_file() && max_bytes > 0 && md.len() > max_bytes => {
+        vec![format!("<too large: {} bytes>", md.len())]
+      }
+      Ok(md) if md.is_file() => read_lines(path),
+      Ok(md) if md.is_dir() => list_dir(path),
+      _ => Vec::new(),
+    };
+    Preview {
+      path: path.to_path_buf(),
+      lines,
+    }
+  }
+
+  /// Rows taken up by the lines in `width` columns, roughly when wrapping
+  fn rows(&self, width: u16, wrap: bool) -> usize {
+    if !wrap || width == 0 {
+      return self.lines.len();
+    }
+    let width = width as usize;
+    self
+      .lines
+      .iter()
+      .map(|l| l.chars().count().div_ceil(width).max(1))
+      .sum()
+  }
+
+  /// Draw the preview, keeping `scroll` within the text
+  pub fn draw(
+    &self,
+    f: &mut Frame,
+    area: Rect,
+    conf: &Config,
+    scroll: &mut u16,
+    wrap: bool,
+    focused: bool,
+  ) {
+    let block = Block::default()
+      .borders(Borders::LEFT)
+      .title(if wrap { " preview [wrap]" } else { " preview" });
+    // Keys go to the preview while it's highlighted
+    let block = if focused {
+      block.border_style(conf.highlight_style.add_modifier(Modifier::BOLD))
+    } else {
+      block
+    };
+    let inner = block.inner(area);
+    let max = self.rows(inner.width, wrap).saturating_sub(1);
+    *scroll = (*scroll).min(max.min(u16::MAX as usize) as u16);
+    let text: Vec<Line> = self.lines.iter().map(|l| Line::from(l.as_str())).collect();
+    let para = Paragraph::new(text).block(block).scroll((*scroll, 0));
+    let para = if wrap {
+      para.wrap(Wrap { trim: false })
+    } else {
+      para
+    };
+    f.render_widget(para, area);
+  }
+}
+
+/// The first lines of the file at `path`, with a placeholder for binary files
+fn read_lines(path: &Path) -> Vec<String> {
+  let mut bytes = Vec::new();
+  if let Ok(file) = std::fs::File::open(path) {
+    let _ = file.take(MAX_READ).read_to_end(&mut bytes);
+  }
+  if bytes[..bytes.len().min(BINARY_CHECK)].contains(&0) {
+    return vec!["<binary>".to_string()];
+  }
+  String::from_utf8_lossy(&bytes)
+    .lines()
+    .take(MAX_LINES)
+    .map(|l| {
+      // Control characters would be written to the terminal as is
+      l.replace('\t', "    ")
+        .chars()
+        .map(|c| if c.is_control() { char::REPLACEMENT_CHARACTER } else { c })
+        .collect()
+    })
+    .collect()
+}
+
+/// The names in the directory at `path`, sorted, with a `/` after directories
+fn list_dir(path: &Path) -> Vec<String> {
+  let Ok(entries) = std::fs::read_dir(path) else {
+    return Vec::new();
+  };
+  let mut names: Vec<String> = entries
+    .flatten()
+    .map(|e| {
+      let name = e.file_name().to_string_lossy().to_string();
+      if e.path().is_dir() {
+        name + "/"
+      } else {
+        name
+      }
+    })
+    .collect();
+  names.sort();
+  names.truncate(MAX_LINES);
+  names
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::preview::Preview;
+
+  #[test]
+  fn load_and_rows() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("f");
+    std::fs::write(&file, "short\n\tindented\n0123456789\n").unwrap();
+    let preview = Preview::load(&file, 0);
+    assert_eq!(preview.lines, ["short", "    indented", "0123456789"]);
+    assert_eq!(preview.rows(4, false), 3);
+    assert_eq!(preview.rows(4, true), 2 + 3 + 3);
+
+    std::fs::create_dir(dir.path().join("sub")).unwrap();
+    assert_eq!(Preview::load(dir.path(), 0).lines, ["f", "sub/"]);
+    assert!(Preview::load(&dir.path().join("sub"), 0).lines.is_empty());
+  }
+
+  #[test]
+  fn placeholders() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("f");
+    std::fs::write(&file, "text\x1b[2J\n").unwrap();
+    assert_eq!(Preview::load(&file, 0).lines, ["text\u{FFFD}[2J"]);
+    assert_eq!(Preview::load(&file, 5).lines, ["<too large: 9 bytes>"]);
+    assert_eq!(Preview::load(&file, 9).lines.len(), 1);
+    std::fs::write(&file, b"ELF\0\x01\n").unwrap();
+    assert_eq!(Preview::load(&file, 0).lines, ["<binary>"]);
+  }
+}
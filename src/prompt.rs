@@ -1,4 +1,6 @@
 use std::collections::HashMap;
+use std::iter;
+use std::path::Path;
 
 use crate::commands::Command;
 use crossterm::event::{KeyCode};
@@ -16,12 +18,27 @@ pub trait Prompt {
   fn on_cancel(&mut self) -> Option<Command> {
     None
   }
+  /// Candidates for the last whitespace-delimited word of the input, cycled
+  /// through with Tab and Shift-Tab
   fn on_complete(&mut self, _: &str) -> Vec<String> {
     Vec::new()
   }
   fn init_text(&self) -> String {
     String::new()
   }
+  /// Called with the input whenever it changes. The result is shown next to it
+  fn on_change(&mut self, _: &str) -> Option<String> {
+    None
+  }
+  /// Whether to remember the input for the next time this prompt is shown.
+  /// Confirmations don't need to
+  fn keeps_history(&self) -> bool {
+    true
+  }
+  /// Called after `on_change`, for a command to run right away
+  fn on_edit(&mut self, _: &str) -> Option<Command> {
+    None
+  }
 }
 
 struct PromptState<'a> {
@@ -29,12 +46,54 @@ struct PromptState<'a> {
   textarea: TextArea<'a>,
   history: Vec<String>,
   hist_index: usize,
+  /// What `Prompt::on_change` returned for the current input
+  preview: Option<String>,
+  completion: Option<Completion>,
+}
+
+/// Completions being cycled through
+struct Completion {
+  /// The input before the completed word
+  head: String,
+  candidates: Vec<String>,
+  index: usize,
+}
+
+/// The word `Prompt::on_complete` completes
+pub fn last_word(input: &str) -> &str {
+  input.rsplit(char::is_whitespace).next().unwrap_or_default()
+}
+
+/// Completions of the path `word` relative to `dir`, with directories ending in
+/// `/`. Hidden entries are only offered once the name starts with a dot
+pub fn complete_path(dir: &Path, word: &str) -> Vec<String> {
+  let (parent, name) = match word.rfind('/') {
+    Some(i) => word.split_at(i + 1),
+    None => ("", word),
+  };
+  let Ok(entries) = std::fs::read_dir(dir.join(parent)) else {
+    return Vec::new();
+  };
+  let mut res: Vec<_> = entries
+    .filter_map(Result::ok)
+    .filter_map(|e| {
+      let file_name = e.file_name().to_string_lossy().to_string();
+      if !file_name.starts_with(name) || (file_name.starts_with('.') && !name.starts_with('.')) {
+        return None;
+      }
+      // Follows links, so links to directories complete like directories
+      let slash = if e.path().is_dir() { "/" } else { "" };
+      Some(format!("{}{}{}", parent, file_name, slash))
+    })
+    .collect();
+  res.sort();
+  res
 }
 //pub fn input(&mut self, input: impl Into<Input>) -> bool
 // self.textarea.input(input);
-impl Into<Input> for KeyPress {
-  fn into(self) -> Input {
-    self.to_input()
+impl From<KeyPress> for Input {
+  fn from(k: KeyPress) -> Input {
+    k.to_input()
   }
 }
 impl<'a> PromptState<'a> {
@@ -42,39 +101,98 @@ impl<'a> PromptState<'a> {
     history.insert(0, String::new());
     let mut textarea = TextArea::new(vec![prompt.init_text()]);
     textarea.move_cursor(CursorMove::End);
-    PromptState {
+    let mut state = PromptState {
       textarea,
       prompt,
       history,
       hist_index: 0,
-    }
+      preview: None,
+      completion: None,
+    };
+    state.preview = state.prompt.on_change(&state.textarea.lines()[0]);
+    state
+  }
+
+  fn input_changed(&mut self) -> Option<Command> {
+    let input = self.textarea.lines()[0].as_str();
+    self.preview = self.prompt.on_change(input);
+    self.prompt.on_edit(input)
   }
   /// Returns true if the prompt should be exited
   pub fn on_key(&mut self, key: KeyPress) -> (bool, Option<Command>) {
+    match key {
+      KeyPress(KeyCode::Tab, _) => return (false, self.complete(1)),
+      KeyPress(KeyCode::BackTab, _) => return (false, self.complete(-1)),
+      _ => self.completion = None,
+    }
     match key {
       KeyPress(KeyCode::Char('\n'),_) => (true, self.submit()),
-      KeyPress(KeyCode::Up, _) => {
-        self.walk_history(1);
-        (false, None)
-      }
-      KeyPress(KeyCode::Down, _) => {
-        self.walk_history(-1);
-        (false, None)
-      }
+      KeyPress(KeyCode::Up, _) => (false, self.walk_history(1)),
+      KeyPress(KeyCode::Down, _) => (false, self.walk_history(-1)),
       KeyPress(KeyCode::Esc, _) => (true, self.cancel()),
       input => {
-        self.textarea.input(input);
-        self.history[0] = self.textarea.lines()[0].clone();
+        if self.textarea.input(input) {
+          self.history[0] = self.textarea.lines()[0].clone();
+          return (false, self.input_changed());
+        }
         (false, None)
       }
     }
   }
 
-  fn walk_history(&mut self, i: isize) {
+  /// Insert pasted text in one go. Prompts are single line, so line breaks
+  /// become spaces
+  pub fn on_paste(&mut self, text: &str) -> Option<Command> {
+    let text = text
+      .trim_end_matches(['\r', '\n'])
+      .replace("\r\n", " ")
+      .replace(['\r', '\n'], " ");
+    self.textarea.insert_str(text);
+    self.history[0] = self.textarea.lines()[0].clone();
+    self.input_changed()
+  }
+
+  /// Replace the last word with the next completion, or the previous one when
+  /// `step` is negative
+  fn complete(&mut self, step: isize) -> Option<Command> {
+    let completion = match self.completion.take() {
+      Some(mut c) => {
+        c.index = (c.index as isize + step).rem_euclid(c.candidates.len() as isize) as usize;
+        c
+      }
+      None => {
+        let input = self.textarea.lines()[0].as_str();
+        let word = last_word(input);
+        let candidates = self.prompt.on_complete(input);
+        if candidates.is_empty() {
+          return None;
+        }
+        let index = if step < 0 { candidates.len() - 1 } else { 0 };
+        Completion {
+          head: input[..input.len() - word.len()].to_string(),
+          candidates,
+          index,
+        }
+      }
+    };
+    let text = format!("{}{}", completion.head, completion.candidates[completion.index]);
+    self.textarea = TextArea::new(vec![text.clone()]);
+    self.textarea.move_cursor(CursorMove::End);
+    self.history[0] = text;
+    // A single candidate is taken as is, so the next Tab completes further
+    // inside of it
+    if completion.candidates.len() > 1 {
+      self.completion = Some(completion);
+    }
+    self.input_changed()
+  }
+
+  fn walk_history(&mut self, i: isize) -> Option<Command> {
     self.hist_index = self.hist_index.saturating_add_signed(i);
     self.hist_index = self.hist_index.clamp(0, self.history.len() - 1);
     self.textarea = TextArea::new(vec![self.history[self.hist_index].clone()]);
     self.textarea.move_cursor(CursorMove::End);
+    self.input_changed()
   }
 
   pub fn submit(&mut self) -> Option<Command> {
@@ -92,6 +210,31 @@ impl<'a> PromptState<'a> {
   }
 
   pub fn draw(&mut self, f: &mut Frame, rect: Rect) {
+    // The preview takes up to half the line on the right, cut off on the left
+    let rect = match &self.preview {
+      Some(preview) => {
+        let max = (rect.width / 2) as usize;
+        let len = preview.chars().count() + 1;
+        let text: String = if len > max {
+          let skip = len - max + 1;
+          iter::once('…').chain(preview.chars().skip(skip)).collect()
+        } else {
+          preview.clone()
+        };
+        let width = len.min(max) as u16;
+        let area = Rect {
+          x: rect.x + rect.width - width,
+          width,
+          ..rect
+        };
+        f.render_widget(Paragraph::new(format!(" {}", text)), area);
+        Rect {
+          width: rect.width - width,
+          ..rect
+        }
+      }
+      None => rect,
+    };
     let widget = self.textarea.widget();
     let prompt = self.prompt.prompt_text();
     let text = vec![Line::from(vec![Span::raw(prompt)])];
@@ -157,15 +300,44 @@ impl<'a> StatusLine<'a> {
       let (exit, cmd) = p.on_key(key);
       if exit {
         let p = self.prompt_state.take().unwrap();
-        let mut hist = p.history;
-        hist.dedup();
-        self.histories.insert(p.prompt.prompt_text().into(), hist);
+        if p.prompt.keeps_history() {
+          let mut hist = p.history;
+          hist.dedup();
+          self.histories.insert(p.prompt.prompt_text().into(), hist);
+        }
       }
       return (exit, cmd);
     }
     (false, None)
   }
 
+  /// Forward pasted text to the active prompt, if any
+  pub fn on_paste(&mut self, text: &str) -> Option<Command> {
+    self.prompt_state.as_mut().and_then(|p| p.on_paste(text))
+  }
+
+  /// All prompt histories, without empty entries and cut to `limit` entries
+  /// each unless it's 0
+  pub fn histories(&self, limit: usize) -> HashMap<String, Vec<String>> {
+    let limit = if limit == 0 { usize::MAX } else { limit };
+    self
+      .histories
+      .iter()
+      .map(|(prompt, hist)| {
+        let mut hist: Vec<_> = hist.iter().filter(|h| !h.is_empty()).cloned().collect();
+        hist.dedup();
+        hist.truncate(limit);
+        (prompt.clone(), hist)
+      })
+      .filter(|(_, hist)| !hist.is_empty())
+      .collect()
+  }
+
+  /// Restore histories saved with `histories`
+  pub fn set_histories(&mut self, histories: HashMap<String, Vec<String>>) {
+    self.histories = histories;
+  }
+
   pub fn prompt(&mut self, prompt: Box<dyn Prompt>) {
     self.info.clear();
     let hist = self
@@ -175,11 +347,17 @@ impl<'a> StatusLine<'a> {
     self.prompt_state = Some(PromptState::new(prompt, hist));
   }
 
-  pub fn draw(&mut self, f: &mut Frame, rect: Rect) {
+  /// Draw the prompt if one is open, otherwise the last message, or `idle`
+  /// when there is none
+  pub fn draw(&mut self, f: &mut Frame, rect: Rect, idle: &str) {
     if let Some(prompt) = &mut self.prompt_state {
       prompt.draw(f, rect);
     } else {
-      let text = vec![Line::from(vec![Span::raw(self.info.info_msg.as_str())])];
+      let msg = match self.info.info_msg.is_empty() {
+        true => idle,
+        false => self.info.info_msg.as_str(),
+      };
+      let text = vec![Line::from(vec![Span::raw(msg)])];
       let input = Paragraph::new(text);
       f.render_widget(input, rect);
     }
@@ -196,3 +374,89 @@ impl<'a> StatusLine<'a> {
   }
    */
 }
+
+#[cfg(test)]
+mod tests {
+  use crate::app::KeyPress;
+  use crate::commands::Command;
+  use crate::prompt::{complete_path, last_word, Prompt, StatusLine};
+  use crossterm::event::{KeyCode, KeyModifiers};
+  use std::collections::HashMap;
+  use std::path::PathBuf;
+
+  struct TestPrompt;
+
+  impl Prompt for TestPrompt {
+    fn prompt_text(&self) -> &str {
+      ">"
+    }
+    fn on_submit(&mut self, _: &str) -> Option<Command> {
+      None
+    }
+  }
+
+  fn submit(status: &mut StatusLine, text: &str) {
+    status.prompt(Box::new(TestPrompt));
+    for c in text.chars().chain(['\n']) {
+      status.on_key(KeyPress(KeyCode::Char(c), KeyModifiers::NONE));
+    }
+  }
+
+  #[test]
+  fn saved_histories() {
+    let mut status = StatusLine::new();
+    status.set_histories(HashMap::from([(">".to_string(), vec!["old".to_string()])]));
+    for text in ["a", "b", "b", "", "c"] {
+      submit(&mut status, text);
+    }
+    assert_eq!(status.histories(0)[">"], ["c", "b", "a", "old"]);
+    assert_eq!(status.histories(2)[">"], ["c", "b"]);
+  }
+
+  struct PathPrompt(PathBuf);
+
+  impl Prompt for PathPrompt {
+    fn prompt_text(&self) -> &str {
+      ">"
+    }
+    fn on_submit(&mut self, _: &str) -> Option<Command> {
+      None
+    }
+    fn on_complete(&mut self, input: &str) -> Vec<String> {
+      complete_path(&self.0, last_word(input))
+    }
+  }
+
+  #[test]
+  fn tab_completion() {
+    let dir = tempfile::tempdir().unwrap();
+    for d in ["src/bin", "src/util", ".hidden"] {
+      std::fs::create_dir_all(dir.path().join(d)).unwrap();
+    }
+    std::fs::write(dir.path().join("src/main.rs"), "").unwrap();
+    assert_eq!(complete_path(dir.path(), ""), ["src/"]);
+    assert_eq!(complete_path(dir.path(), "."), [".hidden/"]);
+    assert_eq!(complete_path(dir.path(), "nope/"), Vec::<String>::new());
+
+    let mut status = StatusLine::new();
+    status.prompt(Box::new(PathPrompt(dir.path().into())));
+    let key = |status: &mut StatusLine, code| {
+      status.on_key(KeyPress(code, KeyModifiers::NONE));
+      status.prompt_state.as_ref().unwrap().textarea.lines()[0].clone()
+    };
+    for c in "cd s".chars() {
+      key(&mut status, KeyCode::Char(c));
+    }
+    // Only the last word is replaced, and a single match is taken right away
+    assert_eq!(key(&mut status, KeyCode::Tab), "cd src/");
+    assert_eq!(key(&mut status, KeyCode::Tab), "cd src/bin/");
+    assert_eq!(key(&mut status, KeyCode::Tab), "cd src/main.rs");
+    assert_eq!(key(&mut status, KeyCode::Tab), "cd src/util/");
+    assert_eq!(key(&mut status, KeyCode::Tab), "cd src/bin/");
+    assert_eq!(key(&mut status, KeyCode::BackTab), "cd src/util/");
+    // Typing starts over from the new input
+    key(&mut status, KeyCode::Backspace);
+    assert_eq!(key(&mut status, KeyCode::Char('/')), "cd src/util/");
+    assert_eq!(key(&mut status, KeyCode::Tab), "cd src/util/");
+  }
+}
@@ -0,0 +1,188 @@
+use std::process::Child;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, TryRecvError};
+use std::sync::Arc;
+
+/// How a background task is stopped and waited for
+enum Handle {
+  Process(Child),
+  Thread {
+    cancel: Arc<AtomicBool>,
+    done: Receiver<Result<(), String>>,
+  },
+}
+
+struct Task {
+  name: String,
+  handle: Handle,
+  /// Only failures are reported, for tasks that run regularly
+  quiet: bool,
+}
+
+impl Task {
+  /// The result of the task, if it has finished
+  fn try_finish(&mut self) -> Option<Result<(), String>> {
+    match &mut self.handle {
+      Handle::Process(child) => match child.try_wait() {
+        Ok(Some(status)) if status.success() => Some(Ok(())),
+        Ok(Some(status)) => Some(Err(format!("failed with {}", status))),
+        Ok(None) => None,
+        Err(e) => Some(Err(e.to_string())),
+      },
+      Handle::Thread { done, .. } => match done.try_recv() {
+        Ok(res) => Some(res),
+        Err(TryRecvError::Empty) => None,
+        Err(TryRecvError::Disconnected) => Some(Err("stopped unexpectedly".to_string())),
+      },
+    }
+  }
+}
+
+/// Operations running in the background, most recent last
+#[derive(Default)]
+pub struct Tasks {
+  tasks: Vec<Task>,
+}
+
+impl Tasks {
+  /// Track a spawned process. It's waited for once it exits, so it doesn't
+  /// linger as a zombie
+  pub fn add_process(&mut self, name: &str, child: Child) {
+    self.tasks.push(Task {
+      name: name.to_string(),
+      handle: Handle::Process(child),
+      quiet: false,
+    });
+  }
+
+  /// Run `f` on a thread, only reporting it when it fails. It is passed a
+  /// flag that is set when the task is cancelled, and should return soon after
+  pub fn spawn_quiet<F>(&mut self, name: &str, f: F)
+  where
+    F: FnOnce(&AtomicBool) -> Result<(), String> + Send + 'static,
+  {
+    let cancel = Arc::new(AtomicBool::new(false));
+    let (tx, done) = channel();
+    let flag = cancel.clone();
+    std::thread::spawn(move || {
+      let _ = tx.send(f(&flag));
+    });
+    self.tasks.push(Task {
+      name: name.to_string(),
+      handle: Handle::Thread { cancel, done },
+      quiet: true,
+    });
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.tasks.is_empty()
+  }
+
+  /// Stop the most recently started task. Returns its name
+  pub fn cancel_last(&mut self) -> Option<String> {
+    let mut task = self.tasks.pop()?;
+    match &mut task.handle {
+      Handle::Process(child) => {
+        let _ = child.kill();
+        // Reap it so it doesn't linger as a zombie
+        let _ = child.wait();
+      }
+      // The thread is left to notice the flag and exit on its own
+      Handle::Thread { cancel, .. } => cancel.store(true, Ordering::Relaxed),
+    }
+    Some(task.name)
+  }
+
+  /// Forget about finished tasks, returning their names and results. Quiet
+  /// ones are only returned when they failed
+  pub fn poll(&mut self) -> Vec<(String, Result<(), String>)> {
+    let mut finished = Vec::new();
+    self.tasks.retain_mut(|task| match task.try_finish() {
+      Some(Ok(())) if task.quiet => false,
+      Some(res) => {
+        finished.push((task.name.clone(), res));
+        false
+      }
+      None => true,
+    });
+    finished
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::tasks::Tasks;
+  use std::sync::atomic::Ordering;
+  use std::time::{Duration, Instant};
+
+  /// Poll until a task finishes
+  fn wait(tasks: &mut Tasks) -> Vec<(String, Result<(), String>)> {
+    let start = Instant::now();
+    while start.elapsed() < Duration::from_secs(5) {
+      let finished = tasks.poll();
+      if !finished.is_empty() {
+        return finished;
+      }
+      std::thread::sleep(Duration::from_millis(10));
+    }
+    panic!("task did not finish");
+  }
+
+  #[test]
+  fn cancel_thread() {
+    let mut tasks = Tasks::default();
+    tasks.spawn_quiet("loop", |cancel| {
+      while !cancel.load(Ordering::Relaxed) {
+        std::thread::sleep(Duration::from_millis(1));
+      }
+      Err("cancelled".to_string())
+    });
+    assert!(tasks.poll().is_empty());
+    assert_eq!(tasks.cancel_last(), Some("loop".to_string()));
+    assert!(tasks.is_empty());
+    assert_eq!(tasks.cancel_last(), None);
+  }
+
+  #[test]
+  fn process_result() {
+    let mut tasks = Tasks::default();
+    let child = std::process::Command::new("sh").args(["-c", "exit 3"]).spawn().unwrap();
+    tasks.add_process("exit", child);
+    let finished = wait(&mut tasks);
+    assert_eq!(finished[0].0, "exit");
+    assert!(finished[0].1.as_ref().unwrap_err().contains('3'));
+    assert!(tasks.is_empty());
+  }
+
+  #[test]
+  fn cancel_process() {
+    let mut tasks = Tasks::default();
+    let child = std::process::Command::new("sleep").arg("10").spawn().unwrap();
+    tasks.add_process("sleep", child);
+    let start = Instant::now();
+    assert_eq!(tasks.cancel_last(), Some("sleep".to_string()));
+    assert!(start.elapsed() < Duration::from_secs(5));
+  }
+
+  #[test]
+  fn poll_results() {
+    let mut tasks = Tasks::default();
+    let child = std::process::Command::new("true").spawn().unwrap();
+    tasks.add_process("true", child);
+    assert_eq!(wait(&mut tasks), [("true".to_string(), Ok(()))]);
+    let child = std::process::Command::new("false").spawn().unwrap();
+    tasks.add_process("false", child);
+    let finished = wait(&mut tasks);
+    assert_eq!(finished[0].0, "false");
+    assert!(finished[0].1.is_err());
+    assert!(tasks.is_empty());
+
+    tasks.spawn_quiet("quiet", |_| Ok(()));
+    tasks.spawn_quiet("failing", |_| Err("failed".to_string()));
+    assert_eq!(wait(&mut tasks), [("failing".to_string(), Err("failed".to_string()))]);
+    while !tasks.is_empty() {
+      assert!(tasks.poll().is_empty());
+      std::thread::sleep(Duration::from_millis(1));
+    }
+  }
+}
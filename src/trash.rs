@@ -0,0 +1,231 @@
+use crate::util::copy::move_path;
+use path_absolutize::Absolutize;
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::io::Write;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::path::{Path, PathBuf};
+
+/// The trash to use: `configured` if set, otherwise the home trash from the
+/// XDG trash spec, `$XDG_DATA_HOME/Trash`
+pub fn trash_dir(configured: &str) -> PathBuf {
+  if !configured.is_empty() {
+    return PathBuf::from(configured);
+  }
+  xdg::BaseDirectories::new()
+    .map(|xdg| xdg.get_data_home())
+    .unwrap_or_else(|_| std::env::temp_dir())
+    .join("Trash")
+}
+
+/// Move `path` into `trash`, returning where it ended up. A `.trashinfo` file
+/// records where it came from, so it can be restored, also by other tools.
+/// When the trash is on another file system, `path` is copied and removed
+pub fn put(trash: &Path, path: &Path) -> Result<PathBuf, String> {
+  let path = path.absolutize().map_err(|e| e.to_string())?.to_path_buf();
+  let name = path
+    .file_name()
+    .ok_or_else(|| format!("{}: cannot be trashed", path.display()))?;
+  let files = trash.join("files");
+  for dir in [&files, &trash.join("info")] {
+    fs::create_dir_all(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+  }
+  // Like other tools, a taken name gets a number, and creating the info file
+  // is what claims it
+  for n in 1.. {
+    let mut candidate = name.to_os_string();
+    if n > 1 {
+      candidate.push(format!(".{}", n));
+    }
+    let trashed = files.join(candidate);
+    if fs::symlink_metadata(&trashed).is_ok() {
+      continue;
+    }
+    match write_info(&trashed, &path) {
+      Ok(()) => return finish_put(&path, &trashed).map(|_| trashed),
+      Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+      Err(e) => return Err(format!("{}: {}", info_path(&trashed).display(), e)),
+    }
+  }
+  unreachable!()
+}
+
+/// Move `path` to `trashed`, a path in a trash's `files` directory, as `put`
+/// would have. Used to redo trashing
+pub fn put_at(path: &Path, trashed: &Path) -> Result<(), String> {
+  if fs::symlink_metadata(trashed).is_ok() {
+    return Err(format!("{}: already exists", trashed.display()));
+  }
+  write_info(trashed, path).map_err(|e| format!("{}: {}", info_path(trashed).display(), e))?;
+  finish_put(path, trashed)
+}
+
+fn finish_put(path: &Path, trashed: &Path) -> Result<(), String> {
+  move_path(path, trashed).inspect_err(|_| {
+    let _ = fs::remove_file(info_path(trashed));
+  })
+}
+
+/// Move `trashed` back to where its `.trashinfo` says it came from, and
+/// remove that file. Returns the restored path
+pub fn restore(trashed: &Path) -> Result<PathBuf, String> {
+  let info = info_path(trashed);
+  let text = fs::read(&info).map_err(|e| format!("{}: {}", info.display(), e))?;
+  let original = parse_info(&text).ok_or_else(|| format!("{}: no path", info.display()))?;
+  // Relative paths are relative to the directory holding the trash
+  let original = match trashed.parent().and_then(Path::parent).and_then(Path::parent) {
+    Some(top) => top.join(original),
+    None => original,
+  };
+  if fs::symlink_metadata(&original).is_ok() {
+    return Err(format!("{}: already exists", original.display()));
+  }
+  if let Some(dir) = original.parent() {
+    fs::create_dir_all(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+  }
+  move_path(trashed, &original)?;
+  let _ = fs::remove_file(&info);
+  Ok(original)
+}
+
+/// `<trash>/info/<name>.trashinfo` for `<trash>/files/<name>`
+fn info_path(trashed: &Path) -> PathBuf {
+  let trash = trashed.parent().and_then(Path::parent).unwrap_or(Path::new(""));
+  let mut name = trashed.file_name().unwrap_or_default().to_os_string();
+  name.push(".trashinfo");
+  trash.join("info").join(name)
+}
+
+fn write_info(trashed: &Path, original: &Path) -> std::io::Result<()> {
+  let date = chrono::Local::now().format("%Y-%m-%dT%H:%M:%S");
+  let text = format!(
+    "[Trash Info]\nPath={}\nDeletionDate={}\n",
+    encode_path(original.as_os_str()),
+    date
+  );
+  let res = fs::OpenOptions::new()
+    .write(true)
+    .create_new(true)
+    .open(info_path(trashed))
+    .and_then(|mut f| f.write_all(text.as_bytes()));
+  // Don't leave a half written file claiming the name
+  if let Err(e) = &res {
+    if e.kind() != std::io::ErrorKind::AlreadyExists {
+      let _ = fs::remove_file(info_path(trashed));
+    }
+  }
+  res
+}
+
+/// The `Path` key of a `.trashinfo` file
+fn parse_info(text: &[u8]) -> Option<PathBuf> {
+  let mut lines = text.split(|&b| b == b'\n');
+  lines.find(|l| l.trim_ascii() == b"[Trash Info]")?;
+  lines
+    .take_while(|l| !l.starts_with(b"["))
+    .find_map(|l| l.strip_prefix(b"Path="))
+    .and_then(|v| decode_path(v.trim_ascii_end()))
+}
+
+/// Percent encode a path, as URLs are
+fn encode_path(path: &OsStr) -> String {
+  let mut res = String::new();
+  for &b in path.as_bytes() {
+    match b {
+      b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+        res.push(b as char)
+      }
+      _ => res.push_str(&format!("%{:02X}", b)),
+    }
+  }
+  res
+}
+
+fn decode_path(text: &[u8]) -> Option<PathBuf> {
+  let mut res = Vec::new();
+  let mut bytes = text.iter();
+  while let Some(&b) = bytes.next() {
+    if b == b'%' {
+      let hex = [*bytes.next()?, *bytes.next()?];
+      res.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+    } else {
+      res.push(b);
+    }
+  }
+  Some(PathBuf::from(OsString::from_vec(res)))
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::trash::{decode_path, encode_path, info_path, put, put_at, restore};
+  use std::ffi::OsStr;
+  use std::fs;
+  use std::os::unix::fs::MetadataExt;
+  use std::path::Path;
+
+  #[test]
+  fn path_encoding() {
+    let path = OsStr::new("/home/me/a b%ä.txt");
+    assert_eq!(encode_path(path), "/home/me/a%20b%25%C3%A4.txt");
+    assert_eq!(decode_path(encode_path(path).as_bytes()).unwrap(), Path::new(path));
+    assert!(decode_path(b"/a%2").is_none());
+  }
+
+  #[test]
+  fn trash_and_restore() {
+    let dir = tempfile::tempdir().unwrap();
+    let trash = dir.path().join("Trash");
+    let file = dir.path().join("a b");
+    fs::write(&file, "first").unwrap();
+
+    let trashed = put(&trash, &file).unwrap();
+    assert_eq!(trashed, trash.join("files/a b"));
+    assert!(!file.exists());
+    assert_eq!(fs::read_to_string(&trashed).unwrap(), "first");
+    let info = fs::read_to_string(trash.join("info/a b.trashinfo")).unwrap();
+    let expected = format!("[Trash Info]\nPath={}/a%20b\n", dir.path().display());
+    assert!(info.starts_with(&expected), "{}", info);
+    assert!(info.contains("\nDeletionDate="));
+
+    // A second entry of the same name doesn't replace the first
+    fs::write(&file, "second").unwrap();
+    let second = put(&trash, &file).unwrap();
+    assert_eq!(second, trash.join("files/a b.2"));
+    assert!(info_path(&second).exists());
+
+    // Restoring doesn't replace what took the original's place
+    fs::write(&file, "third").unwrap();
+    assert!(restore(&trashed).is_err());
+    fs::remove_file(&file).unwrap();
+    assert_eq!(restore(&trashed).unwrap(), file);
+    assert_eq!(fs::read_to_string(&file).unwrap(), "first");
+    assert!(!trash.join("info/a b.trashinfo").exists());
+
+    // Putting it back at the same place, like redo does
+    put_at(&file, &trashed).unwrap();
+    assert!(!file.exists() && trashed.exists());
+    assert!(info_path(&trashed).exists());
+  }
+
+  /// The copy fallback itself is tested in `util::copy`, with a rename that
+  /// fails as if crossing file systems
+  #[test]
+  #[ignore = "needs /dev/shm on another file system than the temp dir"]
+  fn trash_across_file_systems() {
+    let other = tempfile::tempdir_in("/dev/shm").unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    let dev = |p: &Path| fs::metadata(p).unwrap().dev();
+    assert_ne!(dev(other.path()), dev(dir.path()), "/dev/shm is on the same file system");
+    let trash = dir.path().join("Trash");
+    let sub = other.path().join("sub");
+    fs::create_dir(&sub).unwrap();
+    fs::write(sub.join("f"), "content").unwrap();
+
+    let trashed = put(&trash, &sub).unwrap();
+    assert!(!sub.exists());
+    assert_eq!(fs::read_to_string(trashed.join("f")).unwrap(), "content");
+    assert_eq!(restore(&trashed).unwrap(), sub);
+    assert_eq!(fs::read_to_string(sub.join("f")).unwrap(), "content");
+    assert!(!trashed.exists());
+  }
+}
@@ -0,0 +1,262 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// What to do when something being copied already exists at the destination
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Overwrite {
+  /// Report it as an error
+  #[default]
+  Never,
+  /// Leave the existing file alone
+  Skip,
+  /// Replace it. Directories are merged rather than replaced
+  Always,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CopyOptions {
+  pub overwrite: Overwrite,
+  /// Copy what symlinks point to instead of the links themselves
+  pub follow_links: bool,
+}
+
+/// A file that couldn't be copied, and why
+pub type CopyError = (PathBuf, String);
+
+/// Copy `src` to `dst`, including everything inside directories. Files keep
+/// their permissions. Copying continues past files that fail, and all
+/// failures are returned
+pub fn copy_recursive(src: &Path, dst: &Path, opts: CopyOptions) -> Result<(), Vec<CopyError>> {
+  let mut errors = Vec::new();
+  if dst.starts_with(src) && src.is_dir() {
+    errors.push((src.to_path_buf(), "cannot copy a directory into itself".to_string()));
+  } else {
+    copy_entry(src, dst, opts, &mut errors);
+  }
+  if errors.is_empty() {
+    Ok(())
+  } else {
+    Err(errors)
+  }
+}
+
+/// Move `src` to `dst`, copying and removing it when they are on different
+/// file systems
+pub fn move_path(src: &Path, dst: &Path) -> Result<(), String> {
+  move_with(src, dst, |src, dst| fs::rename(src, dst))
+}
+
+/// `move_path` with `rename` in place of `fs::rename`, so moves across file
+/// systems can be tested on one
+fn move_with(
+  src: &Path,
+  dst: &Path,
+  rename: impl FnOnce(&Path, &Path) -> std::io::Result<()>,
+) -> Result<(), String> {
+  match rename(src, dst) {
+    Ok(()) => Ok(()),
+    Err(e) if e.kind() == std::io::ErrorKind::CrossesDevices => {
+      copy_recursive(src, dst, CopyOptions::default()).map_err(|e| describe_errors(&e))?;
+      let res = if src.is_dir() {
+        std::fs::remove_dir_all(src)
+      } else {
+        std::fs::remove_file(src)
+      };
+      res.map_err(|e| format!("{}: copied, but not removed: {}", src.display(), e))
+    }
+    Err(e) => Err(format!("{}: {}", src.display(), e)),
+  }
+}
+
+/// Delete `path` for good, with everything inside it if it's a directory.
+/// Symlinks are removed, not what they point to
+pub fn remove_path(path: &Path) -> Result<(), String> {
+  let res = match fs::symlink_metadata(path) {
+    Ok(md) if md.is_dir() => fs::remove_dir_all(path),
+    Ok(_) => fs::remove_file(path),
+    Err(e) => Err(e),
+  };
+  res.map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+/// All errors on one line, for the statusline
+pub fn describe_errors(errors: &[CopyError]) -> String {
+  let errors: Vec<_> = errors
+    .iter()
+    .map(|(path, e)| format!("{}: {}", path.display(), e))
+    .collect();
+  errors.join(", ")
+}
+
+fn copy_entry(src: &Path, dst: &Path, opts: CopyOptions, errors: &mut Vec<CopyError>) {
+  let mut fail = |path: &Path, e: String| errors.push((path.to_path_buf(), e));
+  let md = if opts.follow_links {
+    fs::metadata(src)
+  } else {
+    fs::symlink_metadata(src)
+  };
+  let md = match md {
+    Ok(md) => md,
+    Err(e) => return fail(src, e.to_string()),
+  };
+  // Don't follow a link at the destination either, it's replaced as a whole,
+  // and counts even if it's broken
+  if let Ok(existing) = fs::symlink_metadata(dst) {
+    let merge = md.is_dir() && existing.is_dir();
+    match opts.overwrite {
+      _ if merge => {}
+      Overwrite::Never => return fail(dst, "already exists".to_string()),
+      Overwrite::Skip => return,
+      Overwrite::Always if existing.is_dir() => return fail(dst, "is a directory".to_string()),
+      Overwrite::Always => {
+        if let Err(e) = fs::remove_file(dst) {
+          return fail(dst, e.to_string());
+        }
+      }
+    }
+  }
+
+  if md.file_type().is_symlink() {
+    let res = fs::read_link(src).and_then(|target| std::os::unix::fs::symlink(target, dst));
+    if let Err(e) = res {
+      fail(src, e.to_string());
+    }
+  } else if md.is_dir() {
+    if let Err(e) = fs::create_dir(dst) {
+      if e.kind() != std::io::ErrorKind::AlreadyExists {
+        return fail(dst, e.to_string());
+      }
+    }
+    let entries = match fs::read_dir(src) {
+      Ok(entries) => entries,
+      Err(e) => return fail(src, e.to_string()),
+    };
+    for entry in entries {
+      match entry {
+        Ok(entry) => copy_entry(&entry.path(), &dst.join(entry.file_name()), opts, errors),
+        Err(e) => errors.push((src.to_path_buf(), e.to_string())),
+      }
+    }
+    // Only now, so read-only directories can be filled first
+    if let Err(e) = fs::set_permissions(dst, md.permissions()) {
+      errors.push((dst.to_path_buf(), e.to_string()));
+    }
+  } else if let Err(e) = fs::copy(src, dst) {
+    fail(src, e.to_string());
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::util::copy::{copy_recursive, move_with, CopyOptions, Overwrite};
+  use std::fs;
+  use std::os::unix::fs::{symlink, PermissionsExt};
+  use std::path::Path;
+
+  fn read(path: &Path) -> String {
+    fs::read_to_string(path).unwrap()
+  }
+
+  #[test]
+  fn nested_dirs_and_links() {
+    let dir = tempfile::tempdir().unwrap();
+    let src = dir.path().join("src");
+    fs::create_dir_all(src.join("a/b")).unwrap();
+    fs::write(src.join("a/b/file"), "content").unwrap();
+    fs::set_permissions(src.join("a/b/file"), fs::Permissions::from_mode(0o751)).unwrap();
+    symlink("a/b/file", src.join("link")).unwrap();
+
+    let dst = dir.path().join("dst");
+    assert_eq!(copy_recursive(&src, &dst, CopyOptions::default()), Ok(()));
+    assert_eq!(read(&dst.join("a/b/file")), "content");
+    let mode = fs::metadata(dst.join("a/b/file")).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o751);
+    // The link is copied as a link, still relative
+    assert_eq!(fs::read_link(dst.join("link")).unwrap(), Path::new("a/b/file"));
+
+    let followed = dir.path().join("followed");
+    let opts = CopyOptions {
+      follow_links: true,
+      ..Default::default()
+    };
+    assert_eq!(copy_recursive(&src, &followed, opts), Ok(()));
+    assert!(!fs::symlink_metadata(followed.join("link")).unwrap().file_type().is_symlink());
+    assert_eq!(read(&followed.join("link")), "content");
+
+    let errors = copy_recursive(&src, &src.join("a/inside"), CopyOptions::default()).unwrap_err();
+    assert_eq!(errors.len(), 1);
+    assert!(!src.join("a/inside").exists());
+  }
+
+  #[test]
+  fn existing_destination() {
+    let dir = tempfile::tempdir().unwrap();
+    let (src, dst) = (dir.path().join("src"), dir.path().join("dst"));
+    fs::write(&src, "new").unwrap();
+    fs::write(&dst, "old").unwrap();
+    let errors = copy_recursive(&src, &dst, CopyOptions::default()).unwrap_err();
+    assert_eq!(errors, [(dst.clone(), "already exists".to_string())]);
+    assert_eq!(read(&dst), "old");
+
+    // Not even a broken link is replaced
+    fs::remove_file(&dst).unwrap();
+    symlink("missing", &dst).unwrap();
+    assert!(copy_recursive(&src, &dst, CopyOptions::default()).is_err());
+    assert_eq!(fs::read_link(&dst).unwrap(), Path::new("missing"));
+  }
+
+  #[test]
+  fn overwrite_conflicts() {
+    let dir = tempfile::tempdir().unwrap();
+    let (src, dst) = (dir.path().join("src"), dir.path().join("dst"));
+    fs::create_dir_all(src.join("sub")).unwrap();
+    fs::write(src.join("new"), "new").unwrap();
+    fs::write(src.join("sub/both"), "new").unwrap();
+    fs::create_dir_all(dst.join("sub")).unwrap();
+    fs::write(dst.join("sub/both"), "old").unwrap();
+    let opts = |overwrite| CopyOptions {
+      overwrite,
+      ..Default::default()
+    };
+
+    // Conflicts are reported, everything else is still copied
+    let errors = copy_recursive(&src, &dst, opts(Overwrite::Never)).unwrap_err();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].0, dst.join("sub/both"));
+    assert_eq!(read(&dst.join("new")), "new");
+    assert_eq!(read(&dst.join("sub/both")), "old");
+
+    assert_eq!(copy_recursive(&src, &dst, opts(Overwrite::Skip)), Ok(()));
+    assert_eq!(read(&dst.join("sub/both")), "old");
+
+    assert_eq!(copy_recursive(&src, &dst, opts(Overwrite::Always)), Ok(()));
+    assert_eq!(read(&dst.join("sub/both")), "new");
+
+    // A file never replaces a directory
+    fs::write(src.join("f"), "").unwrap();
+    fs::create_dir(dst.join("f")).unwrap();
+    assert!(copy_recursive(&src.join("f"), &dst.join("f"), opts(Overwrite::Always)).is_err());
+  }
+
+  #[test]
+  fn move_across_file_systems() {
+    let dir = tempfile::tempdir().unwrap();
+    let (src, dst) = (dir.path().join("src"), dir.path().join("dst"));
+    fs::create_dir_all(src.join("sub")).unwrap();
+    fs::write(src.join("sub/f"), "content").unwrap();
+    symlink("sub/f", src.join("link")).unwrap();
+    // What renaming to another file system fails with
+    let rename = |_: &Path, _: &Path| Err(std::io::ErrorKind::CrossesDevices.into());
+
+    assert_eq!(move_with(&src, &dst, rename), Ok(()));
+    assert!(!src.exists());
+    assert_eq!(read(&dst.join("sub/f")), "content");
+    assert_eq!(fs::read_link(dst.join("link")).unwrap(), Path::new("sub/f"));
+
+    // Nothing is removed when the copy fails
+    fs::write(&src, "").unwrap();
+    assert!(move_with(&src, &dst, rename).is_err());
+    assert!(src.exists());
+  }
+}
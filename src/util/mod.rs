@@ -1,6 +1,17 @@
+pub mod copy;
 mod event;
 
 use ratatui::widgets::ListState;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use path_absolutize::Absolutize;
+use std::ffi::OsStr;
+use std::iter::Peekable;
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::str::Chars;
+use std::time::Duration;
 
 pub struct StatefulList<T> {
   pub state: ListState,
@@ -24,22 +35,52 @@ impl<T> StatefulList<T> {
   }
 
   pub fn nth(&mut self, n: usize) {
-    self.state.select(Some((n).min(self.items.len() - 1)));
+    if let Some(last) = self.items.len().checked_sub(1) {
+      self.state.select(Some(n.min(last)));
+    }
   }
 
   pub fn next(&mut self) {
     if let Some(i) = self.state.selected() {
-      self.state.select(Some((i + 1).min(self.items.len() - 1)));
+      if let Some(last) = self.items.len().checked_sub(1) {
+        self.state.select(Some((i + 1).min(last)));
+      }
     } else {
-      self.state.select(Some(0))
+      self.first()
     }
   }
 
+  /// Select the first item, if there is one
+  pub fn first(&mut self) {
+    if !self.items.is_empty() {
+      self.state.select(Some(0));
+    }
+  }
+
+  /// Select the last item, if there is one
+  pub fn last(&mut self) {
+    if let Some(last) = self.items.len().checked_sub(1) {
+      self.state.select(Some(last));
+    }
+  }
+
+  /// Move the selection `n` items down, stopping at the last
+  pub fn forward(&mut self, n: usize) {
+    let index = self.index().map_or(0, |i| i.saturating_add(n));
+    self.nth(index);
+  }
+
+  /// Move the selection `n` items up, stopping at the first
+  pub fn back(&mut self, n: usize) {
+    let index = self.index().map_or(0, |i| i.saturating_sub(n));
+    self.nth(index);
+  }
+
   pub fn previous(&mut self) {
     if let Some(i) = self.state.selected() {
       self.state.select(Some(i.saturating_sub(1)));
     } else {
-      self.state.select(Some(0))
+      self.first()
     }
   }
 
@@ -59,4 +100,635 @@ impl<T> StatefulList<T> {
   pub fn select_index(&mut self, index: usize) {
     self.state.select(Some(index));
   }
-}
\ No newline at end of file
+
+  /// Scroll the least needed for `margin` lines to be visible above and below
+  /// the selection in a list `height` lines high, or as many as there are
+  pub fn scroll_to_selected(&mut self, height: usize, margin: usize) {
+    let Some(selected) = self.index() else {
+      return;
+    };
+    let margin = margin.min(height.saturating_sub(1) / 2);
+    let lowest = (selected + margin + 1).saturating_sub(height);
+    let highest = selected.saturating_sub(margin);
+    let last = self.items.len().saturating_sub(height);
+    let offset = self.state.offset().clamp(lowest, highest).min(last);
+    *self.state.offset_mut() = offset;
+  }
+}
+/// Clean up a file name typed by the user.
+///
+/// Trailing whitespace is always removed and empty names are rejected.
+/// `allow_dirs` allows `/` to create nested paths. With `strict`, also
+/// reject leading whitespace, control characters and `.`/`..` components.
+pub fn sanitize_name(name: &str, allow_dirs: bool, strict: bool) -> Result<String, String> {
+  let name = name.trim_end();
+  if name.is_empty() {
+    return Err("name cannot be empty".to_string());
+  }
+  if !allow_dirs && name.contains('/') {
+    return Err(format!("name cannot contain '/': {}", name));
+  }
+  if strict {
+    if name.starts_with(char::is_whitespace) {
+      return Err(format!("name cannot start with whitespace: '{}'", name));
+    }
+    if name.chars().any(char::is_control) {
+      return Err("name cannot contain control characters".to_string());
+    }
+    let mut parts = name.split('/').collect::<Vec<_>>();
+    if name.ends_with('/') {
+      parts.pop();
+    }
+    for part in parts {
+      match part {
+        "" => return Err(format!("name contains an empty path component: {}", name)),
+        "." | ".." => return Err(format!("name cannot contain '{}' components", part)),
+        p if p != p.trim() => {
+          return Err(format!("path component has surrounding whitespace: '{}'", p))
+        }
+        _ => {}
+      }
+    }
+  }
+  Ok(name.to_string())
+}
+
+/// Where renaming `src` to the typed `name` moves it. `name` is relative to
+/// the directory of `src` and may contain `/` to move it elsewhere
+pub fn rename_target(src: &Path, name: &str, strict: bool) -> Result<PathBuf, String> {
+  let name = sanitize_name(name, true, strict)?;
+  let dir = src.parent().unwrap_or(Path::new("/"));
+  let dst = dir.join(name.trim_end_matches('/'));
+  dst
+    .absolutize()
+    .map(PathBuf::from)
+    .map_err(|e| e.to_string())
+}
+
+/// Move `src` to `dst`, creating missing directories. An existing `dst` is
+/// only replaced with `overwrite`
+pub fn rename_path(src: &Path, dst: &Path, overwrite: bool) -> std::io::Result<()> {
+  if !overwrite && fs::symlink_metadata(dst).is_ok() {
+    return Err(std::io::ErrorKind::AlreadyExists.into());
+  }
+  if let Some(dir) = dst.parent() {
+    fs::create_dir_all(dir)?;
+  }
+  fs::rename(src, dst)
+}
+
+/// A size in bytes in at most 5 characters, like `ls -h`: `512`, `1.2K`, `34M`
+pub fn human_size(bytes: u64) -> String {
+  if bytes < 1024 {
+    return bytes.to_string();
+  }
+  let mut size = bytes as f64;
+  for unit in ["K", "M", "G", "T", "P"] {
+    size /= 1024.0;
+    if size < 10.0 {
+      return format!("{:.1}{}", size, unit);
+    } else if size < 1024.0 {
+      return format!("{:.0}{}", size, unit);
+    }
+  }
+  format!("{:.0}E", size / 1024.0)
+}
+
+/// How long ago something was, in its largest unit: `5s`, `2h`, `3d`, `6w`, `1y`
+pub fn relative_time(age: Duration) -> String {
+  let secs = age.as_secs();
+  let units = [("y", 365 * 86400), ("w", 7 * 86400), ("d", 86400), ("h", 3600), ("m", 60)];
+  units
+    .iter()
+    .find(|(_, len)| secs >= *len)
+    .map(|(unit, len)| format!("{}{}", secs / len, unit))
+    .unwrap_or_else(|| format!("{}s", secs))
+}
+
+/// Replace the contents of `path` in one step, so readers never see a partly
+/// written file
+pub fn write_atomic(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+  let name = path.file_name().ok_or(std::io::ErrorKind::InvalidInput)?;
+  let mut tmp_name = OsStr::new(".").to_os_string();
+  tmp_name.push(name);
+  tmp_name.push(format!(".{}.tmp", std::process::id()));
+  let tmp = path.with_file_name(tmp_name);
+  fs::write(&tmp, contents)
+    .and_then(|_| fs::rename(&tmp, path))
+    .inspect_err(|_| {
+      let _ = fs::remove_file(&tmp);
+    })
+}
+
+/// Score how well `query` matches `text` as a subsequence, higher being better,
+/// or None if it doesn't. Matches right after a separator or after another
+/// match score higher. Ignores case unless `query` has upper case letters
+pub fn fuzzy_score(query: &str, text: &str) -> Option<i32> {
+  let ignore_case = !query.chars().any(char::is_uppercase);
+  let text: Vec<char> = match ignore_case {
+    true => text.chars().flat_map(char::to_lowercase).collect(),
+    false => text.chars().collect(),
+  };
+  let mut score = 0;
+  let mut prev: Option<usize> = None;
+  for q in query.chars() {
+    let start = prev.map_or(0, |p| p + 1);
+    let i = start + text.get(start..)?.iter().position(|&c| c == q)?;
+    score += 1;
+    // Right after the previous match, or at the start of a word
+    let follows = i > 0 && prev == Some(i - 1);
+    if follows || i == 0 || matches!(text[i - 1], '/' | '_' | '-' | '.' | ' ') {
+      score += 4;
+    }
+    if prev.is_some_and(|p| p + 1 < i) {
+      score -= 1;
+    }
+    prev = Some(i);
+  }
+  Some(score)
+}
+
+/// Byte ranges of the places `pattern` appears in `text`. Ignores case unless
+/// `pattern` has upper case letters
+pub fn match_ranges(text: &str, pattern: &str) -> Vec<Range<usize>> {
+  let ignore_case = !pattern.chars().any(char::is_uppercase);
+  let fold = |c: char| match ignore_case {
+    true => c.to_lowercase().next().unwrap_or(c),
+    false => c,
+  };
+  let pattern: Vec<char> = pattern.chars().map(fold).collect();
+  let chars: Vec<(usize, char)> = text.char_indices().map(|(i, c)| (i, fold(c))).collect();
+  let mut res = Vec::new();
+  let mut i = 0;
+  while !pattern.is_empty() && i + pattern.len() <= chars.len() {
+    if chars[i..i + pattern.len()].iter().map(|&(_, c)| c).eq(pattern.iter().copied()) {
+      let end = chars.get(i + pattern.len()).map_or(text.len(), |&(e, _)| e);
+      res.push(chars[i].0..end);
+      i += pattern.len();
+    } else {
+      i += 1;
+    }
+  }
+  res
+}
+
+/// The number of characters to insert, remove or replace to turn `a` into `b`
+pub fn edit_distance(a: &str, b: &str) -> usize {
+  let b: Vec<char> = b.chars().collect();
+  let mut prev: Vec<usize> = (0..=b.len()).collect();
+  for (i, ca) in a.chars().enumerate() {
+    let mut row = vec![i + 1];
+    for (j, &cb) in b.iter().enumerate() {
+      let replace = prev[j] + usize::from(ca != cb);
+      row.push(replace.min(prev[j + 1] + 1).min(row[j] + 1));
+    }
+    prev = row;
+  }
+  prev[b.len()]
+}
+
+/// `dir/name`, or if that exists, the first free `dir/stem_N.ext` counting from 1
+pub fn unique_path(dir: &Path, name: &OsStr) -> PathBuf {
+  let path = dir.join(name);
+  if fs::symlink_metadata(&path).is_err() {
+    return path;
+  }
+  let name = Path::new(name);
+  let stem = name.file_stem().unwrap_or(name.as_os_str()).to_string_lossy();
+  let ext = name
+    .extension()
+    .map(|e| format!(".{}", e.to_string_lossy()))
+    .unwrap_or_default();
+  (1..)
+    .map(|i| dir.join(format!("{}_{}{}", stem, i, ext)))
+    .find(|p| fs::symlink_metadata(p).is_err())
+    .unwrap()
+}
+
+/// Match `name` against a shell-style pattern, where `*` matches any run of
+/// characters and `?` a single one
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+  fn matches(p: &[char], n: &[char]) -> bool {
+    match p.split_first() {
+      None => n.is_empty(),
+      Some(('*', rest)) => (0..=n.len()).any(|i| matches(rest, &n[i..])),
+      Some(('?', rest)) => !n.is_empty() && matches(rest, &n[1..]),
+      Some((c, rest)) => n.first() == Some(c) && matches(rest, &n[1..]),
+    }
+  }
+  let p: Vec<char> = pattern.chars().collect();
+  let n: Vec<char> = name.chars().collect();
+  matches(&p, &n)
+}
+
+/// Parse `path<TAB>badge` lines, with paths relative to `base`.
+/// Lines without a tab, path or badge are skipped
+pub fn parse_badges(output: &str, base: &Path) -> HashMap<PathBuf, String> {
+  output
+    .lines()
+    .filter_map(|line| {
+      let (path, badge) = line.split_once('\t')?;
+      let badge = badge.trim();
+      if path.is_empty() || badge.is_empty() {
+        return None;
+      }
+      Some((base.join(path), badge.to_string()))
+    })
+    .collect()
+}
+
+/// Fill in the placeholders of a new file template for `path`:
+/// `{name}` and `{stem}` for its file name with and without extension, and
+/// `{date}` for `date`
+pub fn fill_template(template: &str, path: &Path, date: &str) -> String {
+  let name = path.file_name().unwrap_or_default().to_string_lossy();
+  let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+  template
+    .replace("{name}", &name)
+    .replace("{stem}", &stem)
+    .replace("{date}", date)
+}
+
+/// The MIME type of the file at `path`, as reported by `file`
+pub fn mime_type(path: &Path) -> Option<String> {
+  let output = std::process::Command::new("file")
+    .args(["--brief", "--mime-type", "--"])
+    .arg(path)
+    .output()
+    .ok()?;
+  let mime = String::from_utf8(output.stdout).ok()?;
+  let mime = mime.trim();
+  (output.status.success() && !mime.is_empty()).then(|| mime.to_string())
+}
+
+/// Standard base64 with padding
+pub fn base64(bytes: &[u8]) -> String {
+  const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+  let mut res = String::new();
+  for chunk in bytes.chunks(3) {
+    let n = chunk.iter().enumerate().fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+    for i in 0..4 {
+      if i <= chunk.len() {
+        res.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+      } else {
+        res.push('=');
+      }
+    }
+  }
+  res
+}
+
+/// The OSC 52 escape sequence asking the terminal to put `text` in the
+/// clipboard, which also works over ssh
+pub fn osc52(text: &[u8]) -> String {
+  format!("\x1b]52;c;{}\x07", base64(text))
+}
+
+/// Replace the `home` prefix of `path` with `~`
+pub fn abbreviate_home(path: &Path, home: &Path) -> PathBuf {
+  match path.strip_prefix(home) {
+    Ok(rest) if !home.as_os_str().is_empty() => Path::new("~").join(rest),
+    _ => path.to_path_buf(),
+  }
+}
+
+/// Replace a leading `~` in `path` with `home`, the reverse of
+/// `abbreviate_home`
+pub fn expand_home(path: &Path, home: &Path) -> PathBuf {
+  match path.strip_prefix("~") {
+    Ok(rest) if !home.as_os_str().is_empty() => home.join(rest),
+    _ => path.to_path_buf(),
+  }
+}
+
+/// Compare names so that numbers in them are ordered by value, like `file2`
+/// before `file10`
+pub fn natural_cmp(a: &str, b: &str, case_sensitive: bool) -> Ordering {
+  let (mut a, mut b) = (a.chars().peekable(), b.chars().peekable());
+  loop {
+    let order = match (a.peek().copied(), b.peek().copied()) {
+      (None, None) => return Ordering::Equal,
+      (None, Some(_)) => return Ordering::Less,
+      (Some(_), None) => return Ordering::Greater,
+      (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+        let x = digit_run(&mut a);
+        let y = digit_run(&mut b);
+        let (x, y) = (x.trim_start_matches('0'), y.trim_start_matches('0'));
+        x.len().cmp(&y.len()).then_with(|| x.cmp(y))
+      }
+      (Some(x), Some(y)) => {
+        a.next();
+        b.next();
+        match case_sensitive {
+          true => x.cmp(&y),
+          false => x.to_lowercase().cmp(y.to_lowercase()),
+        }
+      }
+    };
+    if order != Ordering::Equal {
+      return order;
+    }
+  }
+}
+
+fn digit_run(chars: &mut Peekable<Chars>) -> String {
+  std::iter::from_fn(|| chars.next_if(|c| c.is_ascii_digit())).collect()
+}
+
+/// Fill in the placeholders of `statusline_format`: `%p` for `path`, `%i` for
+/// the selected line `index` and `%n` for the `total` number of lines. `%%` is a
+/// literal `%`
+pub fn format_status(format: &str, path: &str, index: usize, total: usize) -> String {
+  let mut res = String::new();
+  let mut chars = format.chars();
+  while let Some(c) = chars.next() {
+    if c != '%' {
+      res.push(c);
+      continue;
+    }
+    match chars.next() {
+      Some('p') => res.push_str(path),
+      Some('i') => res.push_str(&index.to_string()),
+      Some('n') => res.push_str(&total.to_string()),
+      Some(c) => {
+        res.push('%');
+        if c != '%' {
+          res.push(c);
+        }
+      }
+      None => res.push('%'),
+    }
+  }
+  res
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::util::{
+    abbreviate_home, base64, edit_distance, expand_home, StatefulList, fill_template, format_status, fuzzy_score, glob_match, human_size, match_ranges, natural_cmp, parse_badges,
+    relative_time, osc52, rename_path, rename_target, sanitize_name, unique_path, write_atomic,
+  };
+  use std::ffi::OsStr;
+  use std::path::{Path, PathBuf};
+  use std::time::Duration;
+
+  #[test]
+  fn unique_paths() {
+    let dir = tempfile::tempdir().unwrap();
+    let dir = dir.path();
+    let unique = |name: &str| unique_path(dir, OsStr::new(name));
+    assert_eq!(unique("a.txt"), dir.join("a.txt"));
+    std::fs::write(dir.join("a.txt"), "").unwrap();
+    assert_eq!(unique("a.txt"), dir.join("a_1.txt"));
+    std::fs::write(dir.join("a_1.txt"), "").unwrap();
+    assert_eq!(unique("a.txt"), dir.join("a_2.txt"));
+    std::fs::create_dir(dir.join("d")).unwrap();
+    assert_eq!(unique("d"), dir.join("d_1"));
+  }
+
+  #[test]
+  fn rename_targets() {
+    let src = Path::new("/a/b/file");
+    let target = |name| rename_target(src, name, false);
+    assert_eq!(target("new "), Ok(PathBuf::from("/a/b/new")));
+    assert_eq!(target("sub/new"), Ok(PathBuf::from("/a/b/sub/new")));
+    assert_eq!(target("../up/"), Ok(PathBuf::from("/a/up")));
+    assert!(target("").is_err());
+    assert!(rename_target(src, "../up", true).is_err());
+  }
+
+  #[test]
+  fn rename_without_overwrite() {
+    let dir = tempfile::tempdir().unwrap();
+    let (src, dst) = (dir.path().join("src"), dir.path().join("dst"));
+    std::fs::write(&src, "new").unwrap();
+    std::fs::write(&dst, "old").unwrap();
+    let err = rename_path(&src, &dst, false).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::AlreadyExists);
+    assert_eq!(std::fs::read_to_string(&dst).unwrap(), "old");
+    assert!(src.exists());
+
+    rename_path(&src, &dst, true).unwrap();
+    assert_eq!(std::fs::read_to_string(&dst).unwrap(), "new");
+    assert!(!src.exists());
+
+    rename_path(&dst, &dir.path().join("sub/dst"), false).unwrap();
+    assert!(dir.path().join("sub/dst").exists());
+  }
+
+  #[test]
+  fn atomic_write() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("selection");
+    write_atomic(&file, b"/a").unwrap();
+    write_atomic(&file, b"/b").unwrap();
+    assert_eq!(std::fs::read_to_string(&file).unwrap(), "/b");
+    // Nothing is left behind
+    assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    assert!(write_atomic(&dir.path().join("missing/selection"), b"/a").is_err());
+    assert!(write_atomic(Path::new("/"), b"/a").is_err());
+  }
+
+  #[test]
+  fn fuzzy_scores() {
+    assert_eq!(fuzzy_score("xyz", "src/file_tree.rs"), None);
+    assert_eq!(fuzzy_score("", "anything"), Some(0));
+    assert!(fuzzy_score("ft", "src/file_tree.rs") > fuzzy_score("ft", "src/after.rs"));
+    assert!(fuzzy_score("tree", "file_tree.rs") > fuzzy_score("tree", "t_r_e_e"));
+    // Smart case
+    assert!(fuzzy_score("readme", "README.md").is_some());
+    assert_eq!(fuzzy_score("README", "readme.md"), None);
+  }
+
+  #[test]
+  fn match_ranges_smart_case() {
+    assert_eq!(match_ranges("Cargo.toml", "o"), [4..5, 7..8]);
+    assert_eq!(match_ranges("aaaaa", "aa"), [0..2, 2..4]);
+    assert_eq!(match_ranges("ÄpfelÄp", "äp"), [0..3, 6..9]);
+    assert!(match_ranges("readme", "README").is_empty());
+    assert!(match_ranges("readme", "").is_empty());
+  }
+
+  #[test]
+  fn edit_distances() {
+    assert_eq!(edit_distance("", "abc"), 3);
+    assert_eq!(edit_distance("show_hiden", "show_hidden"), 1);
+    assert_eq!(edit_distance("kitten", "sitting"), 3);
+    assert_eq!(edit_distance("same", "same"), 0);
+  }
+
+  #[test]
+  fn first_and_last() {
+    let mut list = StatefulList::with_items(vec![1, 2, 3]);
+    list.last();
+    assert_eq!(list.selected(), Some(&3));
+    list.first();
+    assert_eq!(list.selected(), Some(&1));
+    list.forward(5);
+    assert_eq!(list.selected(), Some(&3));
+    list.back(1);
+    assert_eq!(list.selected(), Some(&2));
+    list.back(5);
+    assert_eq!(list.selected(), Some(&1));
+    let mut empty = StatefulList::<i32>::new();
+    empty.first();
+    empty.last();
+    empty.nth(2);
+    empty.forward(2);
+    assert_eq!(empty.selected(), None);
+  }
+
+  #[test]
+  fn scroll_margin() {
+    let mut list = StatefulList::with_items((0..20).collect::<Vec<_>>());
+    let mut scroll = |selected, margin| {
+      list.select_index(selected);
+      list.scroll_to_selected(10, margin);
+      list.state.offset()
+    };
+    // Near the top and bottom there are fewer lines than the margin
+    assert_eq!(scroll(0, 3), 0);
+    assert_eq!(scroll(6, 3), 0);
+    assert_eq!(scroll(7, 3), 1);
+    assert_eq!(scroll(19, 3), 10);
+    // Going back up only scrolls once the margin is reached
+    assert_eq!(scroll(14, 3), 10);
+    assert_eq!(scroll(12, 3), 9);
+    // A margin larger than half the height keeps the selection centered
+    assert_eq!(scroll(12, 100), 8);
+    assert_eq!(scroll(5, 0), 5);
+  }
+
+  #[test]
+  fn human_sizes() {
+    assert_eq!(human_size(0), "0");
+    assert_eq!(human_size(1023), "1023");
+    assert_eq!(human_size(1024), "1.0K");
+    assert_eq!(human_size(1229), "1.2K");
+    assert_eq!(human_size(35 * 1024 * 1024), "35M");
+    assert_eq!(human_size(1023 * 1024 * 1024), "1023M");
+    assert_eq!(human_size(u64::MAX), "16E");
+  }
+
+  #[test]
+  fn relative_times() {
+    let ago = |secs| relative_time(Duration::from_secs(secs));
+    assert_eq!(ago(0), "0s");
+    assert_eq!(ago(59), "59s");
+    assert_eq!(ago(2 * 3600 + 59), "2h");
+    assert_eq!(ago(3 * 86400), "3d");
+    assert_eq!(ago(20 * 86400), "2w");
+    assert_eq!(ago(800 * 86400), "2y");
+  }
+
+  #[test]
+  fn home_abbreviation() {
+    let home = Path::new("/home/me");
+    assert_eq!(abbreviate_home(Path::new("/home/me/src"), home), Path::new("~/src"));
+    assert_eq!(abbreviate_home(home, home), Path::new("~"));
+    assert_eq!(abbreviate_home(Path::new("/home/meh"), home), Path::new("/home/meh"));
+    assert_eq!(abbreviate_home(Path::new("/tmp"), Path::new("")), Path::new("/tmp"));
+
+    assert_eq!(expand_home(Path::new("~/src"), home), Path::new("/home/me/src"));
+    assert_eq!(expand_home(Path::new("~other/src"), home), Path::new("~other/src"));
+    assert_eq!(expand_home(Path::new("/tmp/~"), home), Path::new("/tmp/~"));
+    assert_eq!(expand_home(Path::new("~/src"), Path::new("")), Path::new("~/src"));
+  }
+
+  #[test]
+  fn templates() {
+    let path = Path::new("/src/parser.rs");
+    assert_eq!(
+      fill_template("//! {stem}, created {date}\n", path, "2024-01-02"),
+      "//! parser, created 2024-01-02\n"
+    );
+    let path = Path::new("run.sh");
+    assert_eq!(
+      fill_template("#!/bin/sh\n# {name} {unknown}\n", path, ""),
+      "#!/bin/sh\n# run.sh {unknown}\n"
+    );
+  }
+
+  #[test]
+  fn badges() {
+    let base = Path::new("/root");
+    let badges = parse_badges("a.rs\tok\nno tab\n\tempty path\nb.rs\t\n/abs\t 3 \n", base);
+    assert_eq!(badges.len(), 2);
+    assert_eq!(badges[&base.join("a.rs")], "ok");
+    assert_eq!(badges[Path::new("/abs")], "3");
+  }
+
+  #[test]
+  fn glob() {
+    assert!(glob_match("*.rs", "main.rs"));
+    assert!(!glob_match("*.rs", "main.rsx"));
+    assert!(glob_match("Makefile", "Makefile"));
+    assert!(glob_match("?ake*", "Makefile"));
+    assert!(!glob_match("?", ""));
+    assert!(glob_match("*", ""));
+  }
+
+  #[test]
+  fn sanitize_trims_trailing_whitespace() {
+    assert_eq!(sanitize_name("file ", false, false), Ok("file".to_string()));
+    assert_eq!(sanitize_name("dir/\t", true, true), Ok("dir/".to_string()));
+  }
+
+  #[test]
+  fn sanitize_rejects_empty() {
+    assert!(sanitize_name("", false, false).is_err());
+    assert!(sanitize_name("   ", true, false).is_err());
+  }
+
+  #[test]
+  fn sanitize_rejects_separators() {
+    assert!(sanitize_name("a/b", false, false).is_err());
+    assert_eq!(sanitize_name("a/b", true, false), Ok("a/b".to_string()));
+  }
+
+  #[test]
+  fn sanitize_strict() {
+    assert!(sanitize_name(" file", false, true).is_err());
+    assert_eq!(sanitize_name(" file", false, false), Ok(" file".to_string()));
+    assert!(sanitize_name("fi\u{7}le", false, true).is_err());
+    assert!(sanitize_name("..", false, true).is_err());
+    assert!(sanitize_name("a/../b", true, true).is_err());
+    assert!(sanitize_name("a//b", true, true).is_err());
+    assert!(sanitize_name("a /b", true, true).is_err());
+    assert_eq!(sanitize_name("a/b/", true, true), Ok("a/b/".to_string()));
+  }
+
+  #[test]
+  fn base64_encoding() {
+    assert_eq!(base64(b""), "");
+    assert_eq!(base64(b"f"), "Zg==");
+    assert_eq!(base64(b"fo"), "Zm8=");
+    assert_eq!(base64(b"foo"), "Zm9v");
+    assert_eq!(base64(b"foob"), "Zm9vYg==");
+    assert_eq!(base64("/tmp/ä b".as_bytes()), "L3RtcC/DpCBi");
+    assert_eq!(osc52(b"foo"), "\x1b]52;c;Zm9v\x07");
+  }
+
+  #[test]
+  fn status_format() {
+    assert_eq!(format_status("%p [%i/%n]", "src/main.rs", 3, 12), "src/main.rs [3/12]");
+    assert_eq!(format_status("%n%", "%i", 1, 2), "2%");
+    assert_eq!(format_status("%p 100%% %x", "%i", 1, 2), "%i 100% %x");
+  }
+
+  #[test]
+  fn natural_order() {
+    use std::cmp::Ordering::*;
+    assert_eq!(natural_cmp("file2", "file10", true), Less);
+    assert_eq!(natural_cmp("file10", "file9", true), Greater);
+    assert_eq!(natural_cmp("v1.10.2", "v1.9.12", true), Greater);
+    assert_eq!(natural_cmp("a007", "a7", true), Equal);
+    assert_eq!(natural_cmp("a7b", "a07c", true), Less);
+    assert_eq!(natural_cmp("file", "file1", true), Less);
+    assert_eq!(natural_cmp("123456789012345678901234567890", "99", true), Greater);
+    assert_eq!(natural_cmp("Zebra", "apple", true), Less);
+    assert_eq!(natural_cmp("Zebra", "apple", false), Greater);
+    assert_eq!(natural_cmp("Ä2", "ä10", false), Less);
+    assert_eq!(natural_cmp("README", "readme", false), Equal);
+  }
+}
@@ -0,0 +1,190 @@
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher as _};
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver};
+use std::time::{Duration, Instant};
+
+/// Bursts of changes, like from a `git checkout`, cause at most one update
+/// this often
+pub const DEBOUNCE: Duration = Duration::from_millis(100);
+
+/// Watches the expanded directories for changes, so the tree only needs to be
+/// read again when something happened
+pub struct Watcher {
+  /// None when watching isn't possible, for example when inotify ran out of
+  /// watches
+  watcher: Option<RecommendedWatcher>,
+  events: Receiver<notify::Result<Event>>,
+  /// The directories last asked to be watched
+  wanted: HashSet<PathBuf>,
+  /// Those of them that are watched, without the ones that failed
+  watched: HashSet<PathBuf>,
+  /// Whether changes came in since the last update
+  dirty: bool,
+  /// The paths those changes were to
+  paths: HashSet<PathBuf>,
+  last_update: Option<Instant>,
+}
+
+impl Watcher {
+  pub fn new() -> Watcher {
+    let (tx, events) = mpsc::channel();
+    Watcher {
+      watcher: notify::recommended_watcher(tx).ok(),
+      events,
+      wanted: HashSet::new(),
+      watched: HashSet::new(),
+      dirty: false,
+      paths: HashSet::new(),
+      last_update: None,
+    }
+  }
+
+  /// Whether changes are noticed. Otherwise the tree has to be read regularly
+  pub fn is_active(&self) -> bool {
+    self.watcher.is_some() && !self.watched.is_empty()
+  }
+
+  /// Watch exactly `dirs`, each without its subdirectories. A directory that
+  /// can't be watched, like one removed in the meantime, is left out until it's
+  /// asked for again. Running out of watches stops watching altogether
+  pub fn watch(&mut self, dirs: &HashSet<PathBuf>) {
+    let Some(watcher) = &mut self.watcher else {
+      return;
+    };
+    if *dirs == self.wanted {
+      return;
+    }
+    for dir in self.watched.difference(dirs) {
+      let _ = watcher.unwatch(dir);
+    }
+    self.watched.retain(|dir| dirs.contains(dir));
+    let mut exhausted = false;
+    for dir in dirs.difference(&self.wanted) {
+      match watcher.watch(dir, RecursiveMode::NonRecursive) {
+        Ok(()) => {
+          self.watched.insert(dir.clone());
+        }
+        Err(e) => exhausted |= out_of_watches(&e),
+      }
+    }
+    self.wanted = dirs.clone();
+    if exhausted {
+      // Changes would go unnoticed in more and more places, so fall back to
+      // reading regularly
+      self.stop();
+      self.watcher = None;
+    }
+  }
+
+  /// Stop watching, leaving it to regular updates
+  pub fn stop(&mut self) {
+    if let Some(watcher) = &mut self.watcher {
+      for dir in self.watched.drain() {
+        let _ = watcher.unwatch(&dir);
+      }
+    }
+    self.wanted.clear();
+    self.dirty = false;
+    self.paths.clear();
+  }
+
+  /// The paths changed since the last update, if the tree should be updated
+  /// now because something changed and the last update was long enough ago.
+  /// Errors and events without paths update the tree without naming a path
+  pub fn changed(&mut self) -> Option<Vec<PathBuf>> {
+    while let Ok(event) = self.events.try_recv() {
+      match event {
+        // Reading a file changes nothing that is shown
+        Ok(Event {
+          kind: EventKind::Access(_),
+          ..
+        }) => {}
+        Ok(event) => {
+          self.dirty = true;
+          self.paths.extend(event.paths);
+        }
+        Err(_) => self.dirty = true,
+      }
+    }
+    if !self.dirty || self.last_update.is_some_and(|t| t.elapsed() < DEBOUNCE) {
+      return None;
+    }
+    self.dirty = false;
+    self.last_update = Some(Instant::now());
+    Some(self.paths.drain().collect())
+  }
+}
+
+/// Whether watching failed because the system ran out of watches or file
+/// descriptors, rather than because of the directory
+fn out_of_watches(e: &notify::Error) -> bool {
+  match &e.kind {
+    notify::ErrorKind::MaxFilesWatch => true,
+    notify::ErrorKind::Io(e) => matches!(e.raw_os_error(), Some(libc::ENOSPC | libc::EMFILE)),
+    _ => false,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::watcher::{Watcher, DEBOUNCE};
+  use std::collections::HashSet;
+  use std::path::{Path, PathBuf};
+  use std::time::{Duration, Instant};
+
+  /// Wait for the watcher to report a change, returning the changed paths
+  fn wait_changed(watcher: &mut Watcher) -> Option<Vec<PathBuf>> {
+    let start = Instant::now();
+    while start.elapsed() < Duration::from_secs(5) {
+      if let Some(paths) = watcher.changed() {
+        return Some(paths);
+      }
+      std::thread::sleep(Duration::from_millis(10));
+    }
+    None
+  }
+
+  fn dirs(paths: &[&Path]) -> HashSet<PathBuf> {
+    paths.iter().map(|p| p.to_path_buf()).collect()
+  }
+
+  #[test]
+  fn notices_changes() {
+    let dir = tempfile::tempdir().unwrap();
+    let sub = dir.path().join("sub");
+    std::fs::create_dir(&sub).unwrap();
+    let mut watcher = Watcher::new();
+    if watcher.watcher.is_none() {
+      // Not supported here
+      return;
+    }
+    // A directory that can't be watched doesn't stop the others
+    let missing = dir.path().join("missing");
+    watcher.watch(&dirs(&[dir.path(), &missing]));
+    assert!(watcher.is_active());
+    assert!(watcher.watched.contains(dir.path()) && !watcher.watched.contains(&missing));
+    assert!(watcher.changed().is_none());
+
+    std::fs::write(dir.path().join("a"), "").unwrap();
+    let paths = wait_changed(&mut watcher).unwrap();
+    assert!(paths.iter().any(|p| p.ends_with("a")));
+    // A burst right after waits for the debounce interval
+    let updated = Instant::now();
+    std::fs::write(dir.path().join("b"), "").unwrap();
+    assert!(wait_changed(&mut watcher).is_some());
+    assert!(updated.elapsed() >= DEBOUNCE - Duration::from_millis(20));
+
+    // Subdirectories are only watched once they're expanded
+    std::thread::sleep(DEBOUNCE);
+    std::fs::write(sub.join("c"), "").unwrap();
+    std::thread::sleep(DEBOUNCE);
+    assert!(watcher.changed().is_none());
+    watcher.watch(&dirs(&[dir.path(), &sub]));
+    std::fs::write(sub.join("d"), "").unwrap();
+    assert!(wait_changed(&mut watcher).is_some());
+
+    watcher.stop();
+    assert!(!watcher.is_active());
+  }
+}
@@ -0,0 +1,27 @@
+//! Helpers for running sidetree on a pseudo terminal
+
+use std::fs::File;
+use std::os::unix::io::FromRawFd;
+
+/// A new pseudo terminal, as (master, slave)
+pub fn open_pty() -> (File, File) {
+  let (mut master, mut slave) = (0, 0);
+  let size = libc::winsize {
+    ws_row: 10,
+    ws_col: 40,
+    ws_xpixel: 0,
+    ws_ypixel: 0,
+  };
+  let res = unsafe {
+    libc::openpty(&mut master, &mut slave, std::ptr::null_mut(), std::ptr::null(), &size)
+  };
+  assert_eq!(res, 0, "openpty failed");
+  unsafe { (File::from_raw_fd(master), File::from_raw_fd(slave)) }
+}
+
+pub fn is_raw(tty: &File) -> bool {
+  use std::os::unix::io::AsRawFd;
+  let mut termios = unsafe { std::mem::zeroed::<libc::termios>() };
+  assert_eq!(unsafe { libc::tcgetattr(tty.as_raw_fd(), &mut termios) }, 0);
+  termios.c_lflag & libc::ICANON == 0
+}
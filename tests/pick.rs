@@ -0,0 +1,65 @@
+//! Runs sidetree with `--pick` and its stdout captured, as in
+//! `cd "$(sidetree --pick)"`
+
+mod common;
+
+use common::{is_raw, open_pty};
+use std::io::{Read, Write};
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
+use std::time::{Duration, Instant};
+
+const ENTER_ALT_SCREEN: &str = "\x1b[?1049h";
+
+/// Run `sidetree --pick --select select` in `dir`, type `keys` once it's up
+/// and return what it printed
+fn pick(dir: &Path, select: &Path, keys: &[u8]) -> Output {
+  let config = dir.join("sidetreerc");
+  std::fs::write(&config, "").unwrap();
+  let (mut master, slave) = open_pty();
+  let child = Command::new(env!("CARGO_BIN_EXE_sidetree"))
+    .args(["--no-cache", "--pick", "--config"])
+    .arg(&config)
+    .arg("--select")
+    .arg(select)
+    .arg(dir)
+    .env("HOME", dir)
+    .env("XDG_CONFIG_HOME", dir)
+    .env("XDG_CACHE_HOME", dir)
+    .stdin(slave.try_clone().unwrap())
+    .stdout(Stdio::piped())
+    .stderr(slave.try_clone().unwrap())
+    .spawn()
+    .unwrap();
+
+  // The UI goes to the terminal, since stdout isn't one
+  let start = Instant::now();
+  let mut output = Vec::new();
+  let mut buf = [0; 4096];
+  while !String::from_utf8_lossy(&output).contains(ENTER_ALT_SCREEN) {
+    assert!(start.elapsed() < Duration::from_secs(10), "sidetree did not start");
+    let n = master.read(&mut buf).unwrap();
+    output.extend(&buf[..n]);
+  }
+  master.write_all(keys).unwrap();
+  let output = child.wait_with_output().unwrap();
+  assert!(!is_raw(&slave));
+  output
+}
+
+#[test]
+fn pick_prints_the_selected_path() {
+  let dir = tempfile::tempdir().unwrap();
+  let file = dir.path().join("picked file");
+  std::fs::write(&file, "").unwrap();
+
+  // Opening a file picks it
+  let output = pick(dir.path(), &file, b"\r");
+  assert!(output.status.success());
+  assert_eq!(String::from_utf8_lossy(&output.stdout), format!("{}\n", file.display()));
+
+  // Quitting picks nothing
+  let output = pick(dir.path(), &file, b"q");
+  assert_eq!(output.status.code(), Some(1));
+  assert!(output.stdout.is_empty());
+}
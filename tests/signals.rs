@@ -0,0 +1,66 @@
+//! Runs sidetree on a pseudo terminal and kills it, to check that the terminal
+//! is restored on the way out
+
+mod common;
+
+use common::{is_raw, open_pty};
+use std::io::Read;
+use std::process::{Command, Stdio};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+const ENTER_ALT_SCREEN: &str = "\x1b[?1049h";
+const LEAVE_ALT_SCREEN: &str = "\x1b[?1049l";
+
+#[test]
+fn terminal_restored_on_sigterm() {
+  let dir = tempfile::tempdir().unwrap();
+  let config = dir.path().join("sidetreerc");
+  std::fs::write(&config, "").unwrap();
+  let (mut master, slave) = open_pty();
+
+  let mut child = Command::new(env!("CARGO_BIN_EXE_sidetree"))
+    .arg("--no-cache")
+    .arg("--config")
+    .arg(&config)
+    .arg(dir.path())
+    .env("HOME", dir.path())
+    .env("XDG_CONFIG_HOME", dir.path())
+    .stdin(slave.try_clone().unwrap())
+    .stdout(slave.try_clone().unwrap())
+    .stderr(Stdio::null())
+    .spawn()
+    .unwrap();
+
+  // Collect everything written to the terminal
+  let (tx, rx) = mpsc::channel();
+  std::thread::spawn(move || {
+    let mut buf = [0; 4096];
+    while let Ok(n) = master.read(&mut buf) {
+      if n == 0 || tx.send(buf[..n].to_vec()).is_err() {
+        break;
+      }
+    }
+  });
+  let mut output = Vec::new();
+  let read_until = |output: &mut Vec<u8>, text: &str| {
+    let start = Instant::now();
+    while !String::from_utf8_lossy(output).contains(text) {
+      let left = Duration::from_secs(10).saturating_sub(start.elapsed());
+      match rx.recv_timeout(left) {
+        Ok(bytes) => output.extend(bytes),
+        Err(_) => return false,
+      }
+    }
+    true
+  };
+
+  assert!(read_until(&mut output, ENTER_ALT_SCREEN), "sidetree did not start");
+  assert!(is_raw(&slave));
+  unsafe { libc::kill(child.id() as i32, libc::SIGTERM) };
+  let status = child.wait().unwrap();
+
+  assert_eq!(status.code(), Some(128 + libc::SIGTERM));
+  assert!(read_until(&mut output, LEAVE_ALT_SCREEN), "alternate screen was not left");
+  assert!(!is_raw(&slave));
+}